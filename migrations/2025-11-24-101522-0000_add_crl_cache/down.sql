@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS "crl_entries";
+DROP TABLE IF EXISTS "crls";
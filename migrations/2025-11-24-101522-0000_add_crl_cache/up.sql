@@ -0,0 +1,16 @@
+-- Your SQL goes here
+CREATE TABLE "crls"(
+	"distribution_point" TEXT NOT NULL PRIMARY KEY,
+	"issuer" TEXT NOT NULL,
+	"this_update" TIMESTAMPTZ NOT NULL,
+	"next_update" TIMESTAMPTZ,
+	"fetched_at" TIMESTAMPTZ NOT NULL
+);
+
+CREATE TABLE "crl_entries"(
+	"distribution_point" TEXT NOT NULL,
+	"serial_number" TEXT NOT NULL,
+	"revocation_time" TIMESTAMPTZ NOT NULL,
+	PRIMARY KEY("distribution_point", "serial_number"),
+	FOREIGN KEY ("distribution_point") REFERENCES "crls"("distribution_point")
+);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "auth_clients"
+	DROP COLUMN IF EXISTS "subject_type",
+	DROP COLUMN IF EXISTS "sector_identifier";
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE "auth_clients"
+	ADD COLUMN "subject_type" TEXT NOT NULL DEFAULT 'public' CHECK ("subject_type" IN ('public', 'pairwise')),
+	ADD COLUMN "sector_identifier" TEXT;
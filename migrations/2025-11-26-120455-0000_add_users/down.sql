@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "oauth_grants" DROP CONSTRAINT IF EXISTS "oauth_grants_owner_id_fkey";
+DROP TABLE IF EXISTS "users";
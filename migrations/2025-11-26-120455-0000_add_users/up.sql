@@ -0,0 +1,18 @@
+-- Your SQL goes here
+CREATE TABLE "users"(
+	"id" UUID NOT NULL PRIMARY KEY,
+	"given_name" TEXT NOT NULL,
+	"surname" TEXT NOT NULL,
+	"country" TEXT NOT NULL,
+	"certificate_fingerprint" TEXT,
+	"first_login_at" TIMESTAMPTZ NOT NULL,
+	"last_login_at" TIMESTAMPTZ NOT NULL,
+	"disabled" BOOL NOT NULL DEFAULT FALSE
+);
+
+-- Outstanding codes were issued to random owners and cannot be linked to a user.
+DELETE FROM "oauth_grant_extensions";
+DELETE FROM "oauth_grants";
+
+ALTER TABLE "oauth_grants"
+	ADD CONSTRAINT "oauth_grants_owner_id_fkey" FOREIGN KEY ("owner_id") REFERENCES "users"("id");
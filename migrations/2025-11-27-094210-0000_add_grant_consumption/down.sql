@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS "oauth_grant_replays";
+ALTER TABLE "oauth_grants" DROP COLUMN "consumed_at";
@@ -0,0 +1,11 @@
+-- Your SQL goes here
+ALTER TABLE "oauth_grants" ADD COLUMN "consumed_at" TIMESTAMPTZ;
+
+CREATE TABLE "oauth_grant_replays"(
+	"id" UUID NOT NULL PRIMARY KEY,
+	"code_hash" TEXT NOT NULL,
+	"attempted_at" TIMESTAMPTZ NOT NULL,
+	FOREIGN KEY ("code_hash") REFERENCES "oauth_grants"("code_hash") ON DELETE CASCADE
+);
+
+CREATE INDEX "oauth_grant_replays_code_hash_idx" ON "oauth_grant_replays"("code_hash");
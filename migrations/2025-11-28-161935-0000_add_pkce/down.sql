@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "oauth_grants"
+	DROP CONSTRAINT IF EXISTS "oauth_grants_code_challenge_check",
+	DROP COLUMN IF EXISTS "code_challenge",
+	DROP COLUMN IF EXISTS "code_challenge_method";
+
+ALTER TABLE "auth_clients"
+	DROP COLUMN IF EXISTS "require_pkce";
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+ALTER TABLE "auth_clients"
+	ADD COLUMN "require_pkce" BOOL NOT NULL DEFAULT FALSE;
+
+ALTER TABLE "oauth_grants"
+	ADD COLUMN "code_challenge" TEXT,
+	ADD COLUMN "code_challenge_method" TEXT CHECK ("code_challenge_method" IN ('S256', 'plain')),
+	ADD CONSTRAINT "oauth_grants_code_challenge_check" CHECK (("code_challenge" IS NULL) = ("code_challenge_method" IS NULL));
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+CREATE TABLE "audit_events"(
+	"id" UUID NOT NULL PRIMARY KEY,
+	"event_type" TEXT NOT NULL CHECK ("event_type" IN ('code_issued', 'code_redeemed', 'token_issued', 'client_auth_failed', 'certificate_rejected', 'replay_detected')),
+	"client_id" UUID,
+	"subject" UUID,
+	"certificate_serial" TEXT,
+	"certificate_issuer" TEXT,
+	"source_ip" TEXT,
+	"occurred_at" TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX "audit_events_occurred_at_idx" ON "audit_events"("occurred_at");
+CREATE INDEX "audit_events_subject_idx" ON "audit_events"("subject");
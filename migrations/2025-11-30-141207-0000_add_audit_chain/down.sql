@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS "audit_checkpoints";
+
+ALTER TABLE "audit_events"
+	DROP COLUMN IF EXISTS "sequence",
+	DROP COLUMN IF EXISTS "prev_hash",
+	DROP COLUMN IF EXISTS "hash";
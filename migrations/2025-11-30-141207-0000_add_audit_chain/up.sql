@@ -0,0 +1,13 @@
+-- Your SQL goes here
+ALTER TABLE "audit_events"
+	ADD COLUMN "sequence" BIGINT UNIQUE,
+	ADD COLUMN "prev_hash" TEXT,
+	ADD COLUMN "hash" TEXT;
+
+CREATE TABLE "audit_checkpoints"(
+	"sequence" BIGINT NOT NULL PRIMARY KEY,
+	"hash" TEXT NOT NULL,
+	"key_id" TEXT,
+	"signature" TEXT NOT NULL,
+	"signed_at" TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "audit_events" DROP CONSTRAINT "audit_events_event_type_check";
+ALTER TABLE "audit_events" ADD CONSTRAINT "audit_events_event_type_check"
+	CHECK ("event_type" IN ('code_issued', 'code_redeemed', 'token_issued', 'client_auth_failed', 'certificate_rejected', 'replay_detected'));
+
+DROP TABLE IF EXISTS "oauth_access_tokens";
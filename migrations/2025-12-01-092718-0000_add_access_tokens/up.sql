@@ -0,0 +1,19 @@
+-- Your SQL goes here
+CREATE TABLE "oauth_access_tokens"(
+	"token_hash" TEXT NOT NULL PRIMARY KEY,
+	"jti" UUID NOT NULL UNIQUE,
+	"client_id" UUID NOT NULL REFERENCES "auth_clients"("id"),
+	"subject" UUID NOT NULL REFERENCES "users"("id"),
+	"scope" TEXT NOT NULL,
+	"redirect_uri" TEXT NOT NULL,
+	"issued_at" TIMESTAMPTZ NOT NULL,
+	"expires_at" TIMESTAMPTZ NOT NULL,
+	"revoked_at" TIMESTAMPTZ
+);
+
+CREATE INDEX "oauth_access_tokens_subject_idx" ON "oauth_access_tokens"("subject");
+CREATE INDEX "oauth_access_tokens_expires_at_idx" ON "oauth_access_tokens"("expires_at");
+
+ALTER TABLE "audit_events" DROP CONSTRAINT "audit_events_event_type_check";
+ALTER TABLE "audit_events" ADD CONSTRAINT "audit_events_event_type_check"
+	CHECK ("event_type" IN ('code_issued', 'code_redeemed', 'token_issued', 'token_revoked', 'client_auth_failed', 'certificate_rejected', 'replay_detected'));
@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "oauth_access_tokens" DROP COLUMN "certificate_thumbprint";
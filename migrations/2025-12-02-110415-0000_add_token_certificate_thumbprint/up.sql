@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE "oauth_access_tokens" ADD COLUMN "certificate_thumbprint" TEXT;
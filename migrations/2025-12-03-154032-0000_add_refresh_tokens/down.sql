@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "audit_events" DROP CONSTRAINT "audit_events_event_type_check";
+ALTER TABLE "audit_events" ADD CONSTRAINT "audit_events_event_type_check"
+	CHECK ("event_type" IN ('code_issued', 'code_redeemed', 'token_issued', 'token_revoked', 'client_auth_failed', 'certificate_rejected', 'replay_detected'));
+
+ALTER TABLE "oauth_access_tokens" DROP COLUMN "refresh_family_id";
+DROP TABLE IF EXISTS "oauth_refresh_tokens";
+ALTER TABLE "auth_clients" DROP COLUMN "allow_refresh_tokens";
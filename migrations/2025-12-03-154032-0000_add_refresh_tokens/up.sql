@@ -0,0 +1,28 @@
+-- Your SQL goes here
+ALTER TABLE "auth_clients" ADD COLUMN "allow_refresh_tokens" BOOL NOT NULL DEFAULT FALSE;
+
+CREATE TABLE "oauth_refresh_tokens"(
+	"token_hash" TEXT NOT NULL PRIMARY KEY,
+	"family_id" UUID NOT NULL,
+	"client_id" UUID NOT NULL REFERENCES "auth_clients"("id"),
+	"subject" UUID NOT NULL REFERENCES "users"("id"),
+	"scope" TEXT NOT NULL,
+	"redirect_uri" TEXT NOT NULL,
+	"certificate_thumbprint" TEXT,
+	"issued_at" TIMESTAMPTZ NOT NULL,
+	"expires_at" TIMESTAMPTZ NOT NULL,
+	"session_expires_at" TIMESTAMPTZ NOT NULL,
+	"consumed_at" TIMESTAMPTZ,
+	"revoked_at" TIMESTAMPTZ
+);
+
+CREATE INDEX "oauth_refresh_tokens_family_id_idx" ON "oauth_refresh_tokens"("family_id");
+CREATE INDEX "oauth_refresh_tokens_subject_idx" ON "oauth_refresh_tokens"("subject");
+CREATE INDEX "oauth_refresh_tokens_session_expires_at_idx" ON "oauth_refresh_tokens"("session_expires_at");
+
+ALTER TABLE "oauth_access_tokens" ADD COLUMN "refresh_family_id" UUID;
+CREATE INDEX "oauth_access_tokens_refresh_family_id_idx" ON "oauth_access_tokens"("refresh_family_id");
+
+ALTER TABLE "audit_events" DROP CONSTRAINT "audit_events_event_type_check";
+ALTER TABLE "audit_events" ADD CONSTRAINT "audit_events_event_type_check"
+	CHECK ("event_type" IN ('code_issued', 'code_redeemed', 'token_issued', 'token_refreshed', 'token_revoked', 'client_auth_failed', 'certificate_rejected', 'replay_detected', 'refresh_token_reused'));
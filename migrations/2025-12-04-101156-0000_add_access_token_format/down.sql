@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "auth_clients" DROP COLUMN "access_token_format";
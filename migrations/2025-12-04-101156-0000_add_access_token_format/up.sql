@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE "auth_clients" ADD COLUMN "access_token_format" TEXT NOT NULL DEFAULT 'opaque'
+	CHECK ("access_token_format" IN ('opaque', 'jwt'));
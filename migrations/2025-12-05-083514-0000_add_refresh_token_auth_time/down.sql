@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "oauth_refresh_tokens" DROP COLUMN "auth_time";
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE "oauth_refresh_tokens" ADD COLUMN "auth_time" TIMESTAMPTZ;
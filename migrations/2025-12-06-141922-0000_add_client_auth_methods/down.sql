@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "auth_clients"
+	DROP COLUMN "token_endpoint_auth_method",
+	DROP COLUMN "tls_client_auth_subject_dn",
+	DROP COLUMN "tls_client_certificate_thumbprint",
+	DROP COLUMN "jwks";
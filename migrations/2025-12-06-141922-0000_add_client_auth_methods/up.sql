@@ -0,0 +1,9 @@
+-- Your SQL goes here
+ALTER TABLE "auth_clients"
+	ADD COLUMN "token_endpoint_auth_method" TEXT NOT NULL DEFAULT 'client_secret_basic'
+		CHECK ("token_endpoint_auth_method" IN ('none', 'client_secret_basic', 'client_secret_post', 'tls_client_auth', 'self_signed_tls_client_auth')),
+	ADD COLUMN "tls_client_auth_subject_dn" TEXT,
+	ADD COLUMN "tls_client_certificate_thumbprint" TEXT,
+	ADD COLUMN "jwks" TEXT;
+
+UPDATE "auth_clients" SET "token_endpoint_auth_method" = 'none' WHERE NOT "confidential";
@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE "client_assertion_jtis";
+
+ALTER TABLE "auth_clients"
+	DROP COLUMN "jwks_uri",
+	DROP COLUMN "encrypted_client_secret",
+	DROP CONSTRAINT "auth_clients_token_endpoint_auth_method_check",
+	ADD CONSTRAINT "auth_clients_token_endpoint_auth_method_check"
+		CHECK ("token_endpoint_auth_method" IN ('none', 'client_secret_basic', 'client_secret_post', 'tls_client_auth', 'self_signed_tls_client_auth'));
@@ -0,0 +1,16 @@
+-- Your SQL goes here
+ALTER TABLE "auth_clients"
+	DROP CONSTRAINT "auth_clients_token_endpoint_auth_method_check",
+	ADD CONSTRAINT "auth_clients_token_endpoint_auth_method_check"
+		CHECK ("token_endpoint_auth_method" IN ('none', 'client_secret_basic', 'client_secret_post', 'client_secret_jwt', 'private_key_jwt', 'tls_client_auth', 'self_signed_tls_client_auth')),
+	ADD COLUMN "jwks_uri" TEXT,
+	ADD COLUMN "encrypted_client_secret" TEXT;
+
+CREATE TABLE "client_assertion_jtis"(
+	"client_id" UUID NOT NULL REFERENCES "auth_clients"("id"),
+	"jti" TEXT NOT NULL,
+	"expires_at" TIMESTAMPTZ NOT NULL,
+	PRIMARY KEY ("client_id", "jti")
+);
+
+CREATE INDEX "client_assertion_jtis_expires_at_idx" ON "client_assertion_jtis"("expires_at");
@@ -0,0 +1,11 @@
+-- Your SQL goes here
+CREATE TABLE "signing_keys"(
+	"kid" TEXT NOT NULL PRIMARY KEY,
+	"state" TEXT NOT NULL CHECK ("state" IN ('next', 'active', 'retired')),
+	"encrypted_private_key" TEXT NOT NULL,
+	"created_at" TIMESTAMPTZ NOT NULL DEFAULT now(),
+	"activated_at" TIMESTAMPTZ,
+	"retired_at" TIMESTAMPTZ
+);
+
+CREATE UNIQUE INDEX "signing_keys_state_idx" ON "signing_keys"("state") WHERE "state" <> 'retired';
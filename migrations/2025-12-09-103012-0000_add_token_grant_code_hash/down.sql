@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "oauth_refresh_tokens" DROP COLUMN "grant_code_hash";
+ALTER TABLE "oauth_access_tokens" DROP COLUMN "grant_code_hash";
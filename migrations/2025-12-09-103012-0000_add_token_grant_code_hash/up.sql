@@ -0,0 +1,6 @@
+-- Your SQL goes here
+ALTER TABLE "oauth_access_tokens" ADD COLUMN "grant_code_hash" TEXT;
+ALTER TABLE "oauth_refresh_tokens" ADD COLUMN "grant_code_hash" TEXT;
+
+CREATE INDEX "oauth_access_tokens_grant_code_hash_idx" ON "oauth_access_tokens"("grant_code_hash");
+CREATE INDEX "oauth_refresh_tokens_grant_code_hash_idx" ON "oauth_refresh_tokens"("grant_code_hash");
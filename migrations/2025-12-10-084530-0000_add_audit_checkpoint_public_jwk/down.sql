@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "audit_checkpoints" DROP COLUMN IF EXISTS "public_jwk";
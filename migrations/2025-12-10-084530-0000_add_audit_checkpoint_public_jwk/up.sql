@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE "audit_checkpoints" ADD COLUMN "public_jwk" TEXT;
@@ -0,0 +1,41 @@
+use aes_gcm::KeyInit;
+use aes_gcm::aead::{Aead, Nonce};
+use aes_gcm::{Aes256Gcm, Key};
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use rand::TryRngCore;
+use rand::rand_core::OsRng;
+
+const NONCE_LEN: usize = 12;
+
+pub(crate) fn encrypt_value(key_bytes: &[u8], plaintext: &[u8]) -> Option<Vec<u8>> {
+    let key = Key::<Aes256Gcm>::from_slice(key_bytes);
+    let cipher = Aes256Gcm::new(key);
+
+    let mut nonce_bytes = [0u8; NONCE_LEN];
+    OsRng.try_fill_bytes(&mut nonce_bytes).ok()?;
+
+    let nonce = Nonce::<Aes256Gcm>::from_slice(&nonce_bytes);
+    let ciphertext = cipher.encrypt(nonce, plaintext).ok()?;
+
+    let mut combined = Vec::with_capacity(nonce_bytes.len() + ciphertext.len());
+    combined.extend_from_slice(&nonce_bytes);
+    combined.extend_from_slice(&ciphertext);
+
+    Some(combined)
+}
+
+pub(crate) fn decrypt_value(key_bytes: &[u8], value: &str) -> Option<Vec<u8>> {
+    let key = Key::<Aes256Gcm>::from_slice(key_bytes);
+    let cipher = Aes256Gcm::new(key);
+
+    let combined = BASE64_STANDARD.decode(value).ok()?;
+    if combined.len() < NONCE_LEN {
+        return None;
+    }
+
+    let (nonce_bytes, ciphertext) = combined.split_at(NONCE_LEN);
+    let nonce = Nonce::<Aes256Gcm>::from_slice(nonce_bytes);
+
+    cipher.decrypt(nonce, ciphertext).ok()
+}
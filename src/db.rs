@@ -3,4 +3,19 @@ use diesel_async::pooled_connection::bb8;
 
 pub mod models;
 pub(crate) mod schema;
+pub mod users;
+pub mod janitor;
+pub mod audit;
+pub mod tokens;
+#[cfg(test)]
+pub(crate) mod fixtures;
 pub type Pool = bb8::Pool<AsyncPgConnection>;
+
+#[cfg(test)]
+pub(crate) async fn test_pool() -> Option<Pool> {
+    use diesel_async::pooled_connection::AsyncDieselConnectionManager;
+
+    let database_url = std::env::var("DATABASE_URL").ok()?;
+    let manager = AsyncDieselConnectionManager::<AsyncPgConnection>::new(database_url);
+    Some(Pool::builder().build(manager).await.unwrap())
+}
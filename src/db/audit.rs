@@ -0,0 +1,693 @@
+use crate::db;
+use crate::db::models::{AuditCheckpoint, AuditEvent};
+use crate::db::schema::audit_checkpoints::dsl::audit_checkpoints;
+use crate::db::schema::audit_events::dsl::audit_events;
+use crate::db::schema::{
+    audit_checkpoints as audit_checkpoints_columns, audit_events as audit_events_columns,
+};
+use crate::error::Error;
+use crate::oauth::client_cert_data::ClientCertData;
+use crate::oauth::key_manager::KeyManager;
+use crate::pki::revocation::serial_number_hex;
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use chrono::{DateTime, SubsecRound, Utc};
+use diesel::dsl::{insert_into, sql_query};
+use diesel::sql_types::BigInt;
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, QueryResult, SelectableHelper};
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use openidconnect::core::CoreJwsSigningAlgorithm::RsaSsaPkcs1V15Sha256;
+use openidconnect::core::{CoreJsonWebKey, CoreRsaPrivateSigningKey};
+use openidconnect::{JsonWebKey, PrivateSigningKey};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use uuid::Uuid;
+use x509_cert::Certificate;
+
+const AUDIT_CHAIN_LOCK: i64 = 0x6175_6469_745f_6c6f;
+const VERIFY_BATCH_SIZE: i64 = 1_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditEventType {
+    CodeIssued,
+    CodeRedeemed,
+    TokenIssued,
+    TokenRefreshed,
+    TokenRevoked,
+    ClientAuthFailed,
+    CertificateRejected,
+    ReplayDetected,
+    RefreshTokenReused,
+}
+
+impl AuditEventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::CodeIssued => "code_issued",
+            Self::CodeRedeemed => "code_redeemed",
+            Self::TokenIssued => "token_issued",
+            Self::TokenRefreshed => "token_refreshed",
+            Self::TokenRevoked => "token_revoked",
+            Self::ClientAuthFailed => "client_auth_failed",
+            Self::CertificateRejected => "certificate_rejected",
+            Self::ReplayDetected => "replay_detected",
+            Self::RefreshTokenReused => "refresh_token_reused",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AuditRecord {
+    event_type: AuditEventType,
+    client_id: Option<Uuid>,
+    subject: Option<Uuid>,
+    certificate_serial: Option<String>,
+    certificate_issuer: Option<String>,
+    source_ip: Option<IpAddr>,
+}
+
+impl AuditRecord {
+    pub fn new(event_type: AuditEventType) -> Self {
+        Self {
+            event_type,
+            client_id: None,
+            subject: None,
+            certificate_serial: None,
+            certificate_issuer: None,
+            source_ip: None,
+        }
+    }
+
+    pub fn with_client_id(mut self, client_id: Uuid) -> Self {
+        self.client_id = Some(client_id);
+        self
+    }
+
+    pub fn with_subject(mut self, subject: Uuid) -> Self {
+        self.subject = Some(subject);
+        self
+    }
+
+    pub fn with_certificate(mut self, certificate: &Certificate) -> Self {
+        self.certificate_serial = Some(serial_number_hex(
+            &certificate.tbs_certificate.serial_number,
+        ));
+        self.certificate_issuer = Some(certificate.tbs_certificate.issuer.to_string());
+        self
+    }
+
+    pub fn with_client_cert_data(self, client_cert_data: &ClientCertData) -> Self {
+        match client_cert_data.decode_certificate() {
+            Ok(Some(certificate)) => self.with_certificate(&certificate),
+            _ => self,
+        }
+    }
+
+    pub fn with_source_ip(mut self, source_ip: Option<IpAddr>) -> Self {
+        self.source_ip = source_ip;
+        self
+    }
+
+    fn into_event(self, occurred_at: DateTime<Utc>) -> AuditEvent {
+        AuditEvent {
+            id: Uuid::new_v4(),
+            event_type: self.event_type.as_str().to_owned(),
+            client_id: self.client_id,
+            subject: self.subject,
+            certificate_serial: self.certificate_serial,
+            certificate_issuer: self.certificate_issuer,
+            source_ip: self.source_ip.map(|x| x.to_string()),
+            occurred_at,
+            sequence: None,
+            prev_hash: None,
+            hash: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditChainBreakKind {
+    Unchained,
+    SequenceGap,
+    PreviousHashMismatch,
+    HashMismatch,
+    CheckpointMismatch,
+    Truncated,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditChainBreak {
+    pub event_id: Option<Uuid>,
+    pub sequence: Option<i64>,
+    pub kind: AuditChainBreakKind,
+}
+
+impl fmt::Display for AuditChainBreak {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self.kind {
+            AuditChainBreakKind::Unchained => "event is not part of the chain",
+            AuditChainBreakKind::SequenceGap => "sequence number is out of order",
+            AuditChainBreakKind::PreviousHashMismatch => "previous hash does not match",
+            AuditChainBreakKind::HashMismatch => "content hash does not match",
+            AuditChainBreakKind::CheckpointMismatch => "hash differs from the signed checkpoint",
+            AuditChainBreakKind::Truncated => "events covered by a checkpoint are missing",
+        };
+        match (self.sequence, self.event_id) {
+            (Some(sequence), _) => write!(f, "audit event {sequence}: {reason}"),
+            (None, Some(event_id)) => write!(f, "audit event {event_id}: {reason}"),
+            (None, None) => write!(f, "audit log: {reason}"),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct AuditChainVerifier {
+    previous: Option<(i64, String)>,
+}
+
+impl AuditChainVerifier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn starting_after(sequence: i64, hash: String) -> Self {
+        Self {
+            previous: Some((sequence, hash)),
+        }
+    }
+
+    pub fn last_sequence(&self) -> Option<i64> {
+        self.previous.as_ref().map(|x| x.0)
+    }
+
+    pub fn verify(&mut self, event: &AuditEvent) -> Result<(), AuditChainBreak> {
+        let chain_break = |kind| AuditChainBreak {
+            event_id: Some(event.id),
+            sequence: event.sequence,
+            kind,
+        };
+
+        let (Some(sequence), Some(hash)) = (event.sequence, &event.hash) else {
+            return Err(chain_break(AuditChainBreakKind::Unchained));
+        };
+        if sequence != self.last_sequence().map_or(1, |x| x + 1) {
+            return Err(chain_break(AuditChainBreakKind::SequenceGap));
+        }
+        if event.prev_hash.as_deref() != self.previous.as_ref().map(|x| x.1.as_str()) {
+            return Err(chain_break(AuditChainBreakKind::PreviousHashMismatch));
+        }
+        if event_hash(event).as_ref() != Some(hash) {
+            return Err(chain_break(AuditChainBreakKind::HashMismatch));
+        }
+
+        self.previous = Some((sequence, hash.clone()));
+        Ok(())
+    }
+}
+
+pub fn verify_events<'a>(
+    events: impl IntoIterator<Item = &'a AuditEvent>,
+) -> Option<AuditChainBreak> {
+    let mut verifier = AuditChainVerifier::new();
+    events.into_iter().find_map(|x| verifier.verify(x).err())
+}
+
+pub fn event_hash(event: &AuditEvent) -> Option<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(b"dnie-audit-event-v1");
+    hasher.update(event.sequence?.to_be_bytes());
+    update_field(&mut hasher, Some(event.id.as_bytes()));
+    update_field(&mut hasher, Some(event.event_type.as_bytes()));
+    update_field(
+        &mut hasher,
+        event.client_id.as_ref().map(|x| &x.as_bytes()[..]),
+    );
+    update_field(
+        &mut hasher,
+        event.subject.as_ref().map(|x| &x.as_bytes()[..]),
+    );
+    update_field(
+        &mut hasher,
+        event.certificate_serial.as_deref().map(str::as_bytes),
+    );
+    update_field(
+        &mut hasher,
+        event.certificate_issuer.as_deref().map(str::as_bytes),
+    );
+    update_field(&mut hasher, event.source_ip.as_deref().map(str::as_bytes));
+    hasher.update(event.occurred_at.timestamp_micros().to_be_bytes());
+    update_field(&mut hasher, event.prev_hash.as_deref().map(str::as_bytes));
+
+    Some(BASE64_STANDARD.encode(hasher.finalize()))
+}
+
+pub fn checkpoint_message(sequence: i64, hash: &str, signed_at: DateTime<Utc>) -> Vec<u8> {
+    let mut message = b"dnie-audit-checkpoint-v1".to_vec();
+    message.extend_from_slice(&sequence.to_be_bytes());
+    message.extend_from_slice(&(hash.len() as u32).to_be_bytes());
+    message.extend_from_slice(hash.as_bytes());
+    message.extend_from_slice(&signed_at.timestamp_micros().to_be_bytes());
+    message
+}
+
+pub fn sign_checkpoint(
+    signing_key: &CoreRsaPrivateSigningKey,
+    sequence: i64,
+    hash: String,
+) -> Result<AuditCheckpoint, Error> {
+    let signed_at = Utc::now().trunc_subsecs(6);
+    let signature = signing_key
+        .sign(
+            &RsaSsaPkcs1V15Sha256,
+            &checkpoint_message(sequence, &hash, signed_at),
+        )
+        .map_err(|_| Error::Crypto("failed to sign the audit checkpoint"))?;
+    let verification_key = signing_key.as_verification_key();
+    let public_jwk = serde_json::to_string(&verification_key)
+        .map_err(|_| Error::Crypto("failed to encode the checkpoint key"))?;
+    Ok(AuditCheckpoint {
+        sequence,
+        hash,
+        key_id: verification_key.key_id().map(|x| x.as_str().to_owned()),
+        signature: BASE64_STANDARD.encode(signature),
+        signed_at,
+        public_jwk: Some(public_jwk),
+    })
+}
+
+// Signing keys are deleted after their retention period, so each checkpoint keeps
+// the public key it was signed with.
+pub fn checkpoint_verification_key(checkpoint: &AuditCheckpoint) -> Option<CoreJsonWebKey> {
+    let verification_key =
+        serde_json::from_str::<CoreJsonWebKey>(checkpoint.public_jwk.as_deref()?).ok()?;
+    let key_id = verification_key.key_id().map(|x| x.as_str());
+    (key_id == checkpoint.key_id.as_deref()).then_some(verification_key)
+}
+
+pub fn verify_checkpoint(checkpoint: &AuditCheckpoint, verification_key: &CoreJsonWebKey) -> bool {
+    let Ok(signature) = BASE64_STANDARD.decode(&checkpoint.signature) else {
+        return false;
+    };
+    let message = checkpoint_message(checkpoint.sequence, &checkpoint.hash, checkpoint.signed_at);
+
+    verification_key
+        .verify_signature(&RsaSsaPkcs1V15Sha256, &message, &signature)
+        .is_ok()
+}
+
+fn update_field(hasher: &mut Sha256, value: Option<&[u8]>) {
+    match value {
+        Some(value) => {
+            hasher.update([1]);
+            hasher.update((value.len() as u32).to_be_bytes());
+            hasher.update(value);
+        }
+        None => hasher.update([0]),
+    }
+}
+
+pub struct AuditLog {
+    pool: Arc<db::Pool>,
+}
+
+impl AuditLog {
+    pub fn new(pool: Arc<db::Pool>) -> Self {
+        Self { pool }
+    }
+
+    pub async fn record(&self, record: AuditRecord) -> Result<AuditEvent, Error> {
+        let mut conn = self.pool.get().await.map_err(|_| Error::Pool)?;
+        Ok(Self::insert(&mut conn, record).await?)
+    }
+
+    pub(crate) async fn record_failure(&self, record: AuditRecord) {
+        if let Err(err) = self.record(record).await {
+            tracing::warn!(error = %err, "failed to record audit event");
+        }
+    }
+
+    pub async fn events(&self, after_sequence: i64, limit: i64) -> Result<Vec<AuditEvent>, Error> {
+        let mut conn = self.pool.get().await.map_err(|_| Error::Pool)?;
+        Ok(Self::load_events(&mut conn, after_sequence, limit).await?)
+    }
+
+    pub async fn checkpoints(&self) -> Result<Vec<AuditCheckpoint>, Error> {
+        let mut conn = self.pool.get().await.map_err(|_| Error::Pool)?;
+        Ok(audit_checkpoints
+            .order(audit_checkpoints_columns::sequence.asc())
+            .select(AuditCheckpoint::as_select())
+            .load(&mut conn)
+            .await?)
+    }
+
+    pub async fn verify_chain(&self) -> Result<Option<AuditChainBreak>, Error> {
+        let checkpoints = self
+            .checkpoints()
+            .await?
+            .into_iter()
+            .map(|x| (x.sequence, x.hash))
+            .collect::<HashMap<_, _>>();
+        let mut conn = self.pool.get().await.map_err(|_| Error::Pool)?;
+
+        let mut verifier = AuditChainVerifier::new();
+        if let Some(chain_break) = Self::walk_chain(&mut conn, &mut verifier, &checkpoints).await? {
+            return Ok(Some(chain_break));
+        }
+
+        let last_sequence = verifier.last_sequence().unwrap_or(0);
+        if checkpoints.keys().any(|x| *x > last_sequence) {
+            return Ok(Some(AuditChainBreak {
+                event_id: None,
+                sequence: None,
+                kind: AuditChainBreakKind::Truncated,
+            }));
+        }
+
+        let genesis = audit_events
+            .filter(audit_events_columns::sequence.eq(1))
+            .select(audit_events_columns::occurred_at)
+            .first::<DateTime<Utc>>(&mut conn)
+            .await
+            .optional()?;
+        let Some(genesis) = genesis else {
+            return Ok(None);
+        };
+
+        // Events recorded before the chain was introduced are left unchained.
+        let unchained = audit_events
+            .filter(audit_events_columns::sequence.is_null())
+            .filter(audit_events_columns::occurred_at.ge(genesis))
+            .order(audit_events_columns::occurred_at.asc())
+            .select(audit_events_columns::id)
+            .first::<Uuid>(&mut conn)
+            .await
+            .optional()?;
+        Ok(unchained.map(|x| AuditChainBreak {
+            event_id: Some(x),
+            sequence: None,
+            kind: AuditChainBreakKind::Unchained,
+        }))
+    }
+
+    pub async fn create_checkpoint(
+        &self,
+        key_manager: &KeyManager,
+    ) -> Result<Option<AuditCheckpoint>, Error> {
+        let mut conn = self.pool.get().await.map_err(|_| Error::Pool)?;
+
+        let latest_checkpoint = audit_checkpoints
+            .order(audit_checkpoints_columns::sequence.desc())
+            .select(AuditCheckpoint::as_select())
+            .first(&mut conn)
+            .await
+            .optional()?;
+        let mut verifier = match latest_checkpoint {
+            Some(checkpoint) => {
+                AuditChainVerifier::starting_after(checkpoint.sequence, checkpoint.hash)
+            }
+            None => AuditChainVerifier::new(),
+        };
+        let start_sequence = verifier.last_sequence();
+
+        if let Some(chain_break) =
+            Self::walk_chain(&mut conn, &mut verifier, &HashMap::new()).await?
+        {
+            tracing::warn!(%chain_break, "refusing to checkpoint a broken audit chain");
+            return Err(Error::Validation("audit chain is broken"));
+        }
+
+        let Some((sequence, hash)) = verifier.previous else {
+            return Ok(None);
+        };
+        if Some(sequence) == start_sequence {
+            return Ok(None);
+        }
+
+        let keys = key_manager.current().await?;
+        let checkpoint = sign_checkpoint(keys.active(), sequence, hash)?;
+
+        Ok(Some(
+            insert_into(audit_checkpoints)
+                .values(&checkpoint)
+                .returning(AuditCheckpoint::as_returning())
+                .get_result(&mut conn)
+                .await?,
+        ))
+    }
+
+    pub fn spawn_checkpoints(
+        self: Arc<Self>,
+        key_manager: Arc<KeyManager>,
+        interval: Duration,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            loop {
+                interval.tick().await;
+                match self.create_checkpoint(&key_manager).await {
+                    Ok(Some(checkpoint)) => {
+                        tracing::info!(sequence = checkpoint.sequence, "signed audit checkpoint")
+                    }
+                    Ok(None) => tracing::debug!("no new audit events to checkpoint"),
+                    Err(err) => tracing::warn!(error = %err, "audit checkpoint failed"),
+                }
+            }
+        })
+    }
+
+    pub(crate) async fn insert(
+        conn: &mut AsyncPgConnection,
+        record: AuditRecord,
+    ) -> QueryResult<AuditEvent> {
+        conn.transaction(|conn| {
+            async move {
+                sql_query("SELECT pg_advisory_xact_lock($1)")
+                    .bind::<BigInt, _>(AUDIT_CHAIN_LOCK)
+                    .execute(conn)
+                    .await?;
+
+                let previous = audit_events
+                    .filter(audit_events_columns::sequence.is_not_null())
+                    .order(audit_events_columns::sequence.desc())
+                    .select((audit_events_columns::sequence, audit_events_columns::hash))
+                    .first::<(Option<i64>, Option<String>)>(conn)
+                    .await
+                    .optional()?;
+                let (previous_sequence, previous_hash) = previous.unwrap_or_default();
+
+                // PostgreSQL stores microseconds, so hash what will be read back.
+                let mut event = record.into_event(Utc::now().trunc_subsecs(6));
+                event.sequence = Some(previous_sequence.unwrap_or(0) + 1);
+                event.prev_hash = previous_hash;
+                event.hash = event_hash(&event);
+
+                insert_into(audit_events)
+                    .values(&event)
+                    .returning(AuditEvent::as_returning())
+                    .get_result(conn)
+                    .await
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    async fn load_events(
+        conn: &mut AsyncPgConnection,
+        after_sequence: i64,
+        limit: i64,
+    ) -> QueryResult<Vec<AuditEvent>> {
+        audit_events
+            .filter(audit_events_columns::sequence.gt(after_sequence))
+            .order(audit_events_columns::sequence.asc())
+            .limit(limit)
+            .select(AuditEvent::as_select())
+            .load(conn)
+            .await
+    }
+
+    async fn walk_chain(
+        conn: &mut AsyncPgConnection,
+        verifier: &mut AuditChainVerifier,
+        checkpoints: &HashMap<i64, String>,
+    ) -> Result<Option<AuditChainBreak>, Error> {
+        loop {
+            let after_sequence = verifier.last_sequence().unwrap_or(0);
+            let events = Self::load_events(conn, after_sequence, VERIFY_BATCH_SIZE).await?;
+
+            for event in &events {
+                if let Err(chain_break) = verifier.verify(event) {
+                    return Ok(Some(chain_break));
+                }
+                if let Some(sequence) = event.sequence
+                    && checkpoints
+                        .get(&sequence)
+                        .is_some_and(|x| Some(x) != event.hash.as_ref())
+                {
+                    return Ok(Some(AuditChainBreak {
+                        event_id: Some(event.id),
+                        sequence: event.sequence,
+                        kind: AuditChainBreakKind::CheckpointMismatch,
+                    }));
+                }
+            }
+
+            if events.len() < VERIFY_BATCH_SIZE as usize {
+                return Ok(None);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeDelta, TimeZone};
+    use openidconnect::JsonWebKeyId;
+
+    fn event(sequence: i64, prev_hash: Option<String>) -> AuditEvent {
+        let mut event = AuditEvent {
+            id: Uuid::from_u128(sequence as u128),
+            event_type: AuditEventType::TokenIssued.as_str().to_owned(),
+            client_id: Some(Uuid::from_u128(42)),
+            subject: None,
+            certificate_serial: Some("01".to_owned()),
+            certificate_issuer: None,
+            source_ip: Some("192.0.2.1".to_owned()),
+            occurred_at: Utc.timestamp_opt(1_700_000_000 + sequence, 0).unwrap(),
+            sequence: Some(sequence),
+            prev_hash,
+            hash: None,
+        };
+        event.hash = event_hash(&event);
+        event
+    }
+
+    fn chain(len: i64) -> Vec<AuditEvent> {
+        let mut events: Vec<AuditEvent> = vec![];
+        for sequence in 1..=len {
+            let prev_hash = events.last().and_then(|x| x.hash.clone());
+            events.push(event(sequence, prev_hash));
+        }
+        events
+    }
+
+    fn signing_key(kid: &str) -> CoreRsaPrivateSigningKey {
+        CoreRsaPrivateSigningKey::from_pem(
+            include_str!("../../testdata/signing_key.pem"),
+            Some(JsonWebKeyId::new(kid.to_owned())),
+        )
+        .unwrap()
+    }
+
+    fn chain_break(events: &[AuditEvent]) -> Option<AuditChainBreakKind> {
+        verify_events(events).map(|x| x.kind)
+    }
+
+    #[test]
+    fn event_hash_covers_every_field() {
+        let hash = event(1, None).hash;
+        assert_eq!(event_hash(&event(1, None)), hash);
+
+        let changes: [fn(&mut AuditEvent); 7] = [
+            |x| x.event_type = AuditEventType::ClientAuthFailed.as_str().to_owned(),
+            |x| x.client_id = None,
+            |x| x.subject = Some(Uuid::from_u128(42)),
+            |x| x.certificate_serial = Some("02".to_owned()),
+            |x| x.certificate_issuer = Some("01".to_owned()),
+            |x| x.occurred_at += TimeDelta::microseconds(1),
+            |x| x.prev_hash = Some(String::new()),
+        ];
+        for change in changes {
+            let mut changed = event(1, None);
+            change(&mut changed);
+            assert_ne!(event_hash(&changed), hash);
+        }
+    }
+
+    #[test]
+    fn event_hash_requires_sequence() {
+        let mut event = chain(1).remove(0);
+        event.sequence = None;
+        assert_eq!(event_hash(&event), None);
+    }
+
+    #[test]
+    fn verifies_intact_chain() {
+        let events = chain(5);
+        assert_eq!(chain_break(&events), None);
+
+        let mut verifier = AuditChainVerifier::starting_after(2, events[1].hash.clone().unwrap());
+        for event in &events[2..] {
+            verifier.verify(event).unwrap();
+        }
+        assert_eq!(verifier.last_sequence(), Some(5));
+    }
+
+    #[test]
+    fn detects_tampered_event() {
+        let mut events = chain(3);
+        events[1].event_type = AuditEventType::ClientAuthFailed.as_str().to_owned();
+        let chain_break = verify_events(&events).unwrap();
+        assert_eq!(chain_break.kind, AuditChainBreakKind::HashMismatch);
+        assert_eq!(chain_break.sequence, Some(2));
+    }
+
+    #[test]
+    fn detects_removed_event() {
+        let mut events = chain(3);
+        events.remove(1);
+        assert_eq!(chain_break(&events), Some(AuditChainBreakKind::SequenceGap));
+    }
+
+    #[test]
+    fn detects_rehashed_event_with_wrong_predecessor() {
+        let mut events = chain(3);
+        events[2].prev_hash = events[0].hash.clone();
+        events[2].hash = event_hash(&events[2]);
+        assert_eq!(
+            chain_break(&events),
+            Some(AuditChainBreakKind::PreviousHashMismatch)
+        );
+    }
+
+    #[test]
+    fn detects_unchained_event() {
+        let mut events = chain(2);
+        events[1].hash = None;
+        assert_eq!(chain_break(&events), Some(AuditChainBreakKind::Unchained));
+    }
+
+    #[test]
+    fn verifies_checkpoint_with_its_stored_key() {
+        let checkpoint = sign_checkpoint(&signing_key("kid-1"), 3, "hash".to_owned()).unwrap();
+        assert_eq!(checkpoint.key_id.as_deref(), Some("kid-1"));
+
+        let verification_key = checkpoint_verification_key(&checkpoint).unwrap();
+        assert_eq!(verification_key, signing_key("kid-1").as_verification_key());
+        assert!(verify_checkpoint(&checkpoint, &verification_key));
+
+        let mut tampered = checkpoint;
+        tampered.hash = "other".to_owned();
+        assert!(!verify_checkpoint(&tampered, &verification_key));
+    }
+
+    #[test]
+    fn rejects_stored_key_with_another_key_id() {
+        let mut checkpoint = sign_checkpoint(&signing_key("kid-1"), 3, "hash".to_owned()).unwrap();
+        checkpoint.key_id = Some("kid-2".to_owned());
+        assert_eq!(checkpoint_verification_key(&checkpoint), None);
+
+        checkpoint.public_jwk = None;
+        assert_eq!(checkpoint_verification_key(&checkpoint), None);
+    }
+}
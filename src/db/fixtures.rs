@@ -0,0 +1,150 @@
+use crate::db;
+use crate::db::models::{AuthClient, OAuthGrant, User};
+use crate::db::schema::auth_clients::dsl::auth_clients;
+use crate::db::schema::client_assertion_jtis::dsl::client_assertion_jtis;
+use crate::db::schema::oauth_access_tokens::dsl::oauth_access_tokens;
+use crate::db::schema::oauth_grant_extensions::dsl::oauth_grant_extensions;
+use crate::db::schema::oauth_grant_replays::dsl::oauth_grant_replays;
+use crate::db::schema::oauth_grants::dsl::oauth_grants;
+use crate::db::schema::oauth_refresh_tokens::dsl::oauth_refresh_tokens;
+use crate::db::schema::users::dsl::users;
+use crate::db::schema::{
+    auth_clients as auth_clients_columns, client_assertion_jtis as client_assertion_jtis_columns,
+    oauth_access_tokens as oauth_access_tokens_columns,
+    oauth_grant_extensions as oauth_grant_extensions_columns,
+    oauth_grant_replays as oauth_grant_replays_columns, oauth_grants as oauth_grants_columns,
+    oauth_refresh_tokens as oauth_refresh_tokens_columns, users as users_columns,
+};
+use chrono::{DateTime, Utc};
+use diesel::dsl::{delete, insert_into};
+use diesel::{ExpressionMethods, QueryDsl};
+use diesel_async::RunQueryDsl;
+use std::sync::Arc;
+use uuid::Uuid;
+
+pub(crate) const ENCRYPTION_KEY: [u8; 32] = [7u8; 32];
+
+pub(crate) async fn pool() -> Option<Arc<db::Pool>> {
+    db::test_pool().await.map(Arc::new)
+}
+
+pub(crate) async fn insert_client(pool: &db::Pool, allow_refresh_tokens: bool) -> Uuid {
+    let client = AuthClient {
+        id: Uuid::new_v4(),
+        client_secret_hash: None,
+        default_scope: "openid".to_owned(),
+        confidential: false,
+        subject_type: "public".to_owned(),
+        sector_identifier: None,
+        require_pkce: false,
+        allow_refresh_tokens,
+        access_token_format: "opaque".to_owned(),
+        token_endpoint_auth_method: "none".to_owned(),
+        tls_client_auth_subject_dn: None,
+        tls_client_certificate_thumbprint: None,
+        jwks: None,
+        jwks_uri: None,
+        encrypted_client_secret: None,
+    };
+    let mut conn = pool.get().await.unwrap();
+    insert_into(auth_clients)
+        .values(&client)
+        .execute(&mut conn)
+        .await
+        .unwrap();
+    client.id
+}
+
+pub(crate) async fn insert_user(pool: &db::Pool) -> Uuid {
+    let now = Utc::now();
+    let user = User {
+        id: Uuid::new_v4(),
+        given_name: "JUAN".to_owned(),
+        surname: "ESPAÑOL ESPAÑOL".to_owned(),
+        country: "ES".to_owned(),
+        certificate_fingerprint: None,
+        first_login_at: now,
+        last_login_at: now,
+        disabled: false,
+    };
+    let mut conn = pool.get().await.unwrap();
+    insert_into(users)
+        .values(&user)
+        .execute(&mut conn)
+        .await
+        .unwrap();
+    user.id
+}
+
+pub(crate) async fn insert_grant(
+    pool: &db::Pool,
+    code_hash: &str,
+    client_id: Uuid,
+    owner_id: Uuid,
+    until: DateTime<Utc>,
+) {
+    let grant = OAuthGrant {
+        code_hash: code_hash.to_owned(),
+        client_id,
+        owner_id,
+        redirect_uri: "https://client.example/callback".to_owned(),
+        scope: "openid".to_owned(),
+        until,
+        consumed_at: None,
+        code_challenge: None,
+        code_challenge_method: None,
+    };
+    let mut conn = pool.get().await.unwrap();
+    insert_into(oauth_grants)
+        .values(&grant)
+        .execute(&mut conn)
+        .await
+        .unwrap();
+}
+
+pub(crate) async fn remove_client(pool: &db::Pool, client_id: Uuid) {
+    let mut conn = pool.get().await.unwrap();
+    let code_hashes = oauth_grants
+        .filter(oauth_grants_columns::client_id.eq(client_id))
+        .select(oauth_grants_columns::code_hash);
+
+    delete(oauth_access_tokens.filter(oauth_access_tokens_columns::client_id.eq(client_id)))
+        .execute(&mut conn)
+        .await
+        .unwrap();
+    delete(oauth_refresh_tokens.filter(oauth_refresh_tokens_columns::client_id.eq(client_id)))
+        .execute(&mut conn)
+        .await
+        .unwrap();
+    delete(
+        oauth_grant_extensions
+            .filter(oauth_grant_extensions_columns::code_hash.eq_any(code_hashes)),
+    )
+    .execute(&mut conn)
+    .await
+    .unwrap();
+    delete(oauth_grant_replays.filter(oauth_grant_replays_columns::code_hash.eq_any(code_hashes)))
+        .execute(&mut conn)
+        .await
+        .unwrap();
+    delete(oauth_grants.filter(oauth_grants_columns::client_id.eq(client_id)))
+        .execute(&mut conn)
+        .await
+        .unwrap();
+    delete(client_assertion_jtis.filter(client_assertion_jtis_columns::client_id.eq(client_id)))
+        .execute(&mut conn)
+        .await
+        .unwrap();
+    delete(auth_clients.filter(auth_clients_columns::id.eq(client_id)))
+        .execute(&mut conn)
+        .await
+        .unwrap();
+}
+
+pub(crate) async fn remove_user(pool: &db::Pool, user_id: Uuid) {
+    let mut conn = pool.get().await.unwrap();
+    delete(users.filter(users_columns::id.eq(user_id)))
+        .execute(&mut conn)
+        .await
+        .unwrap();
+}
@@ -0,0 +1,526 @@
+use crate::db;
+use crate::db::schema::oauth_access_tokens::dsl::oauth_access_tokens;
+use crate::db::schema::oauth_grant_extensions::dsl::oauth_grant_extensions;
+use crate::db::schema::oauth_grant_replays::dsl::oauth_grant_replays;
+use crate::db::schema::oauth_grants::dsl::oauth_grants;
+use crate::db::schema::oauth_refresh_tokens::dsl::oauth_refresh_tokens;
+use crate::db::schema::{
+    oauth_access_tokens as oauth_access_tokens_columns,
+    oauth_grant_extensions as oauth_grant_extensions_columns,
+    oauth_grant_replays as oauth_grant_replays_columns, oauth_grants as oauth_grants_columns,
+    oauth_refresh_tokens as oauth_refresh_tokens_columns,
+};
+use chrono::{DateTime, TimeDelta, Utc};
+use diesel::dsl::{delete, sql_query};
+use diesel::sql_types::{BigInt, Timestamptz};
+use diesel::{ExpressionMethods, QueryDsl};
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use std::fmt;
+use std::ops::AddAssign;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+
+#[derive(Debug)]
+pub enum JanitorError {
+    Pool,
+    Database(diesel::result::Error),
+}
+
+impl fmt::Display for JanitorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pool => write!(f, "no database connection available"),
+            Self::Database(err) => write!(f, "purge query failed: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for JanitorError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Pool => None,
+            Self::Database(err) => Some(err),
+        }
+    }
+}
+
+impl From<diesel::result::Error> for JanitorError {
+    fn from(err: diesel::result::Error) -> Self {
+        Self::Database(err)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PurgeReport {
+    pub grants: usize,
+    pub grant_extensions: usize,
+    pub grant_replays: usize,
+    pub access_tokens: usize,
+    pub refresh_tokens: usize,
+    pub client_assertion_jtis: usize,
+}
+
+impl PurgeReport {
+    pub fn total(&self) -> usize {
+        self.grants
+            + self.grant_extensions
+            + self.grant_replays
+            + self.access_tokens
+            + self.refresh_tokens
+            + self.client_assertion_jtis
+    }
+}
+
+impl AddAssign for PurgeReport {
+    fn add_assign(&mut self, other: Self) {
+        self.grants += other.grants;
+        self.grant_extensions += other.grant_extensions;
+        self.grant_replays += other.grant_replays;
+        self.access_tokens += other.access_tokens;
+        self.refresh_tokens += other.refresh_tokens;
+        self.client_assertion_jtis += other.client_assertion_jtis;
+    }
+}
+
+pub struct Janitor {
+    pool: Arc<db::Pool>,
+    interval: Duration,
+    batch_size: usize,
+    retention: TimeDelta,
+}
+
+impl Janitor {
+    pub fn new(pool: Arc<db::Pool>) -> Self {
+        Self {
+            pool,
+            interval: Duration::from_mins(5),
+            batch_size: 1_000,
+            retention: TimeDelta::hours(1),
+        }
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    pub fn with_retention(mut self, retention: TimeDelta) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(self.interval);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            loop {
+                interval.tick().await;
+                match self.purge().await {
+                    Ok(report) if report.total() > 0 => tracing::info!(
+                        grants = report.grants,
+                        grant_extensions = report.grant_extensions,
+                        grant_replays = report.grant_replays,
+                        access_tokens = report.access_tokens,
+                        refresh_tokens = report.refresh_tokens,
+                        client_assertion_jtis = report.client_assertion_jtis,
+                        "purged expired rows"
+                    ),
+                    Ok(_) => tracing::debug!("nothing to purge"),
+                    Err(err) => tracing::warn!(error = %err, "purge failed"),
+                }
+            }
+        })
+    }
+
+    pub async fn purge(&self) -> Result<PurgeReport, JanitorError> {
+        let cutoff = Utc::now() - self.retention;
+        let mut conn = self.pool.get().await.map_err(|_| JanitorError::Pool)?;
+
+        let mut report = PurgeReport::default();
+        loop {
+            let batch = Self::purge_grants(&mut conn, cutoff, self.batch_size).await?;
+            report += batch;
+            if batch.grants < self.batch_size {
+                break;
+            }
+        }
+        loop {
+            let batch = Self::purge_access_tokens(&mut conn, cutoff, self.batch_size).await?;
+            report += batch;
+            if batch.access_tokens < self.batch_size {
+                break;
+            }
+        }
+        loop {
+            let batch = Self::purge_refresh_tokens(&mut conn, cutoff, self.batch_size).await?;
+            report += batch;
+            if batch.refresh_tokens < self.batch_size {
+                break;
+            }
+        }
+        loop {
+            let batch =
+                Self::purge_client_assertion_jtis(&mut conn, cutoff, self.batch_size).await?;
+            report += batch;
+            if batch.client_assertion_jtis < self.batch_size {
+                break;
+            }
+        }
+
+        Ok(report)
+    }
+
+    async fn purge_grants(
+        conn: &mut AsyncPgConnection,
+        cutoff: DateTime<Utc>,
+        batch_size: usize,
+    ) -> Result<PurgeReport, JanitorError> {
+        conn.transaction::<_, JanitorError, _>(|conn| {
+            async move {
+                let code_hashes = oauth_grants
+                    .filter(oauth_grants_columns::until.lt(cutoff))
+                    .select(oauth_grants_columns::code_hash)
+                    .limit(batch_size as i64)
+                    .for_update()
+                    .skip_locked()
+                    .load::<String>(conn)
+                    .await?;
+                if code_hashes.is_empty() {
+                    return Ok(PurgeReport::default());
+                }
+
+                let grant_extensions = delete(
+                    oauth_grant_extensions
+                        .filter(oauth_grant_extensions_columns::code_hash.eq_any(&code_hashes)),
+                )
+                .execute(conn)
+                .await?;
+
+                let grant_replays = delete(
+                    oauth_grant_replays
+                        .filter(oauth_grant_replays_columns::code_hash.eq_any(&code_hashes)),
+                )
+                .execute(conn)
+                .await?;
+
+                let grants = delete(
+                    oauth_grants.filter(oauth_grants_columns::code_hash.eq_any(&code_hashes)),
+                )
+                .execute(conn)
+                .await?;
+
+                Ok(PurgeReport {
+                    grants,
+                    grant_extensions,
+                    grant_replays,
+                    ..Default::default()
+                })
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    async fn purge_access_tokens(
+        conn: &mut AsyncPgConnection,
+        cutoff: DateTime<Utc>,
+        batch_size: usize,
+    ) -> Result<PurgeReport, JanitorError> {
+        conn.transaction::<_, JanitorError, _>(|conn| {
+            async move {
+                let token_hashes = oauth_access_tokens
+                    .filter(oauth_access_tokens_columns::expires_at.lt(cutoff))
+                    .select(oauth_access_tokens_columns::token_hash)
+                    .limit(batch_size as i64)
+                    .for_update()
+                    .skip_locked()
+                    .load::<String>(conn)
+                    .await?;
+                if token_hashes.is_empty() {
+                    return Ok(PurgeReport::default());
+                }
+
+                let access_tokens = delete(
+                    oauth_access_tokens
+                        .filter(oauth_access_tokens_columns::token_hash.eq_any(&token_hashes)),
+                )
+                .execute(conn)
+                .await?;
+
+                Ok(PurgeReport {
+                    access_tokens,
+                    ..Default::default()
+                })
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    async fn purge_refresh_tokens(
+        conn: &mut AsyncPgConnection,
+        cutoff: DateTime<Utc>,
+        batch_size: usize,
+    ) -> Result<PurgeReport, JanitorError> {
+        conn.transaction::<_, JanitorError, _>(|conn| {
+            async move {
+                let token_hashes = oauth_refresh_tokens
+                    .filter(oauth_refresh_tokens_columns::session_expires_at.lt(cutoff))
+                    .select(oauth_refresh_tokens_columns::token_hash)
+                    .limit(batch_size as i64)
+                    .for_update()
+                    .skip_locked()
+                    .load::<String>(conn)
+                    .await?;
+                if token_hashes.is_empty() {
+                    return Ok(PurgeReport::default());
+                }
+
+                let refresh_tokens = delete(
+                    oauth_refresh_tokens
+                        .filter(oauth_refresh_tokens_columns::token_hash.eq_any(&token_hashes)),
+                )
+                .execute(conn)
+                .await?;
+
+                Ok(PurgeReport {
+                    refresh_tokens,
+                    ..Default::default()
+                })
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    async fn purge_client_assertion_jtis(
+        conn: &mut AsyncPgConnection,
+        cutoff: DateTime<Utc>,
+        batch_size: usize,
+    ) -> Result<PurgeReport, JanitorError> {
+        // A materialized CTE locks the batch once; an IN subquery may be rescanned and
+        // delete rows beyond the batch.
+        let client_assertion_jtis = sql_query(
+            "WITH batch AS MATERIALIZED (\
+             SELECT client_id, jti FROM client_assertion_jtis WHERE expires_at < $1 \
+             LIMIT $2 FOR UPDATE SKIP LOCKED) \
+             DELETE FROM client_assertion_jtis USING batch \
+             WHERE client_assertion_jtis.client_id = batch.client_id \
+             AND client_assertion_jtis.jti = batch.jti",
+        )
+        .bind::<Timestamptz, _>(cutoff)
+        .bind::<BigInt, _>(batch_size as i64)
+        .execute(conn)
+        .await?;
+
+        Ok(PurgeReport {
+            client_assertion_jtis,
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::fixtures;
+    use crate::db::models::{ClientAssertionJti, OAuthGrantExtension, OAuthGrantReplay};
+    use crate::db::schema::client_assertion_jtis as client_assertion_jtis_columns;
+    use crate::db::schema::client_assertion_jtis::dsl::client_assertion_jtis;
+    use chrono::TimeZone;
+    use diesel::dsl::insert_into;
+    use tokio::sync::Mutex;
+    use uuid::Uuid;
+
+    // Purges remove every row older than the cutoff, so tests that purge must not overlap.
+    static PURGE: Mutex<()> = Mutex::const_new(());
+
+    fn at(year: i32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, 1, 1, 0, 0, 0).unwrap()
+    }
+
+    struct Fixture {
+        pool: Arc<db::Pool>,
+        client_id: Uuid,
+        owner_id: Uuid,
+    }
+
+    impl Fixture {
+        async fn new() -> Option<Self> {
+            let pool = fixtures::pool().await?;
+            let client_id = fixtures::insert_client(&pool, false).await;
+            let owner_id = fixtures::insert_user(&pool).await;
+            Some(Self {
+                pool,
+                client_id,
+                owner_id,
+            })
+        }
+
+        async fn grant(&self, until: DateTime<Utc>) -> String {
+            let code_hash = Uuid::new_v4().to_string();
+            fixtures::insert_grant(&self.pool, &code_hash, self.client_id, self.owner_id, until)
+                .await;
+            code_hash
+        }
+
+        async fn grants(&self) -> i64 {
+            let mut conn = self.pool.get().await.unwrap();
+            oauth_grants
+                .filter(oauth_grants_columns::client_id.eq(self.client_id))
+                .count()
+                .get_result(&mut conn)
+                .await
+                .unwrap()
+        }
+
+        async fn remove(self) {
+            fixtures::remove_client(&self.pool, self.client_id).await;
+            fixtures::remove_user(&self.pool, self.owner_id).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn purges_grants_in_batches() {
+        let _purge = PURGE.lock().await;
+        let Some(fixture) = Fixture::new().await else {
+            return;
+        };
+        for _ in 0..3 {
+            fixture.grant(at(1975)).await;
+        }
+        fixture.grant(at(1985)).await;
+
+        let mut conn = fixture.pool.get().await.unwrap();
+        let mut batches = vec![];
+        for _ in 0..3 {
+            let batch = Janitor::purge_grants(&mut conn, at(1980), 2).await.unwrap();
+            batches.push(batch.grants);
+        }
+        drop(conn);
+        let remaining = fixture.grants().await;
+        fixture.remove().await;
+
+        assert_eq!(batches, [2, 1, 0]);
+        assert_eq!(remaining, 1);
+    }
+
+    #[tokio::test]
+    async fn purge_repeats_full_batches() {
+        let _purge = PURGE.lock().await;
+        let Some(fixture) = Fixture::new().await else {
+            return;
+        };
+        for _ in 0..5 {
+            fixture.grant(at(1975)).await;
+        }
+
+        let janitor = Janitor::new(fixture.pool.clone())
+            .with_batch_size(2)
+            .with_retention(Utc::now() - at(1980));
+        let report = janitor.purge().await;
+        let remaining = fixture.grants().await;
+        fixture.remove().await;
+
+        assert_eq!(report.unwrap().grants, 5);
+        assert_eq!(remaining, 0);
+    }
+
+    #[tokio::test]
+    async fn purges_replays_and_extensions_with_their_grant() {
+        let _purge = PURGE.lock().await;
+        let Some(fixture) = Fixture::new().await else {
+            return;
+        };
+        let code_hash = fixture.grant(at(1975)).await;
+
+        let mut conn = fixture.pool.get().await.unwrap();
+        insert_into(oauth_grant_extensions)
+            .values(&OAuthGrantExtension {
+                code_hash: code_hash.clone(),
+                name: "mtls".to_owned(),
+                value: String::new(),
+            })
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        insert_into(oauth_grant_replays)
+            .values(&OAuthGrantReplay {
+                id: Uuid::new_v4(),
+                code_hash: code_hash.clone(),
+                attempted_at: at(1975),
+            })
+            .execute(&mut conn)
+            .await
+            .unwrap();
+
+        let report = Janitor::purge_grants(&mut conn, at(1980), 10).await;
+        drop(conn);
+        let remaining = fixture.grants().await;
+        fixture.remove().await;
+
+        assert_eq!(
+            report.unwrap(),
+            PurgeReport {
+                grants: 1,
+                grant_extensions: 1,
+                grant_replays: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(remaining, 0);
+    }
+
+    #[tokio::test]
+    async fn purges_client_assertion_jtis_by_client_and_jti() {
+        let _purge = PURGE.lock().await;
+        let Some(fixture) = Fixture::new().await else {
+            return;
+        };
+        let other_client_id = fixtures::insert_client(&fixture.pool, false).await;
+        // Interleaved so that the first batch spans both clients and both jtis.
+        let jtis = [
+            (fixture.client_id, "first"),
+            (other_client_id, "second"),
+            (fixture.client_id, "second"),
+            (other_client_id, "first"),
+        ]
+        .map(|(client_id, jti)| ClientAssertionJti {
+            client_id,
+            jti: jti.to_owned(),
+            expires_at: at(1975),
+        });
+        let mut conn = fixture.pool.get().await.unwrap();
+        insert_into(client_assertion_jtis)
+            .values(&jtis)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+
+        let report = Janitor::purge_client_assertion_jtis(&mut conn, at(1980), 2).await;
+        let remaining = client_assertion_jtis
+            .filter(
+                client_assertion_jtis_columns::client_id
+                    .eq_any([fixture.client_id, other_client_id]),
+            )
+            .count()
+            .get_result::<i64>(&mut conn)
+            .await
+            .unwrap();
+        drop(conn);
+        fixtures::remove_client(&fixture.pool, other_client_id).await;
+        fixture.remove().await;
+
+        assert_eq!(report.unwrap().client_assertion_jtis, 2);
+        assert_eq!(remaining, 2);
+    }
+}
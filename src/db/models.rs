@@ -1,5 +1,5 @@
 use chrono::{DateTime, Utc};
-use diesel::{Associations, Identifiable, Insertable, Queryable, Selectable};
+use diesel::{AsChangeset, Associations, Identifiable, Insertable, Queryable, Selectable};
 use uuid::Uuid;
 
 #[derive(Debug, Queryable, Identifiable, Selectable, Insertable, PartialEq)]
@@ -10,6 +10,17 @@ pub struct AuthClient {
     pub client_secret_hash: Option<String>,
     pub default_scope: String,
     pub confidential: bool,
+    pub subject_type: String,
+    pub sector_identifier: Option<String>,
+    pub require_pkce: bool,
+    pub allow_refresh_tokens: bool,
+    pub access_token_format: String,
+    pub token_endpoint_auth_method: String,
+    pub tls_client_auth_subject_dn: Option<String>,
+    pub tls_client_certificate_thumbprint: Option<String>,
+    pub jwks: Option<String>,
+    pub jwks_uri: Option<String>,
+    pub encrypted_client_secret: Option<String>,
 }
 
 #[derive(Debug, Queryable, Identifiable, Selectable, Insertable, Associations, PartialEq)]
@@ -32,10 +43,11 @@ pub struct AuthClientRedirectUri {
     pub uri: String,
 }
 
-#[derive(Debug, Queryable, Identifiable, Selectable, Insertable, PartialEq)]
+#[derive(Debug, Queryable, Identifiable, Selectable, Insertable, Associations, PartialEq)]
 #[diesel(primary_key(code_hash))]
 #[diesel(table_name = crate::db::schema::oauth_grants)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(belongs_to(User, foreign_key = owner_id))]
 pub struct OAuthGrant {
     pub code_hash: String,
     pub client_id: Uuid,
@@ -43,6 +55,9 @@ pub struct OAuthGrant {
     pub redirect_uri: String,
     pub scope: String,
     pub until: DateTime<Utc>,
+    pub consumed_at: Option<DateTime<Utc>>,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<String>,
 }
 
 #[derive(Debug, Queryable, Identifiable, Selectable, Insertable, Associations, PartialEq)]
@@ -55,3 +70,146 @@ pub struct OAuthGrantExtension {
     pub name: String,
     pub value: String,
 }
+
+#[derive(Debug, Queryable, Identifiable, Selectable, Insertable, Associations, PartialEq)]
+#[diesel(table_name = crate::db::schema::oauth_grant_replays)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(belongs_to(OAuthGrant, foreign_key = code_hash))]
+pub struct OAuthGrantReplay {
+    pub id: Uuid,
+    pub code_hash: String,
+    pub attempted_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Queryable, Identifiable, Selectable, Insertable, AsChangeset, PartialEq)]
+#[diesel(primary_key(distribution_point))]
+#[diesel(table_name = crate::db::schema::crls)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Crl {
+    pub distribution_point: String,
+    pub issuer: String,
+    pub this_update: DateTime<Utc>,
+    pub next_update: Option<DateTime<Utc>>,
+    pub fetched_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Queryable, Identifiable, Selectable, Insertable, Associations, PartialEq)]
+#[diesel(primary_key(distribution_point, serial_number))]
+#[diesel(table_name = crate::db::schema::crl_entries)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(belongs_to(Crl, foreign_key = distribution_point))]
+pub struct CrlEntry {
+    pub distribution_point: String,
+    pub serial_number: String,
+    pub revocation_time: DateTime<Utc>,
+}
+
+#[derive(Debug, Queryable, Identifiable, Selectable, Insertable, PartialEq)]
+#[diesel(table_name = crate::db::schema::users)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct User {
+    pub id: Uuid,
+    pub given_name: String,
+    pub surname: String,
+    pub country: String,
+    pub certificate_fingerprint: Option<String>,
+    pub first_login_at: DateTime<Utc>,
+    pub last_login_at: DateTime<Utc>,
+    pub disabled: bool,
+}
+
+#[derive(Debug, Queryable, Identifiable, Selectable, Insertable, PartialEq)]
+#[diesel(table_name = crate::db::schema::audit_events)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct AuditEvent {
+    pub id: Uuid,
+    pub event_type: String,
+    pub client_id: Option<Uuid>,
+    pub subject: Option<Uuid>,
+    pub certificate_serial: Option<String>,
+    pub certificate_issuer: Option<String>,
+    pub source_ip: Option<String>,
+    pub occurred_at: DateTime<Utc>,
+    pub sequence: Option<i64>,
+    pub prev_hash: Option<String>,
+    pub hash: Option<String>,
+}
+
+#[derive(Debug, Queryable, Identifiable, Selectable, Insertable, PartialEq)]
+#[diesel(primary_key(sequence))]
+#[diesel(table_name = crate::db::schema::audit_checkpoints)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct AuditCheckpoint {
+    pub sequence: i64,
+    pub hash: String,
+    pub key_id: Option<String>,
+    pub signature: String,
+    pub signed_at: DateTime<Utc>,
+    pub public_jwk: Option<String>,
+}
+
+#[derive(Debug, Queryable, Identifiable, Selectable, Insertable, Associations, PartialEq)]
+#[diesel(primary_key(token_hash))]
+#[diesel(table_name = crate::db::schema::oauth_access_tokens)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(belongs_to(User, foreign_key = subject))]
+pub struct OAuthAccessToken {
+    pub token_hash: String,
+    pub jti: Uuid,
+    pub client_id: Uuid,
+    pub subject: Uuid,
+    pub scope: String,
+    pub redirect_uri: String,
+    pub issued_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub certificate_thumbprint: Option<String>,
+    pub refresh_family_id: Option<Uuid>,
+    pub grant_code_hash: Option<String>,
+}
+
+#[derive(Debug, Queryable, Identifiable, Selectable, Insertable, Associations, PartialEq)]
+#[diesel(primary_key(token_hash))]
+#[diesel(table_name = crate::db::schema::oauth_refresh_tokens)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(belongs_to(User, foreign_key = subject))]
+pub struct OAuthRefreshToken {
+    pub token_hash: String,
+    pub family_id: Uuid,
+    pub client_id: Uuid,
+    pub subject: Uuid,
+    pub scope: String,
+    pub redirect_uri: String,
+    pub certificate_thumbprint: Option<String>,
+    pub issued_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub session_expires_at: DateTime<Utc>,
+    pub consumed_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub auth_time: Option<DateTime<Utc>>,
+    pub grant_code_hash: Option<String>,
+}
+
+#[derive(Debug, Queryable, Identifiable, Selectable, Insertable, Associations, PartialEq)]
+#[diesel(primary_key(client_id, jti))]
+#[diesel(table_name = crate::db::schema::client_assertion_jtis)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(belongs_to(AuthClient, foreign_key = client_id))]
+pub struct ClientAssertionJti {
+    pub client_id: Uuid,
+    pub jti: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Queryable, Identifiable, Selectable, Insertable, PartialEq)]
+#[diesel(primary_key(kid))]
+#[diesel(table_name = crate::db::schema::signing_keys)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct SigningKey {
+    pub kid: String,
+    pub state: String,
+    pub encrypted_private_key: String,
+    pub created_at: DateTime<Utc>,
+    pub activated_at: Option<DateTime<Utc>>,
+    pub retired_at: Option<DateTime<Utc>>,
+}
@@ -1,5 +1,32 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    audit_checkpoints (sequence) {
+        sequence -> Int8,
+        hash -> Text,
+        key_id -> Nullable<Text>,
+        signature -> Text,
+        signed_at -> Timestamptz,
+        public_jwk -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    audit_events (id) {
+        id -> Uuid,
+        event_type -> Text,
+        client_id -> Nullable<Uuid>,
+        subject -> Nullable<Uuid>,
+        certificate_serial -> Nullable<Text>,
+        certificate_issuer -> Nullable<Text>,
+        source_ip -> Nullable<Text>,
+        occurred_at -> Timestamptz,
+        sequence -> Nullable<Int8>,
+        prev_hash -> Nullable<Text>,
+        hash -> Nullable<Text>,
+    }
+}
+
 diesel::table! {
     auth_client_allowed_scopes (id) {
         id -> Uuid,
@@ -22,6 +49,60 @@ diesel::table! {
         client_secret_hash -> Nullable<Text>,
         default_scope -> Text,
         confidential -> Bool,
+        subject_type -> Text,
+        sector_identifier -> Nullable<Text>,
+        require_pkce -> Bool,
+        allow_refresh_tokens -> Bool,
+        access_token_format -> Text,
+        token_endpoint_auth_method -> Text,
+        tls_client_auth_subject_dn -> Nullable<Text>,
+        tls_client_certificate_thumbprint -> Nullable<Text>,
+        jwks -> Nullable<Text>,
+        jwks_uri -> Nullable<Text>,
+        encrypted_client_secret -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    client_assertion_jtis (client_id, jti) {
+        client_id -> Uuid,
+        jti -> Text,
+        expires_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    crl_entries (distribution_point, serial_number) {
+        distribution_point -> Text,
+        serial_number -> Text,
+        revocation_time -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    crls (distribution_point) {
+        distribution_point -> Text,
+        issuer -> Text,
+        this_update -> Timestamptz,
+        next_update -> Nullable<Timestamptz>,
+        fetched_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    oauth_access_tokens (token_hash) {
+        token_hash -> Text,
+        jti -> Uuid,
+        client_id -> Uuid,
+        subject -> Uuid,
+        scope -> Text,
+        redirect_uri -> Text,
+        issued_at -> Timestamptz,
+        expires_at -> Timestamptz,
+        revoked_at -> Nullable<Timestamptz>,
+        certificate_thumbprint -> Nullable<Text>,
+        refresh_family_id -> Nullable<Uuid>,
+        grant_code_hash -> Nullable<Text>,
     }
 }
 
@@ -33,6 +114,14 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    oauth_grant_replays (id) {
+        id -> Uuid,
+        code_hash -> Text,
+        attempted_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     oauth_grants (code_hash) {
         code_hash -> Text,
@@ -41,17 +130,81 @@ diesel::table! {
         redirect_uri -> Text,
         scope -> Text,
         until -> Timestamptz,
+        consumed_at -> Nullable<Timestamptz>,
+        code_challenge -> Nullable<Text>,
+        code_challenge_method -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    oauth_refresh_tokens (token_hash) {
+        token_hash -> Text,
+        family_id -> Uuid,
+        client_id -> Uuid,
+        subject -> Uuid,
+        scope -> Text,
+        redirect_uri -> Text,
+        certificate_thumbprint -> Nullable<Text>,
+        issued_at -> Timestamptz,
+        expires_at -> Timestamptz,
+        session_expires_at -> Timestamptz,
+        consumed_at -> Nullable<Timestamptz>,
+        revoked_at -> Nullable<Timestamptz>,
+        auth_time -> Nullable<Timestamptz>,
+        grant_code_hash -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    signing_keys (kid) {
+        kid -> Text,
+        state -> Text,
+        encrypted_private_key -> Text,
+        created_at -> Timestamptz,
+        activated_at -> Nullable<Timestamptz>,
+        retired_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    users (id) {
+        id -> Uuid,
+        given_name -> Text,
+        surname -> Text,
+        country -> Text,
+        certificate_fingerprint -> Nullable<Text>,
+        first_login_at -> Timestamptz,
+        last_login_at -> Timestamptz,
+        disabled -> Bool,
     }
 }
 
 diesel::joinable!(auth_client_allowed_scopes -> auth_clients (client_id));
 diesel::joinable!(auth_client_redirect_uris -> auth_clients (client_id));
+diesel::joinable!(client_assertion_jtis -> auth_clients (client_id));
+diesel::joinable!(crl_entries -> crls (distribution_point));
+diesel::joinable!(oauth_access_tokens -> auth_clients (client_id));
+diesel::joinable!(oauth_access_tokens -> users (subject));
 diesel::joinable!(oauth_grant_extensions -> oauth_grants (code_hash));
+diesel::joinable!(oauth_grant_replays -> oauth_grants (code_hash));
+diesel::joinable!(oauth_grants -> users (owner_id));
+diesel::joinable!(oauth_refresh_tokens -> auth_clients (client_id));
+diesel::joinable!(oauth_refresh_tokens -> users (subject));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    audit_checkpoints,
+    audit_events,
     auth_client_allowed_scopes,
     auth_client_redirect_uris,
     auth_clients,
+    client_assertion_jtis,
+    crl_entries,
+    crls,
+    oauth_access_tokens,
     oauth_grant_extensions,
+    oauth_grant_replays,
     oauth_grants,
+    oauth_refresh_tokens,
+    signing_keys,
+    users,
 );
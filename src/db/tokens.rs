@@ -0,0 +1,358 @@
+use crate::db;
+use crate::db::audit::{AuditEventType, AuditLog, AuditRecord};
+use crate::db::models::{OAuthAccessToken, OAuthRefreshToken};
+use crate::db::schema::oauth_access_tokens::dsl::oauth_access_tokens;
+use crate::db::schema::oauth_access_tokens::{
+    expires_at, grant_code_hash, jti, refresh_family_id, revoked_at, subject, token_hash,
+};
+use crate::db::schema::oauth_refresh_tokens as oauth_refresh_tokens_columns;
+use crate::db::schema::oauth_refresh_tokens::dsl::oauth_refresh_tokens;
+use crate::error::Error;
+use base64::Engine;
+use base64::prelude::{BASE64_STANDARD, BASE64_URL_SAFE_NO_PAD};
+use chrono::{DateTime, Utc};
+use diesel::dsl::insert_into;
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, QueryResult, SelectableHelper};
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use rand::TryRngCore;
+use rand::rand_core::OsRng;
+use sha2::{Digest, Sha256};
+use std::net::IpAddr;
+use std::sync::Arc;
+use uuid::Uuid;
+
+pub struct TokenRepository {
+    pool: Arc<db::Pool>,
+    source_ip: Option<IpAddr>,
+}
+
+impl TokenRepository {
+    pub fn new(pool: Arc<db::Pool>) -> Self {
+        Self {
+            pool,
+            source_ip: None,
+        }
+    }
+
+    pub fn with_source_ip(mut self, source_ip: IpAddr) -> Self {
+        self.source_ip = Some(source_ip);
+        self
+    }
+
+    pub fn hash_token(token: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(b"access-token-hash-v1");
+        hasher.update(token.as_bytes());
+
+        BASE64_STANDARD.encode(hasher.finalize())
+    }
+
+    pub fn hash_refresh_token(token: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(b"refresh-token-hash-v1");
+        hasher.update(token.as_bytes());
+
+        BASE64_STANDARD.encode(hasher.finalize())
+    }
+
+    pub(crate) fn generate_token() -> Option<String> {
+        let mut bytes = [0u8; 32];
+        OsRng.try_fill_bytes(&mut bytes).ok()?;
+        Some(BASE64_URL_SAFE_NO_PAD.encode(bytes))
+    }
+
+    pub async fn find_active(&self, token: &str) -> Result<Option<OAuthAccessToken>, Error> {
+        let mut conn = self.pool.get().await.map_err(|_| Error::Pool)?;
+        Ok(oauth_access_tokens
+            .filter(token_hash.eq(Self::hash_token(token)))
+            .filter(revoked_at.is_null())
+            .filter(expires_at.gt(Utc::now()))
+            .select(OAuthAccessToken::as_select())
+            .first(&mut conn)
+            .await
+            .optional()?)
+    }
+
+    pub async fn find_refresh(&self, token: &str) -> Result<Option<OAuthRefreshToken>, Error> {
+        let mut conn = self.pool.get().await.map_err(|_| Error::Pool)?;
+        Ok(oauth_refresh_tokens
+            .filter(oauth_refresh_tokens_columns::token_hash.eq(Self::hash_refresh_token(token)))
+            .select(OAuthRefreshToken::as_select())
+            .first(&mut conn)
+            .await
+            .optional()?)
+    }
+
+    pub async fn revoke(
+        &self,
+        token: &str,
+        client_id: &Uuid,
+    ) -> Result<Option<OAuthAccessToken>, Error> {
+        let mut conn = self.pool.get().await.map_err(|_| Error::Pool)?;
+        let access_token = oauth_access_tokens
+            .filter(token_hash.eq(Self::hash_token(token)))
+            .select(OAuthAccessToken::as_select())
+            .first(&mut conn)
+            .await
+            .optional()?;
+
+        match access_token {
+            None => Ok(None),
+            Some(access_token) if access_token.client_id != *client_id => {
+                Err(Error::AccessDenied("token was issued to another client"))
+            }
+            Some(access_token) if access_token.revoked_at.is_some() => Ok(Some(access_token)),
+            Some(access_token) => self.revoke_row(&mut conn, access_token.token_hash).await,
+        }
+    }
+
+    pub async fn revoke_refresh(
+        &self,
+        token: &str,
+        client_id: &Uuid,
+    ) -> Result<Option<OAuthRefreshToken>, Error> {
+        let Some(refresh_token) = self.find_refresh(token).await? else {
+            return Ok(None);
+        };
+        if refresh_token.client_id != *client_id {
+            return Err(Error::AccessDenied("token was issued to another client"));
+        }
+        if refresh_token.revoked_at.is_some() {
+            return Ok(Some(refresh_token));
+        }
+
+        let mut conn = self.pool.get().await.map_err(|_| Error::Pool)?;
+        let audit_record = AuditRecord::new(AuditEventType::TokenRevoked)
+            .with_client_id(refresh_token.client_id)
+            .with_subject(refresh_token.subject)
+            .with_source_ip(self.source_ip);
+        let family = refresh_token.family_id;
+
+        conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            async move {
+                Self::revoke_family(conn, &family, Utc::now()).await?;
+                AuditLog::insert(conn, audit_record).await?;
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await?;
+        Ok(Some(refresh_token))
+    }
+
+    pub async fn revoke_jti(&self, token_id: &Uuid) -> Result<Option<OAuthAccessToken>, Error> {
+        let mut conn = self.pool.get().await.map_err(|_| Error::Pool)?;
+        let access_token = oauth_access_tokens
+            .filter(jti.eq(token_id))
+            .select(OAuthAccessToken::as_select())
+            .first(&mut conn)
+            .await
+            .optional()?;
+
+        match access_token {
+            None => Ok(None),
+            Some(access_token) if access_token.revoked_at.is_some() => Ok(Some(access_token)),
+            Some(access_token) => self.revoke_row(&mut conn, access_token.token_hash).await,
+        }
+    }
+
+    pub async fn revoke_subject(&self, user_id: &Uuid) -> Result<usize, Error> {
+        let mut conn = self.pool.get().await.map_err(|_| Error::Pool)?;
+        let source_ip = self.source_ip;
+        let user_id = *user_id;
+
+        Ok(conn
+            .transaction::<_, diesel::result::Error, _>(|conn| {
+                async move {
+                    let revoked = diesel::update(
+                        oauth_access_tokens
+                            .filter(subject.eq(user_id))
+                            .filter(revoked_at.is_null())
+                            .filter(expires_at.gt(Utc::now())),
+                    )
+                    .set(revoked_at.eq(Utc::now()))
+                    .returning(OAuthAccessToken::as_returning())
+                    .get_results(conn)
+                    .await?;
+
+                    let revoked_refresh = diesel::update(
+                        oauth_refresh_tokens
+                            .filter(oauth_refresh_tokens_columns::subject.eq(user_id))
+                            .filter(oauth_refresh_tokens_columns::revoked_at.is_null())
+                            .filter(oauth_refresh_tokens_columns::expires_at.gt(Utc::now())),
+                    )
+                    .set(oauth_refresh_tokens_columns::revoked_at.eq(Utc::now()))
+                    .returning(oauth_refresh_tokens_columns::client_id)
+                    .get_results::<Uuid>(conn)
+                    .await?;
+
+                    let revoked_clients = revoked
+                        .iter()
+                        .map(|x| x.client_id)
+                        .chain(revoked_refresh.iter().copied());
+                    for revoked_client in revoked_clients {
+                        let audit_record = AuditRecord::new(AuditEventType::TokenRevoked)
+                            .with_client_id(revoked_client)
+                            .with_subject(user_id)
+                            .with_source_ip(source_ip);
+                        AuditLog::insert(conn, audit_record).await?;
+                    }
+                    Ok(revoked.len() + revoked_refresh.len())
+                }
+                .scope_boxed()
+            })
+            .await?)
+    }
+
+    async fn revoke_row(
+        &self,
+        conn: &mut AsyncPgConnection,
+        hashed_token: String,
+    ) -> Result<Option<OAuthAccessToken>, Error> {
+        let source_ip = self.source_ip;
+
+        Ok(conn
+            .transaction::<_, diesel::result::Error, _>(|conn| {
+                async move {
+                    let access_token = diesel::update(
+                        oauth_access_tokens
+                            .filter(token_hash.eq(&hashed_token))
+                            .filter(revoked_at.is_null()),
+                    )
+                    .set(revoked_at.eq(Utc::now()))
+                    .returning(OAuthAccessToken::as_returning())
+                    .get_result(conn)
+                    .await
+                    .optional()?;
+
+                    let Some(access_token) = access_token else {
+                        return oauth_access_tokens
+                            .filter(token_hash.eq(&hashed_token))
+                            .select(OAuthAccessToken::as_select())
+                            .first(conn)
+                            .await
+                            .optional();
+                    };
+
+                    let audit_record = AuditRecord::new(AuditEventType::TokenRevoked)
+                        .with_client_id(access_token.client_id)
+                        .with_subject(access_token.subject)
+                        .with_source_ip(source_ip);
+                    AuditLog::insert(conn, audit_record).await?;
+                    Ok(Some(access_token))
+                }
+                .scope_boxed()
+            })
+            .await?)
+    }
+
+    pub(crate) async fn insert(
+        conn: &mut AsyncPgConnection,
+        access_token: &OAuthAccessToken,
+    ) -> QueryResult<OAuthAccessToken> {
+        insert_into(oauth_access_tokens)
+            .values(access_token)
+            .returning(OAuthAccessToken::as_returning())
+            .get_result(conn)
+            .await
+    }
+
+    pub(crate) async fn insert_refresh(
+        conn: &mut AsyncPgConnection,
+        refresh_token: &OAuthRefreshToken,
+    ) -> QueryResult<OAuthRefreshToken> {
+        insert_into(oauth_refresh_tokens)
+            .values(refresh_token)
+            .returning(OAuthRefreshToken::as_returning())
+            .get_result(conn)
+            .await
+    }
+
+    pub(crate) async fn consume_refresh(
+        conn: &mut AsyncPgConnection,
+        hashed_token: &str,
+        now: DateTime<Utc>,
+    ) -> QueryResult<Option<OAuthRefreshToken>> {
+        diesel::update(
+            oauth_refresh_tokens
+                .filter(oauth_refresh_tokens_columns::token_hash.eq(hashed_token))
+                .filter(oauth_refresh_tokens_columns::consumed_at.is_null())
+                .filter(oauth_refresh_tokens_columns::revoked_at.is_null())
+                .filter(oauth_refresh_tokens_columns::expires_at.gt(now))
+                .filter(oauth_refresh_tokens_columns::session_expires_at.gt(now)),
+        )
+        .set(oauth_refresh_tokens_columns::consumed_at.eq(now))
+        .returning(OAuthRefreshToken::as_returning())
+        .get_result(conn)
+        .await
+        .optional()
+    }
+
+    pub(crate) async fn lock_refresh(
+        conn: &mut AsyncPgConnection,
+        hashed_token: &str,
+    ) -> QueryResult<Option<OAuthRefreshToken>> {
+        oauth_refresh_tokens
+            .filter(oauth_refresh_tokens_columns::token_hash.eq(hashed_token))
+            .select(OAuthRefreshToken::as_select())
+            .for_update()
+            .first(conn)
+            .await
+            .optional()
+    }
+
+    pub(crate) async fn revoke_grant_code(
+        conn: &mut AsyncPgConnection,
+        code_hash: &str,
+        now: DateTime<Utc>,
+    ) -> QueryResult<usize> {
+        let families = oauth_refresh_tokens
+            .filter(oauth_refresh_tokens_columns::grant_code_hash.eq(code_hash))
+            .select(oauth_refresh_tokens_columns::family_id)
+            .distinct()
+            .load::<Uuid>(conn)
+            .await?;
+        let mut revoked = 0;
+        for family in families {
+            revoked += Self::revoke_family(conn, &family, now).await?;
+        }
+
+        revoked += diesel::update(
+            oauth_access_tokens
+                .filter(grant_code_hash.eq(code_hash))
+                .filter(revoked_at.is_null()),
+        )
+        .set(revoked_at.eq(now))
+        .execute(conn)
+        .await?;
+
+        Ok(revoked)
+    }
+
+    pub(crate) async fn revoke_family(
+        conn: &mut AsyncPgConnection,
+        family: &Uuid,
+        now: DateTime<Utc>,
+    ) -> QueryResult<usize> {
+        let refresh_tokens = diesel::update(
+            oauth_refresh_tokens
+                .filter(oauth_refresh_tokens_columns::family_id.eq(family))
+                .filter(oauth_refresh_tokens_columns::revoked_at.is_null()),
+        )
+        .set(oauth_refresh_tokens_columns::revoked_at.eq(now))
+        .execute(conn)
+        .await?;
+
+        let access_tokens = diesel::update(
+            oauth_access_tokens
+                .filter(refresh_family_id.eq(family))
+                .filter(revoked_at.is_null()),
+        )
+        .set(revoked_at.eq(now))
+        .execute(conn)
+        .await?;
+
+        Ok(refresh_tokens + access_tokens)
+    }
+}
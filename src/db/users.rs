@@ -0,0 +1,101 @@
+use crate::db;
+use crate::db::models::{OAuthGrant, User};
+use crate::db::schema::users::dsl::users;
+use crate::db::schema::users::{
+    certificate_fingerprint, country, disabled, given_name, id, last_login_at, surname,
+};
+use crate::error::Error;
+use crate::oauth::client_cert_data::ClientCertData;
+use chrono::{DateTime, Utc};
+use diesel::dsl::insert_into;
+use diesel::upsert::excluded;
+use diesel::{
+    BelongingToDsl, ExpressionMethods, OptionalExtension, QueryDsl, QueryResult, SelectableHelper,
+};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use std::sync::Arc;
+use uuid::Uuid;
+
+pub struct UserRepository {
+    pool: Arc<db::Pool>,
+}
+
+impl UserRepository {
+    pub fn new(pool: Arc<db::Pool>) -> Self {
+        Self { pool }
+    }
+
+    pub async fn find(&self, user_id: &Uuid) -> Result<Option<User>, Error> {
+        let mut conn = self.pool.get().await.map_err(|_| Error::Pool)?;
+        Ok(users
+            .filter(id.eq(user_id))
+            .select(User::as_select())
+            .first(&mut conn)
+            .await
+            .optional()?)
+    }
+
+    pub async fn record_login(
+        &self,
+        user_id: Uuid,
+        client_cert_data: &ClientCertData,
+    ) -> Result<User, Error> {
+        let mut conn = self.pool.get().await.map_err(|_| Error::Pool)?;
+        Ok(Self::upsert_login(&mut conn, user_id, client_cert_data, Utc::now()).await?)
+    }
+
+    pub async fn set_disabled(
+        &self,
+        user_id: &Uuid,
+        is_disabled: bool,
+    ) -> Result<Option<User>, Error> {
+        let mut conn = self.pool.get().await.map_err(|_| Error::Pool)?;
+        Ok(diesel::update(users.filter(id.eq(user_id)))
+            .set(disabled.eq(is_disabled))
+            .returning(User::as_returning())
+            .get_result(&mut conn)
+            .await
+            .optional()?)
+    }
+
+    pub async fn grants(&self, user: &User) -> Result<Vec<OAuthGrant>, Error> {
+        let mut conn = self.pool.get().await.map_err(|_| Error::Pool)?;
+        Ok(OAuthGrant::belonging_to(user)
+            .select(OAuthGrant::as_select())
+            .load(&mut conn)
+            .await?)
+    }
+
+    pub(crate) async fn upsert_login(
+        conn: &mut AsyncPgConnection,
+        user_id: Uuid,
+        client_cert_data: &ClientCertData,
+        now: DateTime<Utc>,
+    ) -> QueryResult<User> {
+        let user = User {
+            id: user_id,
+            given_name: client_cert_data.given_name.clone(),
+            surname: client_cert_data.surname.clone(),
+            country: client_cert_data.country.clone(),
+            certificate_fingerprint: client_cert_data.certificate_fingerprint(),
+            first_login_at: now,
+            last_login_at: now,
+            disabled: false,
+        };
+
+        insert_into(users)
+            .values(&user)
+            .on_conflict(id)
+            .do_update()
+            .set((
+                given_name.eq(excluded(given_name)),
+                surname.eq(excluded(surname)),
+                country.eq(excluded(country)),
+                certificate_fingerprint.eq(excluded(certificate_fingerprint)),
+                last_login_at.eq(excluded(last_login_at)),
+            ))
+            .returning(User::as_returning())
+            .get_result(conn)
+            .await
+    }
+}
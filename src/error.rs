@@ -0,0 +1,96 @@
+use crate::pki::revocation::RevocationError;
+use oxide_auth::primitives::registrar::RegistrarError;
+use std::fmt;
+use tracing::Span;
+
+#[derive(Debug)]
+pub enum Error {
+    Pool,
+    Database(diesel::result::Error),
+    Crypto(&'static str),
+    Certificate(&'static str),
+    Revocation(RevocationError),
+    Expired,
+    Replayed,
+    NotFound(&'static str),
+    AccessDenied(&'static str),
+    Validation(&'static str),
+}
+
+impl Error {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Pool => "pool",
+            Self::Database(_) => "database",
+            Self::Crypto(_) => "crypto",
+            Self::Certificate(_) => "certificate",
+            Self::Revocation(_) => "revocation",
+            Self::Expired => "expired",
+            Self::Replayed => "replayed",
+            Self::NotFound(_) => "not_found",
+            Self::AccessDenied(_) => "access_denied",
+            Self::Validation(_) => "validation",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pool => write!(f, "no database connection available"),
+            Self::Database(err) => write!(f, "database query failed: {err}"),
+            Self::Crypto(reason) => write!(f, "cryptographic operation failed: {reason}"),
+            Self::Certificate(reason) => write!(f, "client certificate rejected: {reason}"),
+            Self::Revocation(err) => write!(f, "client certificate rejected: {err}"),
+            Self::Expired => write!(f, "authorization code has expired"),
+            Self::Replayed => write!(f, "authorization code has already been redeemed"),
+            Self::NotFound(what) => write!(f, "{what} not found"),
+            Self::AccessDenied(reason) => write!(f, "access denied: {reason}"),
+            Self::Validation(reason) => write!(f, "invalid request: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Database(err) => Some(err),
+            Self::Revocation(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<diesel::result::Error> for Error {
+    fn from(err: diesel::result::Error) -> Self {
+        Self::Database(err)
+    }
+}
+
+impl From<RevocationError> for Error {
+    fn from(err: RevocationError) -> Self {
+        Self::Revocation(err)
+    }
+}
+
+impl From<Error> for RegistrarError {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Pool | Error::Database(_) | Error::Crypto(_) => Self::PrimitiveError,
+            _ => Self::Unspecified,
+        }
+    }
+}
+
+pub(crate) fn record_outcome<T>(result: &Result<T, Error>) {
+    match result {
+        Ok(_) => {
+            Span::current().record("outcome", "ok");
+            tracing::info!("completed");
+        }
+        Err(err) => {
+            Span::current().record("outcome", err.kind());
+            tracing::warn!(error = %err, "failed");
+        }
+    }
+}
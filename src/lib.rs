@@ -1,2 +1,5 @@
+pub(crate) mod crypto;
 pub mod db;
+pub mod error;
 pub mod oauth;
+pub mod pki;
@@ -4,3 +4,14 @@ pub mod pg_authorizer;
 pub mod pg_registrar;
 pub mod client_cert_data;
 pub mod dnie_endpoint;
+pub mod subject;
+pub mod pkce_extension;
+pub mod introspection;
+pub mod jwt_access_token;
+pub mod token_response;
+pub mod oidc_extension;
+pub mod client_auth;
+pub mod client_assertion;
+pub mod discovery;
+pub mod key_manager;
+pub mod grant_code_extension;
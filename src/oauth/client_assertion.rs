@@ -0,0 +1,336 @@
+use crate::error::Error;
+use crate::pki::transport::HttpTransport;
+use async_trait::async_trait;
+use base64::Engine;
+use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+use chrono::{DateTime, TimeDelta, Utc};
+use hmac::{Hmac, Mac};
+use openidconnect::JsonWebKey;
+use openidconnect::core::{CoreJsonWebKey, CoreJsonWebKeySet, CoreJwsSigningAlgorithm};
+use serde::Deserialize;
+use sha2::{Sha256, Sha384, Sha512};
+use std::sync::Arc;
+
+pub const CLIENT_ASSERTION_TYPE_JWT_BEARER: &str =
+    "urn:ietf:params:oauth:client-assertion-type:jwt-bearer";
+pub const PRIVATE_KEY_JWT_ALGORITHMS: &[&str] = &[
+    "RS256", "RS384", "RS512", "PS256", "PS384", "PS512", "ES256", "ES384", "EdDSA",
+];
+pub const CLIENT_SECRET_JWT_ALGORITHMS: &[&str] = &["HS256", "HS384", "HS512"];
+pub const MAX_CLIENT_ASSERTION_LIFETIME: TimeDelta = TimeDelta::minutes(5);
+
+#[async_trait]
+pub trait JwksFetcher: Send + Sync {
+    async fn fetch(&self, jwks_uri: &str) -> Result<CoreJsonWebKeySet, Error>;
+}
+
+pub struct HttpJwksFetcher {
+    transport: Arc<dyn HttpTransport>,
+}
+
+impl HttpJwksFetcher {
+    pub fn new(transport: Arc<dyn HttpTransport>) -> Self {
+        Self { transport }
+    }
+}
+
+#[async_trait]
+impl JwksFetcher for HttpJwksFetcher {
+    async fn fetch(&self, jwks_uri: &str) -> Result<CoreJsonWebKeySet, Error> {
+        let body = self
+            .transport
+            .get(jwks_uri)
+            .await
+            .map_err(|_| Error::NotFound("client JWKS"))?;
+        serde_json::from_slice(&body).map_err(|_| Error::Validation("client JWKS is malformed"))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum Audience {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+impl Audience {
+    pub fn contains(&self, audience: &str) -> bool {
+        match self {
+            Self::Single(x) => x == audience,
+            Self::Multiple(x) => x.iter().any(|x| x == audience),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct ClientAssertionHeader {
+    pub alg: String,
+    pub kid: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct ClientAssertionClaims {
+    pub iss: String,
+    pub sub: String,
+    pub aud: Audience,
+    pub exp: i64,
+    pub iat: Option<i64>,
+    pub nbf: Option<i64>,
+    pub jti: String,
+}
+
+pub struct ClientAssertion {
+    pub header: ClientAssertionHeader,
+    pub claims: ClientAssertionClaims,
+    signing_input: String,
+    signature: Vec<u8>,
+}
+
+impl ClientAssertion {
+    pub fn parse(assertion: &str) -> Result<Self, Error> {
+        let Some((signing_input, signature)) = assertion.rsplit_once('.') else {
+            return Err(Error::Validation("client assertion is not a compact JWS"));
+        };
+        let Some((header, claims)) = signing_input.split_once('.') else {
+            return Err(Error::Validation("client assertion is not a compact JWS"));
+        };
+
+        Ok(Self {
+            header: decode_segment(header)?,
+            claims: decode_segment(claims)?,
+            signing_input: signing_input.to_owned(),
+            signature: BASE64_URL_SAFE_NO_PAD
+                .decode(signature)
+                .map_err(|_| Error::Validation("client assertion signature is not base64url"))?,
+        })
+    }
+
+    pub fn expires_at(&self) -> Result<DateTime<Utc>, Error> {
+        DateTime::from_timestamp(self.claims.exp, 0)
+            .ok_or(Error::Validation("client assertion expiry is out of range"))
+    }
+
+    pub fn verify_signature(&self, keys: &[CoreJsonWebKey]) -> Result<(), Error> {
+        if !PRIVATE_KEY_JWT_ALGORITHMS.contains(&self.header.alg.as_str()) {
+            return Err(Error::Validation(
+                "client assertion must use an asymmetric algorithm",
+            ));
+        }
+        let algorithm: CoreJwsSigningAlgorithm =
+            serde_json::from_value(serde_json::Value::String(self.header.alg.clone()))
+                .map_err(|_| Error::Validation("client assertion algorithm is not supported"))?;
+
+        let verified = keys
+            .iter()
+            .filter(|x| match (&self.header.kid, x.key_id()) {
+                (Some(kid), Some(key_id)) => kid == key_id.as_str(),
+                _ => true,
+            })
+            .any(|x| {
+                x.verify_signature(&algorithm, self.signing_input.as_bytes(), &self.signature)
+                    .is_ok()
+            });
+        if !verified {
+            return Err(Error::AccessDenied("client assertion signature is invalid"));
+        }
+        Ok(())
+    }
+
+    pub fn verify_hmac(&self, secret: &[u8]) -> Result<(), Error> {
+        let verified = match self.header.alg.as_str() {
+            "HS256" => Hmac::<Sha256>::new_from_slice(secret).map(|x| {
+                x.chain_update(&self.signing_input)
+                    .verify_slice(&self.signature)
+            }),
+            "HS384" => Hmac::<Sha384>::new_from_slice(secret).map(|x| {
+                x.chain_update(&self.signing_input)
+                    .verify_slice(&self.signature)
+            }),
+            "HS512" => Hmac::<Sha512>::new_from_slice(secret).map(|x| {
+                x.chain_update(&self.signing_input)
+                    .verify_slice(&self.signature)
+            }),
+            _ => {
+                return Err(Error::Validation(
+                    "client assertion must use an HMAC algorithm",
+                ));
+            }
+        };
+        if !matches!(verified, Ok(Ok(()))) {
+            return Err(Error::AccessDenied("client assertion signature is invalid"));
+        }
+        Ok(())
+    }
+
+    pub fn validate_claims(
+        &self,
+        client_id: &str,
+        token_endpoint: &str,
+        leeway: TimeDelta,
+    ) -> Result<(), Error> {
+        if self.claims.iss != client_id || self.claims.sub != client_id {
+            return Err(Error::AccessDenied(
+                "client assertion was not issued by the client",
+            ));
+        }
+        if !self.claims.aud.contains(token_endpoint) {
+            return Err(Error::AccessDenied(
+                "client assertion is meant for another audience",
+            ));
+        }
+        if self.claims.jti.is_empty() {
+            return Err(Error::Validation("client assertion has no jti"));
+        }
+
+        let now = Utc::now();
+        if self.claims.exp <= (now - leeway).timestamp() {
+            return Err(Error::AccessDenied("client assertion has expired"));
+        }
+        if self.claims.exp > (now + MAX_CLIENT_ASSERTION_LIFETIME + leeway).timestamp() {
+            return Err(Error::AccessDenied(
+                "client assertion expires too far in the future",
+            ));
+        }
+        if self
+            .claims
+            .nbf
+            .is_some_and(|x| x > (now + leeway).timestamp())
+        {
+            return Err(Error::AccessDenied("client assertion is not yet valid"));
+        }
+        if self
+            .claims
+            .iat
+            .is_some_and(|x| x > (now + leeway).timestamp())
+        {
+            return Err(Error::AccessDenied(
+                "client assertion was issued in the future",
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn decode_segment<T: for<'de> Deserialize<'de>>(segment: &str) -> Result<T, Error> {
+    let segment = BASE64_URL_SAFE_NO_PAD
+        .decode(segment)
+        .map_err(|_| Error::Validation("client assertion segment is not base64url"))?;
+    serde_json::from_slice(&segment).map_err(|_| Error::Validation("client assertion is malformed"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{Value, json};
+
+    const CLIENT_ID: &str = "5b0c5d43-7d55-4c43-9a37-0d5a1c3a2f10";
+    const TOKEN_ENDPOINT: &str = "https://idp.example/token";
+    const SECRET: &[u8] = b"client-secret";
+
+    fn claims(lifetime: TimeDelta) -> Value {
+        json!({
+            "iss": CLIENT_ID,
+            "sub": CLIENT_ID,
+            "aud": TOKEN_ENDPOINT,
+            "jti": "jti",
+            "exp": (Utc::now() + lifetime).timestamp(),
+        })
+    }
+
+    fn hs256(claims: &Value, secret: &[u8]) -> String {
+        let signing_input = format!(
+            "{}.{}",
+            BASE64_URL_SAFE_NO_PAD.encode(json!({ "alg": "HS256" }).to_string()),
+            BASE64_URL_SAFE_NO_PAD.encode(claims.to_string())
+        );
+        let signature = Hmac::<Sha256>::new_from_slice(secret)
+            .unwrap()
+            .chain_update(&signing_input)
+            .finalize()
+            .into_bytes();
+        format!(
+            "{signing_input}.{}",
+            BASE64_URL_SAFE_NO_PAD.encode(signature)
+        )
+    }
+
+    fn validate(claims: &Value) -> Result<(), Error> {
+        ClientAssertion::parse(&hs256(claims, SECRET))?.validate_claims(
+            CLIENT_ID,
+            TOKEN_ENDPOINT,
+            TimeDelta::seconds(30),
+        )
+    }
+
+    #[test]
+    fn verifies_hmac_with_client_secret() {
+        let assertion =
+            ClientAssertion::parse(&hs256(&claims(TimeDelta::minutes(1)), SECRET)).unwrap();
+        assert!(assertion.verify_hmac(SECRET).is_ok());
+        assert!(matches!(
+            assertion.verify_hmac(b"other-secret"),
+            Err(Error::AccessDenied(_))
+        ));
+        assert!(matches!(
+            assertion.verify_signature(&[]),
+            Err(Error::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_hmac_for_asymmetric_algorithm() {
+        let assertion = hs256(&claims(TimeDelta::minutes(1)), SECRET);
+        let (_, rest) = assertion.split_once('.').unwrap();
+        let header = BASE64_URL_SAFE_NO_PAD.encode(json!({ "alg": "RS256" }).to_string());
+        let assertion = ClientAssertion::parse(&format!("{header}.{rest}")).unwrap();
+        assert!(matches!(
+            assertion.verify_hmac(SECRET),
+            Err(Error::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn accepts_valid_claims() {
+        assert!(validate(&claims(TimeDelta::minutes(1))).is_ok());
+
+        let mut claims = claims(TimeDelta::minutes(1));
+        claims["aud"] = json!(["https://other.example", TOKEN_ENDPOINT]);
+        assert!(validate(&claims).is_ok());
+    }
+
+    #[test]
+    fn rejects_claims_for_another_client_or_audience() {
+        let mut claims = claims(TimeDelta::minutes(1));
+        claims["iss"] = json!("another-client");
+        assert!(matches!(validate(&claims), Err(Error::AccessDenied(_))));
+
+        claims["iss"] = json!(CLIENT_ID);
+        claims["aud"] = json!("https://other.example/token");
+        assert!(matches!(validate(&claims), Err(Error::AccessDenied(_))));
+    }
+
+    #[test]
+    fn rejects_claims_without_jti() {
+        let mut claims = claims(TimeDelta::minutes(1));
+        claims["jti"] = json!("");
+        assert!(matches!(validate(&claims), Err(Error::Validation(_))));
+    }
+
+    #[test]
+    fn rejects_claims_outside_their_lifetime() {
+        assert!(matches!(
+            validate(&claims(TimeDelta::minutes(-1))),
+            Err(Error::AccessDenied(_))
+        ));
+        assert!(matches!(
+            validate(&claims(
+                MAX_CLIENT_ASSERTION_LIFETIME + TimeDelta::minutes(1)
+            )),
+            Err(Error::AccessDenied(_))
+        ));
+
+        let mut claims = claims(TimeDelta::minutes(1));
+        claims["nbf"] = json!((Utc::now() + TimeDelta::minutes(1)).timestamp());
+        assert!(matches!(validate(&claims), Err(Error::AccessDenied(_))));
+    }
+}
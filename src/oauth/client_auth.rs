@@ -0,0 +1,253 @@
+use crate::error::Error;
+use base64::Engine;
+use base64::prelude::{BASE64_STANDARD, BASE64_URL_SAFE_NO_PAD};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::str::FromStr;
+use subtle::ConstantTimeEq;
+use x509_cert::Certificate;
+use x509_cert::attr::AttributeTypeAndValue;
+use x509_cert::der::Tag;
+use x509_cert::der::Tagged;
+use x509_cert::der::asn1::Any;
+use x509_cert::name::Name;
+
+pub const TOKEN_ENDPOINT_AUTH_METHOD_NONE: &str = "none";
+pub const TOKEN_ENDPOINT_AUTH_METHOD_CLIENT_SECRET_BASIC: &str = "client_secret_basic";
+pub const TOKEN_ENDPOINT_AUTH_METHOD_CLIENT_SECRET_POST: &str = "client_secret_post";
+pub const TOKEN_ENDPOINT_AUTH_METHOD_CLIENT_SECRET_JWT: &str = "client_secret_jwt";
+pub const TOKEN_ENDPOINT_AUTH_METHOD_PRIVATE_KEY_JWT: &str = "private_key_jwt";
+pub const TOKEN_ENDPOINT_AUTH_METHOD_TLS_CLIENT_AUTH: &str = "tls_client_auth";
+pub const TOKEN_ENDPOINT_AUTH_METHOD_SELF_SIGNED_TLS_CLIENT_AUTH: &str =
+    "self_signed_tls_client_auth";
+pub const TOKEN_ENDPOINT_AUTH_METHODS: &[&str] = &[
+    TOKEN_ENDPOINT_AUTH_METHOD_NONE,
+    TOKEN_ENDPOINT_AUTH_METHOD_CLIENT_SECRET_BASIC,
+    TOKEN_ENDPOINT_AUTH_METHOD_CLIENT_SECRET_POST,
+    TOKEN_ENDPOINT_AUTH_METHOD_CLIENT_SECRET_JWT,
+    TOKEN_ENDPOINT_AUTH_METHOD_PRIVATE_KEY_JWT,
+    TOKEN_ENDPOINT_AUTH_METHOD_TLS_CLIENT_AUTH,
+    TOKEN_ENDPOINT_AUTH_METHOD_SELF_SIGNED_TLS_CLIENT_AUTH,
+];
+
+#[derive(Deserialize)]
+struct JsonWebKeySet {
+    keys: Vec<JsonWebKeyCertificates>,
+}
+
+#[derive(Deserialize)]
+struct JsonWebKeyCertificates {
+    #[serde(default)]
+    x5c: Vec<String>,
+    #[serde(rename = "x5t#S256")]
+    x5t_s256: Option<String>,
+}
+
+pub fn certificate_thumbprint(certificate: &[u8]) -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(certificate))
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ClientCredentials {
+    pub passphrase: Option<Vec<u8>>,
+    pub certificate: Option<Vec<u8>>,
+    pub intermediates: Vec<Vec<u8>>,
+    pub client_assertion_type: Option<String>,
+    pub client_assertion: Option<String>,
+}
+
+impl ClientCredentials {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_passphrase(mut self, passphrase: Vec<u8>) -> Self {
+        self.passphrase = Some(passphrase);
+        self
+    }
+
+    pub fn with_certificate(mut self, certificate: Vec<u8>, intermediates: Vec<Vec<u8>>) -> Self {
+        self.certificate = Some(certificate);
+        self.intermediates = intermediates;
+        self
+    }
+
+    pub fn with_client_assertion(
+        mut self,
+        client_assertion_type: String,
+        client_assertion: String,
+    ) -> Self {
+        self.client_assertion_type = Some(client_assertion_type);
+        self.client_assertion = Some(client_assertion);
+        self
+    }
+}
+
+pub fn verify_subject_dn(certificate: &Certificate, subject_dn: &str) -> Result<(), Error> {
+    let subject_dn = Name::from_str(subject_dn)
+        .map_err(|_| Error::Validation("registered subject DN is malformed"))?;
+
+    if !names_match(&certificate.tbs_certificate.subject, &subject_dn) {
+        return Err(Error::AccessDenied(
+            "client certificate subject does not match the registered DN",
+        ));
+    }
+    Ok(())
+}
+
+fn names_match(presented: &Name, registered: &Name) -> bool {
+    presented.0.len() == registered.0.len()
+        && presented
+            .0
+            .iter()
+            .zip(registered.0.iter())
+            .all(|(presented, registered)| {
+                presented.0.len() == registered.0.len()
+                    && presented.0.iter().all(|presented| {
+                        registered
+                            .0
+                            .iter()
+                            .any(|registered| attributes_match(presented, registered))
+                    })
+            })
+}
+
+fn attributes_match(presented: &AttributeTypeAndValue, registered: &AttributeTypeAndValue) -> bool {
+    if presented.oid != registered.oid {
+        return false;
+    }
+    match (
+        attribute_string(&presented.value),
+        attribute_string(&registered.value),
+    ) {
+        (Some(presented), Some(registered)) => presented == registered,
+        _ => presented.value == registered.value,
+    }
+}
+
+fn attribute_string(value: &Any) -> Option<String> {
+    match value.tag() {
+        Tag::Utf8String | Tag::PrintableString | Tag::Ia5String | Tag::TeletexString => {
+            let value = std::str::from_utf8(value.value()).ok()?;
+            Some(
+                value
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(" ")
+                    .to_lowercase(),
+            )
+        }
+        _ => None,
+    }
+}
+
+pub fn verify_self_signed(
+    certificate: &[u8],
+    thumbprint: Option<&str>,
+    jwks: Option<&str>,
+) -> Result<(), Error> {
+    let presented = certificate_thumbprint(certificate);
+    let mut registered = thumbprint
+        .map(|x| x.to_owned())
+        .into_iter()
+        .collect::<Vec<_>>();
+
+    if let Some(jwks) = jwks {
+        let jwks: JsonWebKeySet = serde_json::from_str(jwks)
+            .map_err(|_| Error::Validation("registered JWKS is malformed"))?;
+        for key in jwks.keys {
+            registered.extend(key.x5t_s256);
+            if let Some(x5c) = key.x5c.first() {
+                let der = BASE64_STANDARD
+                    .decode(x5c)
+                    .map_err(|_| Error::Validation("registered JWKS certificate is malformed"))?;
+                registered.push(certificate_thumbprint(&der));
+            }
+        }
+    }
+
+    if !registered
+        .iter()
+        .any(|x| bool::from(x.as_bytes().ct_eq(presented.as_bytes())))
+    {
+        return Err(Error::AccessDenied(
+            "client certificate is not registered for this client",
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use x509_cert::der::{DecodePem, Encode};
+
+    const LEAF: &[u8] = include_bytes!("../../testdata/pki/leaf.pem");
+    const INTERMEDIATE: &[u8] = include_bytes!("../../testdata/pki/int.pem");
+    const LEAF_DN: &str = "CN=ESPA\\C3\\91OL ESPA\\C3\\91OL\\, JUAN (AUTENTICACI\\C3\\93N),\
+        givenName=JUAN,SN=ESPA\\C3\\91OL ESPA\\C3\\91OL,serialNumber=IDCES-12345678Z,C=ES";
+
+    fn der(pem: &[u8]) -> Vec<u8> {
+        Certificate::from_pem(pem).unwrap().to_der().unwrap()
+    }
+
+    #[test]
+    fn accepts_registered_thumbprint() {
+        let thumbprint = certificate_thumbprint(&der(LEAF));
+        assert!(verify_self_signed(&der(LEAF), Some(&thumbprint), None).is_ok());
+        assert!(matches!(
+            verify_self_signed(&der(INTERMEDIATE), Some(&thumbprint), None),
+            Err(Error::AccessDenied(_))
+        ));
+    }
+
+    #[test]
+    fn accepts_certificate_registered_in_jwks() {
+        let jwks = serde_json::json!({
+            "keys": [
+                { "kty": "RSA", "x5c": [BASE64_STANDARD.encode(der(INTERMEDIATE))] },
+                { "kty": "RSA", "x5t#S256": certificate_thumbprint(&der(LEAF)) },
+            ]
+        })
+        .to_string();
+        assert!(verify_self_signed(&der(LEAF), None, Some(&jwks)).is_ok());
+        assert!(verify_self_signed(&der(INTERMEDIATE), None, Some(&jwks)).is_ok());
+    }
+
+    #[test]
+    fn rejects_unregistered_or_malformed_registration() {
+        assert!(matches!(
+            verify_self_signed(&der(LEAF), None, None),
+            Err(Error::AccessDenied(_))
+        ));
+        assert!(matches!(
+            verify_self_signed(&der(LEAF), None, Some("{")),
+            Err(Error::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn matches_subject_dn_ignoring_case_and_spacing() {
+        let certificate = Certificate::from_pem(LEAF).unwrap();
+        assert!(verify_subject_dn(&certificate, LEAF_DN).is_ok());
+        assert!(verify_subject_dn(&certificate, &LEAF_DN.to_lowercase()).is_ok());
+        assert!(verify_subject_dn(&certificate, &LEAF_DN.replace("JUAN,", "JUAN  ,")).is_ok());
+    }
+
+    #[test]
+    fn rejects_different_subject_dn() {
+        let certificate = Certificate::from_pem(LEAF).unwrap();
+        assert!(matches!(
+            verify_subject_dn(&certificate, &LEAF_DN.replace("12345678Z", "00000000T")),
+            Err(Error::AccessDenied(_))
+        ));
+        assert!(matches!(
+            verify_subject_dn(&certificate, &LEAF_DN.replace(",C=ES", "")),
+            Err(Error::AccessDenied(_))
+        ));
+        assert!(matches!(
+            verify_subject_dn(&certificate, "CN=AC DNIE 004,OU=DNIE,C=ES"),
+            Err(Error::AccessDenied(_))
+        ));
+    }
+}
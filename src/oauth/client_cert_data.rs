@@ -1,4 +1,16 @@
+use base64::Engine;
+use base64::prelude::{BASE64_STANDARD, BASE64_URL_SAFE_NO_PAD};
+use const_oid::ObjectIdentifier;
+use const_oid::db::rfc4519::{COUNTRY_NAME, GIVEN_NAME, SERIAL_NUMBER, SURNAME};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use x509_cert::Certificate;
+use x509_cert::der::{Decode, DecodePem, Encode};
+use x509_cert::ext::pkix::name::DirectoryString;
+
+const DNIE_SERIAL_NUMBER_PREFIX: &str = "IDCES-";
+const NIF_CONTROL_LETTERS: &[u8; 23] = b"TRWAGMYFPDXBNJZSQVHLCKE";
 
 #[derive(Serialize, Deserialize)]
 pub struct ClientCertData {
@@ -6,4 +18,191 @@ pub struct ClientCertData {
     pub surname: String,
     pub serial_number: String,
     pub country: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub certificate: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum ClientCertDataError {
+    InvalidCertificate(x509_cert::der::Error),
+    MissingAttribute(&'static str),
+    InvalidAttribute(&'static str),
+    InvalidSerialNumber(String),
+}
+
+impl fmt::Display for ClientCertDataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidCertificate(err) => write!(f, "invalid certificate encoding: {err}"),
+            Self::MissingAttribute(name) => write!(f, "subject attribute {name} is missing"),
+            Self::InvalidAttribute(name) => write!(f, "subject attribute {name} is malformed"),
+            Self::InvalidSerialNumber(value) => {
+                write!(f, "subject serial number {value:?} is not a DNIe NIF")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ClientCertDataError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::InvalidCertificate(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl ClientCertData {
+    pub fn from_der(der: &[u8]) -> Result<Self, ClientCertDataError> {
+        let certificate =
+            Certificate::from_der(der).map_err(ClientCertDataError::InvalidCertificate)?;
+        Self::from_certificate(&certificate)
+    }
+
+    pub fn from_pem(pem: &[u8]) -> Result<Self, ClientCertDataError> {
+        let certificate =
+            Certificate::from_pem(pem).map_err(ClientCertDataError::InvalidCertificate)?;
+        Self::from_certificate(&certificate)
+    }
+
+    pub fn from_certificate(certificate: &Certificate) -> Result<Self, ClientCertDataError> {
+        let given_name = Self::subject_attribute(certificate, GIVEN_NAME, "givenName")?;
+        let surname = Self::subject_attribute(certificate, SURNAME, "surname")?;
+        let serial_number = Self::subject_attribute(certificate, SERIAL_NUMBER, "serialNumber")?;
+        let country = Self::subject_attribute(certificate, COUNTRY_NAME, "countryName")?;
+
+        if !Self::is_valid_serial_number(&serial_number) {
+            return Err(ClientCertDataError::InvalidSerialNumber(serial_number));
+        }
+
+        if country.len() != 2 || !country.bytes().all(|x| x.is_ascii_uppercase()) {
+            return Err(ClientCertDataError::InvalidAttribute("countryName"));
+        }
+
+        Ok(Self {
+            given_name,
+            surname,
+            serial_number,
+            country,
+            certificate: Some(
+                BASE64_STANDARD.encode(
+                    certificate
+                        .to_der()
+                        .map_err(ClientCertDataError::InvalidCertificate)?,
+                ),
+            ),
+        })
+    }
+
+    pub fn decode_certificate(&self) -> Result<Option<Certificate>, ClientCertDataError> {
+        let Some(certificate) = &self.certificate else {
+            return Ok(None);
+        };
+
+        let der = BASE64_STANDARD
+            .decode(certificate)
+            .map_err(|_| ClientCertDataError::InvalidAttribute("certificate"))?;
+        Certificate::from_der(&der)
+            .map(Some)
+            .map_err(ClientCertDataError::InvalidCertificate)
+    }
+
+    pub fn certificate_fingerprint(&self) -> Option<String> {
+        let der = BASE64_STANDARD.decode(self.certificate.as_ref()?).ok()?;
+        Some(BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(der)))
+    }
+
+    pub fn nif(&self) -> &str {
+        self.serial_number
+            .strip_prefix(DNIE_SERIAL_NUMBER_PREFIX)
+            .unwrap_or(&self.serial_number)
+    }
+
+    fn subject_attribute(
+        certificate: &Certificate,
+        oid: ObjectIdentifier,
+        name: &'static str,
+    ) -> Result<String, ClientCertDataError> {
+        let attribute = certificate
+            .tbs_certificate
+            .subject
+            .0
+            .iter()
+            .flat_map(|rdn| rdn.0.iter())
+            .find(|atv| atv.oid == oid)
+            .ok_or(ClientCertDataError::MissingAttribute(name))?;
+
+        let value = match attribute
+            .value
+            .to_der()
+            .and_then(|der| DirectoryString::from_der(&der))
+            .map_err(|_| ClientCertDataError::InvalidAttribute(name))?
+        {
+            DirectoryString::PrintableString(value) => value.as_str().to_owned(),
+            DirectoryString::TeletexString(value) => value.as_str().to_owned(),
+            DirectoryString::Utf8String(value) => value,
+        };
+
+        let value = value.trim();
+        if value.is_empty() {
+            return Err(ClientCertDataError::InvalidAttribute(name));
+        }
+
+        Ok(value.to_owned())
+    }
+
+    fn is_valid_serial_number(serial_number: &str) -> bool {
+        let Some(nif) = serial_number.strip_prefix(DNIE_SERIAL_NUMBER_PREFIX) else {
+            return false;
+        };
+        if nif.len() != 9 || !nif.is_ascii() {
+            return false;
+        }
+
+        let (digits, letter) = nif.split_at(8);
+        if !digits.bytes().all(|x| x.is_ascii_digit()) {
+            return false;
+        }
+        let Ok(number) = digits.parse::<u32>() else {
+            return false;
+        };
+
+        letter.as_bytes()[0] == NIF_CONTROL_LETTERS[(number % 23) as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_nif_with_matching_check_letter() {
+        assert!(ClientCertData::is_valid_serial_number("IDCES-12345678Z"));
+        assert!(ClientCertData::is_valid_serial_number("IDCES-00000000T"));
+        assert!(ClientCertData::is_valid_serial_number("IDCES-99999999R"));
+    }
+
+    #[test]
+    fn rejects_nif_with_wrong_check_letter() {
+        assert!(!ClientCertData::is_valid_serial_number("IDCES-12345678A"));
+        assert!(!ClientCertData::is_valid_serial_number("IDCES-12345678z"));
+    }
+
+    #[test]
+    fn rejects_malformed_serial_numbers() {
+        assert!(!ClientCertData::is_valid_serial_number("12345678Z"));
+        assert!(!ClientCertData::is_valid_serial_number("IDCES-1234567Z"));
+        assert!(!ClientCertData::is_valid_serial_number("IDCES-123456789Z"));
+        assert!(!ClientCertData::is_valid_serial_number("IDCES-1234567XZ"));
+        assert!(!ClientCertData::is_valid_serial_number("IDCES-+1234567Z"));
+        assert!(!ClientCertData::is_valid_serial_number("IDCES-123456ÑZ"));
+    }
+
+    #[test]
+    fn extracts_nif_from_certificate() {
+        let client_cert_data =
+            ClientCertData::from_pem(include_bytes!("../../testdata/pki/leaf.pem")).unwrap();
+        assert_eq!(client_cert_data.serial_number, "IDCES-12345678Z");
+        assert_eq!(client_cert_data.nif(), "12345678Z");
+    }
 }
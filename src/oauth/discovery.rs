@@ -0,0 +1,228 @@
+use crate::oauth::client_assertion::{CLIENT_SECRET_JWT_ALGORITHMS, PRIVATE_KEY_JWT_ALGORITHMS};
+use crate::oauth::client_auth::{
+    TOKEN_ENDPOINT_AUTH_METHOD_CLIENT_SECRET_BASIC, TOKEN_ENDPOINT_AUTH_METHOD_CLIENT_SECRET_JWT,
+    TOKEN_ENDPOINT_AUTH_METHOD_PRIVATE_KEY_JWT,
+};
+use crate::oauth::pg_issuer::PgIssuer;
+use crate::oauth::pg_registrar::PgRegistrar;
+use crate::oauth::pkce_extension::{CODE_CHALLENGE_METHOD_PLAIN, CODE_CHALLENGE_METHOD_S256};
+use crate::oauth::subject::{SUBJECT_TYPE_PAIRWISE, SUBJECT_TYPE_PUBLIC};
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+use std::sync::Arc;
+
+pub const OPENID_CONFIGURATION_PATH: &str = "/.well-known/openid-configuration";
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct DiscoveryDocument {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub introspection_endpoint: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub introspection_endpoint_auth_methods_supported: Vec<String>,
+    pub scopes_supported: Vec<String>,
+    pub response_types_supported: Vec<String>,
+    pub response_modes_supported: Vec<String>,
+    pub grant_types_supported: Vec<String>,
+    pub subject_types_supported: Vec<String>,
+    pub id_token_signing_alg_values_supported: Vec<String>,
+    pub token_endpoint_auth_methods_supported: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub token_endpoint_auth_signing_alg_values_supported: Vec<String>,
+    pub code_challenge_methods_supported: Vec<String>,
+    pub claims_supported: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub acr_values_supported: Vec<String>,
+    pub tls_client_certificate_bound_access_tokens: bool,
+}
+
+impl DiscoveryDocument {
+    pub fn new(
+        issuer: &PgIssuer,
+        registrar: &PgRegistrar,
+        authorization_endpoint: String,
+        token_endpoint: String,
+        jwks_uri: String,
+    ) -> Self {
+        let auth_methods = registrar.token_endpoint_auth_methods();
+        let mut auth_signing_algs = vec![];
+        if auth_methods.contains(&TOKEN_ENDPOINT_AUTH_METHOD_PRIVATE_KEY_JWT) {
+            auth_signing_algs.extend_from_slice(PRIVATE_KEY_JWT_ALGORITHMS);
+        }
+        if auth_methods.contains(&TOKEN_ENDPOINT_AUTH_METHOD_CLIENT_SECRET_JWT) {
+            auth_signing_algs.extend_from_slice(CLIENT_SECRET_JWT_ALGORITHMS);
+        }
+
+        Self {
+            issuer: issuer.issuer().to_owned(),
+            authorization_endpoint,
+            token_endpoint,
+            jwks_uri,
+            introspection_endpoint: None,
+            introspection_endpoint_auth_methods_supported: vec![],
+            scopes_supported: to_strings(&["openid", "profile"]),
+            response_types_supported: to_strings(&["code"]),
+            response_modes_supported: to_strings(&["query"]),
+            grant_types_supported: to_strings(&["authorization_code", "refresh_token"]),
+            subject_types_supported: to_strings(&[SUBJECT_TYPE_PUBLIC, SUBJECT_TYPE_PAIRWISE]),
+            id_token_signing_alg_values_supported: to_strings(&["RS256"]),
+            token_endpoint_auth_methods_supported: to_strings(&auth_methods),
+            token_endpoint_auth_signing_alg_values_supported: to_strings(&auth_signing_algs),
+            code_challenge_methods_supported: to_strings(&[CODE_CHALLENGE_METHOD_S256]),
+            claims_supported: to_strings(&[
+                "iss",
+                "sub",
+                "aud",
+                "exp",
+                "iat",
+                "auth_time",
+                "nonce",
+                "at_hash",
+                "given_name",
+                "family_name",
+            ]),
+            acr_values_supported: issuer.acr().map(|x| vec![x.to_owned()]).unwrap_or_default(),
+            // Tokens are bound to the user's DNIe certificate rather than to the client's
+            // TLS certificate at the token endpoint, which is not what RFC 8705 describes.
+            tls_client_certificate_bound_access_tokens: false,
+        }
+    }
+
+    pub fn with_introspection_endpoint(mut self, introspection_endpoint: String) -> Self {
+        self.introspection_endpoint = Some(introspection_endpoint);
+        self.introspection_endpoint_auth_methods_supported =
+            to_strings(&[TOKEN_ENDPOINT_AUTH_METHOD_CLIENT_SECRET_BASIC]);
+        self
+    }
+
+    pub fn with_scopes(mut self, scopes: Vec<String>) -> Self {
+        self.scopes_supported = scopes;
+        self
+    }
+
+    pub fn with_allow_plain_pkce(mut self, allow_plain: bool) -> Self {
+        self.code_challenge_methods_supported = if allow_plain {
+            to_strings(&[CODE_CHALLENGE_METHOD_S256, CODE_CHALLENGE_METHOD_PLAIN])
+        } else {
+            to_strings(&[CODE_CHALLENGE_METHOD_S256])
+        };
+        self
+    }
+}
+
+pub async fn openid_configuration(State(document): State<Arc<DiscoveryDocument>>) -> Response {
+    match serde_json::to_string(&*document) {
+        Ok(json) => ([(CONTENT_TYPE, "application/json")], json).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+fn to_strings(values: &[&str]) -> Vec<String> {
+    values.iter().map(|x| (*x).to_owned()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+    use crate::oauth::key_manager::KeyManager;
+    use crate::oauth::subject::SubjectGenerator;
+    use diesel_async::AsyncPgConnection;
+    use diesel_async::pooled_connection::AsyncDieselConnectionManager;
+    use serde_json::{Value, json};
+
+    fn pool() -> Arc<db::Pool> {
+        let manager =
+            AsyncDieselConnectionManager::<AsyncPgConnection>::new("postgres://localhost/unused");
+        Arc::new(db::Pool::builder().build_unchecked(manager))
+    }
+
+    fn document(registrar: &PgRegistrar) -> DiscoveryDocument {
+        let pool = pool();
+        let issuer = PgIssuer::new(
+            Arc::new(KeyManager::new(pool.clone(), [0u8; 32])),
+            pool,
+            "https://idp.example".to_owned(),
+            Arc::new(SubjectGenerator::new(b"secret".to_vec())),
+        );
+        DiscoveryDocument::new(
+            &issuer,
+            registrar,
+            "https://idp.example/authorize".to_owned(),
+            "https://idp.example/token".to_owned(),
+            "https://idp.example/jwks".to_owned(),
+        )
+    }
+
+    fn to_json(document: &DiscoveryDocument) -> Value {
+        serde_json::to_value(document).unwrap()
+    }
+
+    #[tokio::test]
+    async fn serializes_default_document() {
+        let registrar = PgRegistrar::new(pool());
+        let document = to_json(&document(&registrar));
+
+        assert_eq!(document["scopes_supported"], json!(["openid", "profile"]));
+        assert_eq!(
+            document["code_challenge_methods_supported"],
+            json!(["S256"])
+        );
+        assert_eq!(
+            document["token_endpoint_auth_methods_supported"],
+            json!(registrar.token_endpoint_auth_methods())
+        );
+        assert!(document.get("introspection_endpoint").is_none());
+        assert!(
+            document
+                .get("introspection_endpoint_auth_methods_supported")
+                .is_none()
+        );
+        assert!(
+            document
+                .get("token_endpoint_auth_signing_alg_values_supported")
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn serializes_optional_endpoints_and_methods() {
+        let registrar = PgRegistrar::new(pool())
+            .with_token_endpoint("https://idp.example/token".to_owned())
+            .with_client_secret_key([0u8; 32]);
+        let document = document(&registrar)
+            .with_introspection_endpoint("https://idp.example/introspect".to_owned())
+            .with_allow_plain_pkce(true);
+        let document = to_json(&document);
+
+        assert_eq!(
+            document["introspection_endpoint"],
+            json!("https://idp.example/introspect")
+        );
+        assert_eq!(
+            document["introspection_endpoint_auth_methods_supported"],
+            json!([TOKEN_ENDPOINT_AUTH_METHOD_CLIENT_SECRET_BASIC])
+        );
+        assert_eq!(
+            document["code_challenge_methods_supported"],
+            json!([CODE_CHALLENGE_METHOD_S256, CODE_CHALLENGE_METHOD_PLAIN])
+        );
+        assert_eq!(
+            document["token_endpoint_auth_methods_supported"],
+            json!(registrar.token_endpoint_auth_methods())
+        );
+        let signing_algs = document["token_endpoint_auth_signing_alg_values_supported"]
+            .as_array()
+            .unwrap();
+        assert_eq!(
+            signing_algs.len(),
+            PRIVATE_KEY_JWT_ALGORITHMS.len() + CLIENT_SECRET_JWT_ALGORITHMS.len()
+        );
+    }
+}
@@ -45,8 +45,8 @@ where
 
     fn response(
         &mut self,
-        request: &mut OAuthRequest,
-        kind: Template,
+        _request: &mut OAuthRequest,
+        _kind: Template,
     ) -> Result<<OAuthRequest as WebRequest>::Response, Self::Error> {
         Ok(Default::default())
     }
@@ -0,0 +1,42 @@
+use oxide_auth::code_grant::accesstoken::Request as AccessTokenRequest;
+use oxide_auth::frontends::simple::extensions::{AccessTokenAddon, AddonResult};
+use oxide_auth::primitives::grant::{Extensions, GrantExtension, Value};
+
+#[derive(Default)]
+pub struct GrantCodeExtension;
+
+impl GrantCodeExtension {
+    pub fn new() -> Self {
+        Self
+    }
+
+    pub fn code_hash(extensions: &Extensions) -> Option<String> {
+        extensions
+            .private()
+            .find_map(|x| if x.0 == "grant_code" { x.1 } else { None })
+            .map(|x| x.to_owned())
+    }
+
+    pub(crate) fn to_value(code_hash: &str) -> Value {
+        Value::Private(Some(code_hash.to_owned()))
+    }
+}
+
+impl GrantExtension for GrantCodeExtension {
+    fn identifier(&self) -> &'static str {
+        "grant_code"
+    }
+}
+
+// Carries the hash of the redeemed code to the issuer, so tokens can be
+// revoked if the code is presented again.
+impl AccessTokenAddon for GrantCodeExtension {
+    fn execute(&self, _: &dyn AccessTokenRequest, code_data: Option<Value>) -> AddonResult {
+        match code_data {
+            Some(Value::Private(Some(code_hash))) => {
+                AddonResult::Data(Value::Private(Some(code_hash)))
+            }
+            _ => AddonResult::Err,
+        }
+    }
+}
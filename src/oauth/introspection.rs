@@ -0,0 +1,228 @@
+use crate::error::{Error, record_outcome};
+use crate::oauth::client_auth::{ClientCredentials, certificate_thumbprint};
+use crate::oauth::pg_issuer::PgIssuer;
+use crate::oauth::pg_registrar::PgRegistrar;
+use axum::extract::State;
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use oxide_auth::endpoint::{WebRequest, WebResponse};
+use oxide_auth_axum::{OAuthRequest, OAuthResponse, WebError};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
+use tracing::{Span, instrument};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Confirmation {
+    #[serde(rename = "x5t#S256")]
+    pub x5t_s256: String,
+}
+
+impl Confirmation {
+    pub fn from_certificate(certificate: &[u8]) -> Self {
+        Self {
+            x5t_s256: certificate_thumbprint(certificate),
+        }
+    }
+
+    pub fn verify(&self, certificate: &[u8]) -> Result<(), Error> {
+        let presented = Self::from_certificate(certificate);
+        if !bool::from(
+            presented
+                .x5t_s256
+                .as_bytes()
+                .ct_eq(self.x5t_s256.as_bytes()),
+        ) {
+            return Err(Error::AccessDenied(
+                "token is bound to another client certificate",
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct IntrospectionResponse {
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iat: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cnf: Option<Confirmation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub given_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub family_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+}
+
+impl IntrospectionResponse {
+    pub fn inactive() -> Self {
+        Self::default()
+    }
+
+    pub fn verify_certificate(&self, certificate: &[u8]) -> Result<(), Error> {
+        if !self.active {
+            return Err(Error::AccessDenied("token is not active"));
+        }
+        self.cnf
+            .as_ref()
+            .ok_or(Error::AccessDenied(
+                "token is not bound to a client certificate",
+            ))?
+            .verify(certificate)
+    }
+}
+
+#[derive(Clone)]
+pub struct IntrospectionState {
+    pub issuer: Arc<PgIssuer>,
+    pub registrar: Arc<PgRegistrar>,
+}
+
+#[instrument(skip_all, fields(client_id, active, outcome))]
+pub async fn introspect(
+    State(state): State<IntrospectionState>,
+    mut request: OAuthRequest,
+) -> Result<OAuthResponse, WebError> {
+    let mut response = OAuthResponse::default();
+
+    let credentials = request
+        .authheader()?
+        .and_then(|x| parse_basic_credentials(&x));
+    let Some((client_id, passphrase)) = credentials else {
+        response.unauthorized("Basic")?;
+        return Ok(response);
+    };
+    Span::current().record("client_id", &client_id);
+
+    let result = authenticate_resource_server(&state.registrar, &client_id, &passphrase).await;
+    match &result {
+        Ok(()) => {}
+        Err(Error::Pool | Error::Database(_) | Error::Crypto(_)) => {
+            record_outcome(&result);
+            return Err(WebError::InternalError(None));
+        }
+        Err(_) => {
+            record_outcome(&result);
+            response.unauthorized("Basic")?;
+            return Ok(response);
+        }
+    }
+
+    let token = request
+        .urlbody()?
+        .unique_value("token")
+        .map(|x| x.into_owned());
+    let Some(token) = token else {
+        response.client_error()?;
+        response.body_json(r#"{"error":"invalid_request"}"#)?;
+        return Ok(response);
+    };
+
+    let result = state.issuer.introspect_token(&token).await;
+    record_outcome(&result);
+    let introspection = result.map_err(|_| WebError::InternalError(None))?;
+    Span::current().record("active", introspection.active);
+
+    let json = serde_json::to_string(&introspection).map_err(|_| WebError::InternalError(None))?;
+    response.ok()?;
+    response.body_json(&json)?;
+    Ok(response)
+}
+
+async fn authenticate_resource_server(
+    registrar: &PgRegistrar,
+    client_id: &str,
+    passphrase: &[u8],
+) -> Result<(), Error> {
+    let credentials = ClientCredentials::new().with_passphrase(passphrase.to_vec());
+    registrar
+        .check_client(client_id, &credentials)
+        .await
+        .map_err(|err| match err {
+            Error::Pool | Error::Database(_) | Error::Crypto(_) => err,
+            _ => Error::AccessDenied("resource server credentials were rejected"),
+        })?;
+    if !registrar.is_confidential(client_id).await? {
+        return Err(Error::AccessDenied(
+            "resource server is not a confidential client",
+        ));
+    }
+    Ok(())
+}
+
+fn parse_basic_credentials(header: &str) -> Option<(String, Vec<u8>)> {
+    let encoded = header.strip_prefix("Basic ")?;
+    let decoded = BASE64_STANDARD.decode(encoded.trim()).ok()?;
+    let (client_id, passphrase) = decoded.split_at(decoded.iter().position(|x| *x == b':')?);
+    let client_id = String::from_utf8(client_id.to_vec()).ok()?;
+
+    Some((client_id, passphrase[1..].to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CERTIFICATE: &[u8] = b"bound certificate";
+
+    #[test]
+    fn confirmation_accepts_bound_certificate() {
+        let confirmation = Confirmation::from_certificate(CERTIFICATE);
+        assert_eq!(confirmation.x5t_s256, certificate_thumbprint(CERTIFICATE));
+        assert!(confirmation.verify(CERTIFICATE).is_ok());
+    }
+
+    #[test]
+    fn confirmation_rejects_other_certificate() {
+        let confirmation = Confirmation::from_certificate(CERTIFICATE);
+        assert!(matches!(
+            confirmation.verify(b"other certificate"),
+            Err(Error::AccessDenied(_))
+        ));
+    }
+
+    #[test]
+    fn confirmation_serializes_as_x5t_s256() {
+        let confirmation = Confirmation::from_certificate(CERTIFICATE);
+        let json = serde_json::to_value(&confirmation).unwrap();
+        assert_eq!(json["x5t#S256"], confirmation.x5t_s256);
+    }
+
+    #[test]
+    fn introspection_requires_active_bound_token() {
+        let active = IntrospectionResponse {
+            active: true,
+            cnf: Some(Confirmation::from_certificate(CERTIFICATE)),
+            ..Default::default()
+        };
+        assert!(active.verify_certificate(CERTIFICATE).is_ok());
+
+        let unbound = IntrospectionResponse {
+            active: true,
+            ..Default::default()
+        };
+        assert!(unbound.verify_certificate(CERTIFICATE).is_err());
+
+        let inactive = IntrospectionResponse {
+            active: false,
+            ..active
+        };
+        assert!(inactive.verify_certificate(CERTIFICATE).is_err());
+    }
+}
@@ -0,0 +1,376 @@
+use crate::error::Error;
+use crate::oauth::introspection::Confirmation;
+use base64::Engine;
+use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+use chrono::{DateTime, TimeDelta, Utc};
+use openidconnect::core::CoreJwsSigningAlgorithm::RsaSsaPkcs1V15Sha256;
+use openidconnect::core::{CoreJsonWebKey, CoreRsaPrivateSigningKey};
+use openidconnect::{JsonWebKey, PrivateSigningKey};
+use oxide_auth::primitives::scope::Scope;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+pub const ACCESS_TOKEN_FORMAT_OPAQUE: &str = "opaque";
+pub const ACCESS_TOKEN_FORMAT_JWT: &str = "jwt";
+pub const JWT_ACCESS_TOKEN_TYPE: &str = "at+jwt";
+const JWT_ACCESS_TOKEN_MEDIA_TYPE: &str = "application/at+jwt";
+const JWT_ACCESS_TOKEN_ALGORITHM: &str = "RS256";
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct JwtHeader {
+    pub alg: String,
+    pub typ: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kid: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct JwtAccessTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    pub aud: String,
+    pub exp: i64,
+    pub iat: i64,
+    pub jti: String,
+    pub client_id: String,
+    pub scope: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth_time: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub acr: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cnf: Option<Confirmation>,
+}
+
+impl JwtAccessTokenClaims {
+    pub fn scope(&self) -> Option<Scope> {
+        self.scope.parse().ok()
+    }
+
+    pub fn verify_certificate(&self, certificate: &[u8]) -> Result<(), Error> {
+        self.cnf
+            .as_ref()
+            .ok_or(Error::AccessDenied(
+                "token is not bound to a client certificate",
+            ))?
+            .verify(certificate)
+    }
+}
+
+pub struct JwtAccessTokenBuilder<'a> {
+    signing_key: &'a CoreRsaPrivateSigningKey,
+    claims: JwtAccessTokenClaims,
+}
+
+impl<'a> JwtAccessTokenBuilder<'a> {
+    pub fn new(
+        signing_key: &'a CoreRsaPrivateSigningKey,
+        issuer: String,
+        subject: String,
+        client_id: &Uuid,
+    ) -> Self {
+        let now = Utc::now();
+        Self {
+            signing_key,
+            claims: JwtAccessTokenClaims {
+                iss: issuer,
+                sub: subject,
+                aud: client_id.to_string(),
+                exp: (now + TimeDelta::hours(1)).timestamp(),
+                iat: now.timestamp(),
+                jti: Uuid::new_v4().to_string(),
+                client_id: client_id.to_string(),
+                scope: String::new(),
+                auth_time: None,
+                acr: None,
+                cnf: None,
+            },
+        }
+    }
+
+    pub fn with_audience(mut self, audience: String) -> Self {
+        self.claims.aud = audience;
+        self
+    }
+
+    pub fn with_token_id(mut self, token_id: &Uuid) -> Self {
+        self.claims.jti = token_id.to_string();
+        self
+    }
+
+    pub fn with_scope(mut self, scope: &Scope) -> Self {
+        self.claims.scope = scope.to_string();
+        self
+    }
+
+    pub fn with_validity(mut self, issued_at: DateTime<Utc>, expires_at: DateTime<Utc>) -> Self {
+        self.claims.iat = issued_at.timestamp();
+        self.claims.exp = expires_at.timestamp();
+        self
+    }
+
+    pub fn with_auth_time(mut self, auth_time: DateTime<Utc>) -> Self {
+        self.claims.auth_time = Some(auth_time.timestamp());
+        self
+    }
+
+    pub fn with_acr(mut self, acr: String) -> Self {
+        self.claims.acr = Some(acr);
+        self
+    }
+
+    pub fn with_confirmation(mut self, confirmation: Confirmation) -> Self {
+        self.claims.cnf = Some(confirmation);
+        self
+    }
+
+    pub fn sign(self) -> Result<String, Error> {
+        let header = JwtHeader {
+            alg: JWT_ACCESS_TOKEN_ALGORITHM.to_owned(),
+            typ: JWT_ACCESS_TOKEN_TYPE.to_owned(),
+            kid: self
+                .signing_key
+                .as_verification_key()
+                .key_id()
+                .map(|x| x.as_str().to_owned()),
+        };
+        let header = serde_json::to_vec(&header)
+            .map_err(|_| Error::Crypto("failed to encode the access token header"))?;
+        let claims = serde_json::to_vec(&self.claims)
+            .map_err(|_| Error::Crypto("failed to encode the access token claims"))?;
+
+        let signing_input = format!(
+            "{}.{}",
+            BASE64_URL_SAFE_NO_PAD.encode(header),
+            BASE64_URL_SAFE_NO_PAD.encode(claims)
+        );
+        let signature = self
+            .signing_key
+            .sign(&RsaSsaPkcs1V15Sha256, signing_input.as_bytes())
+            .map_err(|_| Error::Crypto("failed to sign the access token"))?;
+
+        Ok(format!(
+            "{signing_input}.{}",
+            BASE64_URL_SAFE_NO_PAD.encode(signature)
+        ))
+    }
+}
+
+pub struct JwtAccessTokenValidator {
+    issuer: String,
+    audience: String,
+    keys: Vec<CoreJsonWebKey>,
+    leeway: TimeDelta,
+}
+
+impl JwtAccessTokenValidator {
+    pub fn new(issuer: String, audience: String, keys: Vec<CoreJsonWebKey>) -> Self {
+        Self {
+            issuer,
+            audience,
+            keys,
+            leeway: TimeDelta::seconds(30),
+        }
+    }
+
+    pub fn with_leeway(mut self, leeway: TimeDelta) -> Self {
+        self.leeway = leeway;
+        self
+    }
+
+    pub fn validate(&self, token: &str) -> Result<JwtAccessTokenClaims, Error> {
+        let Some((signing_input, signature)) = token.rsplit_once('.') else {
+            return Err(Error::Validation("access token is not a compact JWS"));
+        };
+        let Some((header, claims)) = signing_input.split_once('.') else {
+            return Err(Error::Validation("access token is not a compact JWS"));
+        };
+
+        let header: JwtHeader = decode_segment(header)?;
+        if header.alg != JWT_ACCESS_TOKEN_ALGORITHM {
+            return Err(Error::Validation("access token algorithm is not supported"));
+        }
+        if !header.typ.eq_ignore_ascii_case(JWT_ACCESS_TOKEN_TYPE)
+            && !header.typ.eq_ignore_ascii_case(JWT_ACCESS_TOKEN_MEDIA_TYPE)
+        {
+            return Err(Error::Validation("token is not a JWT access token"));
+        }
+
+        let signature = BASE64_URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| Error::Validation("access token signature is not base64url"))?;
+        let verified = self
+            .keys
+            .iter()
+            .filter(|x| match (&header.kid, x.key_id()) {
+                (Some(kid), Some(key_id)) => kid == key_id.as_str(),
+                _ => true,
+            })
+            .any(|x| {
+                x.verify_signature(&RsaSsaPkcs1V15Sha256, signing_input.as_bytes(), &signature)
+                    .is_ok()
+            });
+        if !verified {
+            return Err(Error::AccessDenied("access token signature is invalid"));
+        }
+
+        let claims: JwtAccessTokenClaims = decode_segment(claims)?;
+        if claims.iss != self.issuer {
+            return Err(Error::AccessDenied(
+                "access token was issued by another issuer",
+            ));
+        }
+        if claims.aud != self.audience {
+            return Err(Error::AccessDenied(
+                "access token is meant for another audience",
+            ));
+        }
+        let now = Utc::now();
+        if claims.exp <= (now - self.leeway).timestamp() {
+            return Err(Error::AccessDenied("access token has expired"));
+        }
+        if claims.iat > (now + self.leeway).timestamp() {
+            return Err(Error::AccessDenied("access token was issued in the future"));
+        }
+        Ok(claims)
+    }
+}
+
+fn decode_segment<T: for<'de> Deserialize<'de>>(segment: &str) -> Result<T, Error> {
+    let segment = BASE64_URL_SAFE_NO_PAD
+        .decode(segment)
+        .map_err(|_| Error::Validation("access token segment is not base64url"))?;
+    serde_json::from_slice(&segment).map_err(|_| Error::Validation("access token is malformed"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openidconnect::JsonWebKeyId;
+
+    const ISSUER: &str = "https://idp.example";
+    const AUDIENCE: &str = "https://api.example";
+
+    fn signing_key(kid: &str) -> CoreRsaPrivateSigningKey {
+        CoreRsaPrivateSigningKey::from_pem(
+            include_str!("../../testdata/signing_key.pem"),
+            Some(JsonWebKeyId::new(kid.to_owned())),
+        )
+        .unwrap()
+    }
+
+    fn validator(signing_key: &CoreRsaPrivateSigningKey) -> JwtAccessTokenValidator {
+        JwtAccessTokenValidator::new(
+            ISSUER.to_owned(),
+            AUDIENCE.to_owned(),
+            vec![signing_key.as_verification_key()],
+        )
+    }
+
+    fn builder(signing_key: &CoreRsaPrivateSigningKey) -> JwtAccessTokenBuilder<'_> {
+        JwtAccessTokenBuilder::new(
+            signing_key,
+            ISSUER.to_owned(),
+            "subject".to_owned(),
+            &Uuid::nil(),
+        )
+        .with_audience(AUDIENCE.to_owned())
+    }
+
+    #[test]
+    fn validates_signed_token() {
+        let signing_key = signing_key("k1");
+        let token_id = Uuid::new_v4();
+        let confirmation = Confirmation {
+            x5t_s256: "thumbprint".to_owned(),
+        };
+        let token = builder(&signing_key)
+            .with_token_id(&token_id)
+            .with_scope(&"openid profile".parse().unwrap())
+            .with_acr("high".to_owned())
+            .with_confirmation(confirmation.clone())
+            .sign()
+            .unwrap();
+
+        let header: JwtHeader = decode_segment(token.split('.').next().unwrap()).unwrap();
+        assert_eq!(header.typ, JWT_ACCESS_TOKEN_TYPE);
+        assert_eq!(header.kid.as_deref(), Some("k1"));
+
+        let claims = validator(&signing_key).validate(&token).unwrap();
+        assert_eq!(claims.iss, ISSUER);
+        assert_eq!(claims.sub, "subject");
+        assert_eq!(claims.aud, AUDIENCE);
+        assert_eq!(claims.jti, token_id.to_string());
+        assert_eq!(claims.client_id, Uuid::nil().to_string());
+        assert_eq!(claims.scope(), "openid profile".parse().ok());
+        assert_eq!(claims.acr.as_deref(), Some("high"));
+        assert_eq!(claims.cnf, Some(confirmation));
+    }
+
+    #[test]
+    fn rejects_token_signed_under_another_kid() {
+        let token = builder(&signing_key("k1")).sign().unwrap();
+        let result = validator(&signing_key("k2")).validate(&token);
+        assert!(matches!(result, Err(Error::AccessDenied(_))));
+    }
+
+    #[test]
+    fn rejects_tampered_claims() {
+        let signing_key = signing_key("k1");
+        let token = builder(&signing_key).sign().unwrap();
+        let forged = builder(&signing_key)
+            .with_scope(&"admin".parse().unwrap())
+            .sign()
+            .unwrap();
+
+        let mut segments = token.split('.').collect::<Vec<_>>();
+        segments[1] = forged.split('.').nth(1).unwrap();
+        let result = validator(&signing_key).validate(&segments.join("."));
+        assert!(matches!(result, Err(Error::AccessDenied(_))));
+    }
+
+    #[test]
+    fn rejects_wrong_issuer_or_audience() {
+        let signing_key = signing_key("k1");
+        let token = builder(&signing_key).sign().unwrap();
+
+        let result = JwtAccessTokenValidator::new(
+            "https://other.example".to_owned(),
+            AUDIENCE.to_owned(),
+            vec![signing_key.as_verification_key()],
+        )
+        .validate(&token);
+        assert!(matches!(result, Err(Error::AccessDenied(_))));
+
+        let result = JwtAccessTokenValidator::new(
+            ISSUER.to_owned(),
+            "https://other.example".to_owned(),
+            vec![signing_key.as_verification_key()],
+        )
+        .validate(&token);
+        assert!(matches!(result, Err(Error::AccessDenied(_))));
+    }
+
+    #[test]
+    fn rejects_expired_token() {
+        let signing_key = signing_key("k1");
+        let now = Utc::now();
+        let token = builder(&signing_key)
+            .with_validity(now - TimeDelta::hours(2), now - TimeDelta::hours(1))
+            .sign()
+            .unwrap();
+
+        let result = validator(&signing_key).validate(&token);
+        assert!(matches!(result, Err(Error::AccessDenied(_))));
+        let result = validator(&signing_key)
+            .with_leeway(TimeDelta::hours(2))
+            .validate(&token);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn rejects_malformed_token() {
+        let result = validator(&signing_key("k1")).validate("not-a-jwt");
+        assert!(matches!(result, Err(Error::Validation(_))));
+    }
+}
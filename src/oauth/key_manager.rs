@@ -0,0 +1,468 @@
+use crate::crypto;
+use crate::db;
+use crate::db::models::SigningKey;
+use crate::db::schema::signing_keys as signing_keys_columns;
+use crate::db::schema::signing_keys::dsl::signing_keys;
+use crate::error::Error;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::{IntoResponse, Response};
+use base64::Engine;
+use base64::prelude::{BASE64_STANDARD, BASE64_URL_SAFE_NO_PAD};
+use chrono::{DateTime, TimeDelta, Utc};
+use diesel::dsl::{delete, insert_into, sql_query, update};
+use diesel::sql_types::BigInt;
+use diesel::{BoolExpressionMethods, ExpressionMethods, QueryDsl};
+use diesel::{QueryResult, SelectableHelper};
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use openidconnect::core::{CoreJsonWebKey, CoreJsonWebKeySet, CoreRsaPrivateSigningKey};
+use openidconnect::{JsonWebKeyId, PrivateSigningKey};
+use rsa::pkcs1::{EncodeRsaPrivateKey, LineEnding};
+use rsa::traits::PublicKeyParts;
+use rsa::{RsaPrivateKey, rand_core::OsRng};
+use sha2::{Digest, Sha256};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+
+pub const JWKS_PATH: &str = "/jwks";
+pub const SIGNING_KEY_STATE_NEXT: &str = "next";
+pub const SIGNING_KEY_STATE_ACTIVE: &str = "active";
+pub const SIGNING_KEY_STATE_RETIRED: &str = "retired";
+
+const SIGNING_KEY_LOCK: i64 = 0x7369_676e_5f6b_6579;
+const SIGNING_KEY_BITS: usize = 2048;
+
+pub struct SigningKeys {
+    active: Arc<CoreRsaPrivateSigningKey>,
+    next: Option<Arc<CoreRsaPrivateSigningKey>>,
+    retired: Vec<Arc<CoreRsaPrivateSigningKey>>,
+}
+
+impl SigningKeys {
+    pub fn active(&self) -> &Arc<CoreRsaPrivateSigningKey> {
+        &self.active
+    }
+
+    // The next key is published ahead of its activation so relying parties
+    // already have it cached when tokens signed with it start arriving.
+    pub fn verification_keys(&self) -> Vec<CoreJsonWebKey> {
+        std::iter::once(&self.active)
+            .chain(&self.next)
+            .chain(&self.retired)
+            .map(|x| x.as_verification_key())
+            .collect()
+    }
+
+    pub fn jwks(&self) -> CoreJsonWebKeySet {
+        CoreJsonWebKeySet::new(self.verification_keys())
+    }
+}
+
+pub struct KeyManager {
+    pool: Arc<db::Pool>,
+    encryption_key: [u8; 32],
+    rotation_period: TimeDelta,
+    retention_period: TimeDelta,
+    keys: RwLock<Option<Arc<SigningKeys>>>,
+}
+
+impl KeyManager {
+    pub fn new(pool: Arc<db::Pool>, encryption_key: [u8; 32]) -> Self {
+        Self {
+            pool,
+            encryption_key,
+            rotation_period: TimeDelta::days(90),
+            retention_period: TimeDelta::days(7),
+            keys: RwLock::new(None),
+        }
+    }
+
+    pub fn with_rotation_period(mut self, rotation_period: TimeDelta) -> Self {
+        self.rotation_period = rotation_period;
+        self
+    }
+
+    pub fn with_retention_period(mut self, retention_period: TimeDelta) -> Self {
+        self.retention_period = retention_period;
+        self
+    }
+
+    pub async fn current(&self) -> Result<Arc<SigningKeys>, Error> {
+        let cached = self
+            .keys
+            .read()
+            .map_err(|_| Error::Crypto("signing key cache is poisoned"))?
+            .clone();
+        match cached {
+            Some(keys) => Ok(keys),
+            None => self.refresh().await,
+        }
+    }
+
+    pub async fn refresh(&self) -> Result<Arc<SigningKeys>, Error> {
+        let mut conn = self.pool.get().await.map_err(|_| Error::Pool)?;
+        let encryption_key = self.encryption_key;
+        conn.transaction::<_, Error, _>(|conn| {
+            async move {
+                Self::lock(conn).await?;
+                Self::ensure_keys(conn, &encryption_key, Utc::now()).await
+            }
+            .scope_boxed()
+        })
+        .await?;
+        self.load(&mut conn).await
+    }
+
+    pub async fn rotate(&self) -> Result<Arc<SigningKeys>, Error> {
+        let mut conn = self.pool.get().await.map_err(|_| Error::Pool)?;
+        let encryption_key = self.encryption_key;
+        conn.transaction::<_, Error, _>(|conn| {
+            async move {
+                let now = Utc::now();
+                Self::lock(conn).await?;
+                Self::ensure_keys(conn, &encryption_key, now).await?;
+                Self::rotate_keys(conn, &encryption_key, now).await
+            }
+            .scope_boxed()
+        })
+        .await?;
+        self.load(&mut conn).await
+    }
+
+    pub async fn rotate_if_due(&self) -> Result<bool, Error> {
+        let mut conn = self.pool.get().await.map_err(|_| Error::Pool)?;
+        let encryption_key = self.encryption_key;
+        let rotation_period = self.rotation_period;
+        let retention_period = self.retention_period;
+        let rotated = conn
+            .transaction::<_, Error, _>(|conn| {
+                async move {
+                    let now = Utc::now();
+                    Self::lock(conn).await?;
+                    Self::ensure_keys(conn, &encryption_key, now).await?;
+
+                    let activated_at = signing_keys
+                        .filter(signing_keys_columns::state.eq(SIGNING_KEY_STATE_ACTIVE))
+                        .select(signing_keys_columns::activated_at)
+                        .first::<Option<DateTime<Utc>>>(conn)
+                        .await?;
+                    let due = activated_at.is_none_or(|x| x + rotation_period <= now);
+                    if due {
+                        Self::rotate_keys(conn, &encryption_key, now).await?;
+                    }
+
+                    delete(
+                        signing_keys
+                            .filter(signing_keys_columns::state.eq(SIGNING_KEY_STATE_RETIRED))
+                            .filter(signing_keys_columns::retired_at.lt(now - retention_period)),
+                    )
+                    .execute(conn)
+                    .await?;
+                    Ok(due)
+                }
+                .scope_boxed()
+            })
+            .await?;
+        self.load(&mut conn).await?;
+        Ok(rotated)
+    }
+
+    pub fn spawn_rotation(self: Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            loop {
+                interval.tick().await;
+                match self.rotate_if_due().await {
+                    Ok(true) => tracing::info!("rotated the signing keys"),
+                    Ok(false) => tracing::debug!("signing key rotation is not due"),
+                    Err(err) => tracing::warn!(error = %err, "signing key rotation failed"),
+                }
+            }
+        })
+    }
+
+    pub fn encrypt_private_key(encryption_key: &[u8; 32], pem: &str) -> Option<String> {
+        let encrypted_value = crypto::encrypt_value(encryption_key, pem.as_bytes())?;
+        Some(BASE64_STANDARD.encode(encrypted_value))
+    }
+
+    async fn lock(conn: &mut AsyncPgConnection) -> QueryResult<usize> {
+        sql_query("SELECT pg_advisory_xact_lock($1)")
+            .bind::<BigInt, _>(SIGNING_KEY_LOCK)
+            .execute(conn)
+            .await
+    }
+
+    async fn ensure_keys(
+        conn: &mut AsyncPgConnection,
+        encryption_key: &[u8; 32],
+        now: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        let states = signing_keys
+            .filter(signing_keys_columns::state.ne(SIGNING_KEY_STATE_RETIRED))
+            .select(signing_keys_columns::state)
+            .load::<String>(conn)
+            .await?;
+
+        if !states.iter().any(|x| x == SIGNING_KEY_STATE_ACTIVE) {
+            Self::activate_next(conn, encryption_key, now).await?;
+        }
+        if !states.iter().any(|x| x == SIGNING_KEY_STATE_NEXT) {
+            Self::insert_key(conn, encryption_key, SIGNING_KEY_STATE_NEXT, now).await?;
+        }
+        Ok(())
+    }
+
+    async fn rotate_keys(
+        conn: &mut AsyncPgConnection,
+        encryption_key: &[u8; 32],
+        now: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        update(signing_keys.filter(signing_keys_columns::state.eq(SIGNING_KEY_STATE_ACTIVE)))
+            .set((
+                signing_keys_columns::state.eq(SIGNING_KEY_STATE_RETIRED),
+                signing_keys_columns::retired_at.eq(now),
+            ))
+            .execute(conn)
+            .await?;
+        Self::activate_next(conn, encryption_key, now).await?;
+        Self::insert_key(conn, encryption_key, SIGNING_KEY_STATE_NEXT, now).await
+    }
+
+    async fn activate_next(
+        conn: &mut AsyncPgConnection,
+        encryption_key: &[u8; 32],
+        now: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        let activated =
+            update(signing_keys.filter(signing_keys_columns::state.eq(SIGNING_KEY_STATE_NEXT)))
+                .set((
+                    signing_keys_columns::state.eq(SIGNING_KEY_STATE_ACTIVE),
+                    signing_keys_columns::activated_at.eq(now),
+                ))
+                .execute(conn)
+                .await?;
+        if activated == 0 {
+            Self::insert_key(conn, encryption_key, SIGNING_KEY_STATE_ACTIVE, now).await?;
+        }
+        Ok(())
+    }
+
+    async fn insert_key(
+        conn: &mut AsyncPgConnection,
+        encryption_key: &[u8; 32],
+        state: &'static str,
+        now: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        let encryption_key = *encryption_key;
+        let key =
+            tokio::task::spawn_blocking(move || Self::generate_key(&encryption_key, state, now))
+                .await
+                .map_err(|_| Error::Crypto("signing key generation was aborted"))??;
+
+        insert_into(signing_keys).values(&key).execute(conn).await?;
+        Ok(())
+    }
+
+    fn generate_key(
+        encryption_key: &[u8; 32],
+        state: &str,
+        now: DateTime<Utc>,
+    ) -> Result<SigningKey, Error> {
+        let private_key = RsaPrivateKey::new(&mut OsRng, SIGNING_KEY_BITS)
+            .map_err(|_| Error::Crypto("failed to generate a signing key"))?;
+        let pem = private_key
+            .to_pkcs1_pem(LineEnding::LF)
+            .map_err(|_| Error::Crypto("failed to encode the signing key"))?;
+
+        Ok(SigningKey {
+            kid: key_thumbprint(&private_key),
+            state: state.to_owned(),
+            encrypted_private_key: Self::encrypt_private_key(encryption_key, &pem)
+                .ok_or(Error::Crypto("failed to encrypt the signing key"))?,
+            created_at: now,
+            activated_at: (state == SIGNING_KEY_STATE_ACTIVE).then_some(now),
+            retired_at: None,
+        })
+    }
+
+    async fn load(&self, conn: &mut AsyncPgConnection) -> Result<Arc<SigningKeys>, Error> {
+        let rows = signing_keys
+            .filter(
+                signing_keys_columns::state
+                    .ne(SIGNING_KEY_STATE_RETIRED)
+                    .or(signing_keys_columns::retired_at.ge(Utc::now() - self.retention_period)),
+            )
+            .order(signing_keys_columns::created_at.desc())
+            .select(SigningKey::as_select())
+            .load(conn)
+            .await?;
+
+        let mut active = None;
+        let mut next = None;
+        let mut retired = vec![];
+        for row in rows {
+            let key = Arc::new(self.decrypt_key(&row)?);
+            match row.state.as_str() {
+                SIGNING_KEY_STATE_ACTIVE => active = Some(key),
+                SIGNING_KEY_STATE_NEXT => next = Some(key),
+                _ => retired.push(key),
+            }
+        }
+        let keys = Arc::new(SigningKeys {
+            active: active.ok_or(Error::NotFound("active signing key"))?,
+            next,
+            retired,
+        });
+
+        *self
+            .keys
+            .write()
+            .map_err(|_| Error::Crypto("signing key cache is poisoned"))? = Some(keys.clone());
+        Ok(keys)
+    }
+
+    fn decrypt_key(&self, row: &SigningKey) -> Result<CoreRsaPrivateSigningKey, Error> {
+        let pem = crypto::decrypt_value(&self.encryption_key, &row.encrypted_private_key)
+            .ok_or(Error::Crypto("failed to decrypt the signing key"))?;
+        let pem = String::from_utf8(pem).map_err(|_| Error::Crypto("signing key is malformed"))?;
+        CoreRsaPrivateSigningKey::from_pem(&pem, Some(JsonWebKeyId::new(row.kid.clone())))
+            .map_err(|_| Error::Crypto("signing key is malformed"))
+    }
+}
+
+pub async fn jwks(State(key_manager): State<Arc<KeyManager>>) -> Response {
+    let keys = match key_manager.current().await {
+        Ok(keys) => keys,
+        Err(err) => {
+            tracing::warn!(error = %err, "failed to load the signing keys");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    match serde_json::to_string(&keys.jwks()) {
+        Ok(json) => ([(CONTENT_TYPE, "application/json")], json).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+// RFC 7638 thumbprint, so the kid is stable and derived from the key itself.
+fn key_thumbprint(key: &impl PublicKeyParts) -> String {
+    let jwk = format!(
+        r#"{{"e":"{}","kty":"RSA","n":"{}"}}"#,
+        BASE64_URL_SAFE_NO_PAD.encode(key.e().to_bytes_be()),
+        BASE64_URL_SAFE_NO_PAD.encode(key.n().to_bytes_be())
+    );
+    BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(jwk))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::fixtures::ENCRYPTION_KEY;
+    use diesel_async::AsyncPgConnection;
+    use diesel_async::pooled_connection::AsyncDieselConnectionManager;
+    use openidconnect::JsonWebKey;
+    use rsa::pkcs1::DecodeRsaPrivateKey;
+    use rsa::{BigUint, RsaPublicKey};
+
+    const SIGNING_KEY_PEM: &str = include_str!("../../testdata/signing_key.pem");
+
+    fn signing_key(kid: &str) -> Arc<CoreRsaPrivateSigningKey> {
+        Arc::new(
+            CoreRsaPrivateSigningKey::from_pem(
+                SIGNING_KEY_PEM,
+                Some(JsonWebKeyId::new(kid.to_owned())),
+            )
+            .unwrap(),
+        )
+    }
+
+    fn key_ids(keys: &SigningKeys) -> Vec<String> {
+        keys.verification_keys()
+            .iter()
+            .map(|x| x.key_id().unwrap().as_str().to_owned())
+            .collect()
+    }
+
+    fn key_manager(encryption_key: [u8; 32]) -> KeyManager {
+        let manager =
+            AsyncDieselConnectionManager::<AsyncPgConnection>::new("postgres://localhost/unused");
+        KeyManager::new(
+            Arc::new(db::Pool::builder().build_unchecked(manager)),
+            encryption_key,
+        )
+    }
+
+    #[test]
+    fn computes_rfc7638_thumbprint() {
+        let n = BASE64_URL_SAFE_NO_PAD
+            .decode(
+                "0vx7agoebGcQSuuPiLJXZptN9nndrQmbXEps2aiAFbWhM78LhWx4cbbfAAtVT86zwu1RK7aPFFxuhDR1L6tSoc_B\
+                 JECPebWKRXjBZCiFV4n3oknjhMstn64tZ_2W-5JsGY4Hc5n9yBXArwl93lqt7_RN5w6Cf0h4QyQ5v-65YGjQR0_\
+                 FDW2QvzqY368QQMicAtaSqzs8KJZgnYb9c7d0zgdAZHzu6qMQvRL5hajrn1n91CbOpbISD08qNLyrdkt-bFTWhA\
+                 I4vMQFh6WeZu0fM4lFd2NcRwr3XPksINHaQ-G_xBniIqbw0Ls1jF44-csFCur-kEgU8awapJzKnqDKgw",
+            )
+            .unwrap();
+        let e = BASE64_URL_SAFE_NO_PAD.decode("AQAB").unwrap();
+        let key =
+            RsaPublicKey::new(BigUint::from_bytes_be(&n), BigUint::from_bytes_be(&e)).unwrap();
+        assert_eq!(
+            key_thumbprint(&key),
+            "NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs"
+        );
+    }
+
+    #[test]
+    fn lists_active_then_next_then_retired_keys() {
+        let keys = SigningKeys {
+            active: signing_key("active"),
+            next: Some(signing_key("next")),
+            retired: vec![signing_key("retired-1"), signing_key("retired-2")],
+        };
+        assert_eq!(key_ids(&keys), ["active", "next", "retired-1", "retired-2"]);
+        assert_eq!(
+            keys.verification_keys()[0],
+            signing_key("active").as_verification_key()
+        );
+        assert_eq!(keys.jwks().keys(), &keys.verification_keys());
+
+        let keys = SigningKeys {
+            active: signing_key("active"),
+            next: None,
+            retired: vec![signing_key("retired-1")],
+        };
+        assert_eq!(key_ids(&keys), ["active", "retired-1"]);
+    }
+
+    #[tokio::test]
+    async fn decrypts_encrypted_private_key() {
+        let private_key = RsaPrivateKey::from_pkcs1_pem(SIGNING_KEY_PEM).unwrap();
+        let row = SigningKey {
+            kid: key_thumbprint(&private_key),
+            state: SIGNING_KEY_STATE_ACTIVE.to_owned(),
+            encrypted_private_key: KeyManager::encrypt_private_key(
+                &ENCRYPTION_KEY,
+                SIGNING_KEY_PEM,
+            )
+            .unwrap(),
+            created_at: Utc::now(),
+            activated_at: None,
+            retired_at: None,
+        };
+        assert_ne!(row.encrypted_private_key, SIGNING_KEY_PEM);
+
+        let decrypted = key_manager(ENCRYPTION_KEY).decrypt_key(&row).unwrap();
+        assert_eq!(
+            decrypted.as_verification_key(),
+            signing_key(&row.kid).as_verification_key()
+        );
+
+        assert!(key_manager([8u8; 32]).decrypt_key(&row).is_err());
+    }
+}
@@ -1,15 +1,72 @@
-use crate::oauth::client_cert_data::ClientCertData;
-use oxide_auth::code_grant::authorization::Request;
-use oxide_auth::frontends::simple::extensions::{AddonResult, AuthorizationAddon};
-use oxide_auth::primitives::grant::{GrantExtension, Value};
+use crate::oauth::client_cert_data::{ClientCertData, ClientCertDataError};
+use crate::pki::chain_validator::{ChainError, ChainValidator};
+use oxide_auth::code_grant::accesstoken::Request as AccessTokenRequest;
+use oxide_auth::code_grant::authorization::Request as AuthorizationRequest;
+use oxide_auth::frontends::simple::extensions::{
+    AccessTokenAddon, AddonResult, AuthorizationAddon,
+};
+use oxide_auth::primitives::grant::{Extensions, GrantExtension, Value};
+use std::fmt;
 
+#[derive(Debug)]
+pub enum MtlsExtensionError {
+    UntrustedCertificate(ChainError),
+    InvalidSubject(ClientCertDataError),
+}
+
+impl fmt::Display for MtlsExtensionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UntrustedCertificate(err) => write!(f, "untrusted client certificate: {err}"),
+            Self::InvalidSubject(err) => write!(f, "invalid client certificate subject: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for MtlsExtensionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::UntrustedCertificate(err) => Some(err),
+            Self::InvalidSubject(err) => Some(err),
+        }
+    }
+}
+
+#[derive(Default)]
 pub struct MtlsExtension {
-    client_cert_data: ClientCertData,
+    client_cert_data: Option<ClientCertData>,
 }
 
 impl MtlsExtension {
-    pub fn new(client_cert_data: ClientCertData) -> Self {
-        Self { client_cert_data }
+    pub(crate) fn new(client_cert_data: ClientCertData) -> Self {
+        Self {
+            client_cert_data: Some(client_cert_data),
+        }
+    }
+
+    pub fn from_certificate(
+        validator: &ChainValidator,
+        certificate: &[u8],
+        intermediates: &[&[u8]],
+    ) -> Result<Self, MtlsExtensionError> {
+        let chain = validator
+            .validate_der(certificate, intermediates)
+            .map_err(MtlsExtensionError::UntrustedCertificate)?;
+        let client_cert_data = ClientCertData::from_certificate(&chain.certificate)
+            .map_err(MtlsExtensionError::InvalidSubject)?;
+
+        Ok(Self::new(client_cert_data))
+    }
+
+    pub fn client_cert_data(extensions: &Extensions) -> Option<ClientCertData> {
+        let value = extensions
+            .public()
+            .find_map(|x| if x.0 == "mtls" { x.1 } else { None })?;
+        serde_json::from_str(value).ok()
+    }
+
+    pub fn certificate_thumbprint(extensions: &Extensions) -> Option<String> {
+        Self::client_cert_data(extensions)?.certificate_fingerprint()
     }
 }
 
@@ -20,10 +77,22 @@ impl GrantExtension for MtlsExtension {
 }
 
 impl AuthorizationAddon for MtlsExtension {
-    fn execute(&self, _: &dyn Request) -> AddonResult {
-        match serde_json::to_string(&self.client_cert_data) {
+    fn execute(&self, _: &dyn AuthorizationRequest) -> AddonResult {
+        let Some(client_cert_data) = &self.client_cert_data else {
+            return AddonResult::Err;
+        };
+        match serde_json::to_string(client_cert_data) {
             Ok(json) => AddonResult::Data(Value::Public(Some(json))),
             Err(_) => AddonResult::Err,
         }
     }
 }
+
+impl AccessTokenAddon for MtlsExtension {
+    fn execute(&self, _: &dyn AccessTokenRequest, code_data: Option<Value>) -> AddonResult {
+        match code_data {
+            Some(Value::Public(Some(json))) => AddonResult::Data(Value::Public(Some(json))),
+            _ => AddonResult::Err,
+        }
+    }
+}
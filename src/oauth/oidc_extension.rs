@@ -0,0 +1,71 @@
+use chrono::{DateTime, Utc};
+use oxide_auth::code_grant::accesstoken::Request as AccessTokenRequest;
+use oxide_auth::code_grant::authorization::Request as AuthorizationRequest;
+use oxide_auth::frontends::simple::extensions::{
+    AccessTokenAddon, AddonResult, AuthorizationAddon,
+};
+use oxide_auth::primitives::grant::{Extensions, GrantExtension, Value};
+use serde::{Deserialize, Serialize};
+
+const MAX_NONCE_LENGTH: usize = 512;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct OidcAuthentication {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    pub auth_time: DateTime<Utc>,
+}
+
+pub struct OidcExtension {
+    auth_time: DateTime<Utc>,
+}
+
+impl OidcExtension {
+    pub fn new(auth_time: DateTime<Utc>) -> Self {
+        Self { auth_time }
+    }
+
+    pub fn authentication(extensions: &Extensions) -> Option<OidcAuthentication> {
+        let value = extensions
+            .public()
+            .find_map(|x| if x.0 == "oidc" { x.1 } else { None })?;
+        serde_json::from_str(value).ok()
+    }
+}
+
+impl GrantExtension for OidcExtension {
+    fn identifier(&self) -> &'static str {
+        "oidc"
+    }
+}
+
+impl AuthorizationAddon for OidcExtension {
+    fn execute(&self, request: &dyn AuthorizationRequest) -> AddonResult {
+        let nonce = request.extension("nonce").map(|x| x.into_owned());
+        if nonce
+            .as_ref()
+            .is_some_and(|x| x.is_empty() || x.len() > MAX_NONCE_LENGTH)
+        {
+            return AddonResult::Err;
+        }
+
+        let authentication = OidcAuthentication {
+            nonce,
+            auth_time: self.auth_time,
+        };
+        match serde_json::to_string(&authentication) {
+            Ok(json) => AddonResult::Data(Value::Public(Some(json))),
+            Err(_) => AddonResult::Err,
+        }
+    }
+}
+
+impl AccessTokenAddon for OidcExtension {
+    fn execute(&self, _: &dyn AccessTokenRequest, code_data: Option<Value>) -> AddonResult {
+        match code_data {
+            Some(Value::Public(Some(json))) => AddonResult::Data(Value::Public(Some(json))),
+            Some(_) => AddonResult::Err,
+            None => AddonResult::Ok,
+        }
+    }
+}
@@ -1,34 +1,328 @@
+use crate::crypto;
 use crate::db;
-use crate::db::models::{OAuthGrant, OAuthGrantExtension};
+use crate::db::audit::{AuditEventType, AuditLog, AuditRecord};
+use crate::db::models::{OAuthGrant, OAuthGrantExtension, OAuthGrantReplay};
+use crate::db::schema::auth_clients as auth_clients_columns;
+use crate::db::schema::auth_clients::dsl::auth_clients;
 use crate::db::schema::oauth_grant_extensions::dsl::oauth_grant_extensions;
-use crate::db::schema::oauth_grants::code_hash;
+use crate::db::schema::oauth_grant_replays::dsl::oauth_grant_replays;
 use crate::db::schema::oauth_grants::dsl::oauth_grants;
-use aes_gcm::KeyInit;
-use aes_gcm::aead::{Aead, Nonce};
-use aes_gcm::{Aes256Gcm, Key};
+use crate::db::schema::oauth_grants::{code_hash, consumed_at};
+use crate::db::tokens::TokenRepository;
+use crate::db::users::UserRepository;
+use crate::error::{Error, record_outcome};
+use crate::oauth::grant_code_extension::GrantCodeExtension;
+use crate::oauth::mtls_extension::MtlsExtension;
+use crate::oauth::pkce_extension::{PkceChallenge, PkceExtension};
+use crate::oauth::subject::{SubjectGenerator, hashed_subject};
 use async_trait::async_trait;
 use base64::Engine;
 use base64::prelude::{BASE64_STANDARD, BASE64_URL_SAFE_NO_PAD};
+use chrono::{TimeDelta, Utc};
 use diesel::dsl::insert_into;
-use diesel::{BelongingToDsl, ExpressionMethods};
+use diesel::{BelongingToDsl, ExpressionMethods, OptionalExtension};
 use diesel::{QueryDsl, SelectableHelper};
-use diesel_async::RunQueryDsl;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, RunQueryDsl};
 use hkdf::Hkdf;
 use oxide_auth::primitives::grant::{Extensions, Grant, Value};
 use oxide_auth_async::primitives::Authorizer;
 use rand::TryRngCore;
 use rand::rand_core::OsRng;
 use sha2::{Digest, Sha256};
+use std::net::IpAddr;
 use std::sync::Arc;
+use tracing::field::display;
+use tracing::{Span, instrument};
 use uuid::Uuid;
 
+enum Redemption {
+    Redeemed(OAuthGrant, Vec<OAuthGrantExtension>),
+    Expired(OAuthGrant),
+    Replayed(OAuthGrant),
+    Unknown,
+}
+
 pub struct PgAuthorizer {
     pool: Arc<db::Pool>,
+    subject_generator: Arc<SubjectGenerator>,
+    code_lifetime: TimeDelta,
+    clock_skew: TimeDelta,
+    source_ip: Option<IpAddr>,
 }
 
 impl PgAuthorizer {
-    pub fn new(pool: Arc<db::Pool>) -> Self {
-        Self { pool }
+    pub fn new(pool: Arc<db::Pool>, subject_generator: Arc<SubjectGenerator>) -> Self {
+        Self {
+            pool,
+            subject_generator,
+            code_lifetime: TimeDelta::minutes(10),
+            clock_skew: TimeDelta::seconds(30),
+            source_ip: None,
+        }
+    }
+
+    pub fn with_code_lifetime(mut self, code_lifetime: TimeDelta) -> Self {
+        self.code_lifetime = code_lifetime;
+        self
+    }
+
+    pub fn with_clock_skew(mut self, clock_skew: TimeDelta) -> Self {
+        self.clock_skew = clock_skew;
+        self
+    }
+
+    pub fn with_source_ip(mut self, source_ip: IpAddr) -> Self {
+        self.source_ip = Some(source_ip);
+        self
+    }
+
+    pub async fn authorize_grant(&self, grant: Grant) -> Result<String, Error> {
+        let mut conn = self.pool.get().await.map_err(|_| Error::Pool)?;
+
+        let client_cert_data = MtlsExtension::client_cert_data(&grant.extensions).ok_or(
+            Error::Certificate("grant carries no client certificate data"),
+        )?;
+        let owner_id = self
+            .subject_generator
+            .local_subject(&client_cert_data)
+            .ok_or(Error::Crypto("failed to derive the subject identifier"))?;
+        Span::current().record("subject", hashed_subject(&owner_id));
+
+        let code = Self::generate_code().ok_or(Error::Crypto("failed to generate a code"))?;
+        let derived_key =
+            Self::derive_key(&code).ok_or(Error::Crypto("failed to derive the grant key"))?;
+        let hashed_code = Self::hash_code(&code);
+        let pkce_challenge = PkceExtension::challenge(&grant.extensions);
+
+        let oauth_grant = OAuthGrant {
+            code_hash: hashed_code.clone(),
+            client_id: grant
+                .client_id
+                .parse()
+                .map_err(|_| Error::Validation("client id is not a UUID"))?,
+            owner_id,
+            redirect_uri: grant.redirect_uri.to_string(),
+            scope: grant.scope.to_string(),
+            until: grant.until.min(Utc::now() + self.code_lifetime),
+            consumed_at: None,
+            code_challenge: pkce_challenge.as_ref().map(|x| x.code_challenge.clone()),
+            code_challenge_method: pkce_challenge.map(|x| x.code_challenge_method),
+        };
+
+        let audit_record = AuditRecord::new(AuditEventType::CodeIssued)
+            .with_client_id(oauth_grant.client_id)
+            .with_subject(owner_id)
+            .with_client_cert_data(&client_cert_data)
+            .with_source_ip(self.source_ip);
+
+        let mut grant_extensions = vec![];
+        for extension in grant
+            .extensions
+            .public()
+            .filter(|x| x.0 != "pkce")
+            .filter_map(|x| x.1.map(|v| (x.0, v)))
+        {
+            let encrypted_value = crypto::encrypt_value(&derived_key, extension.1.as_bytes())
+                .ok_or(Error::Crypto("failed to encrypt a grant extension"))?;
+            let encoded_value = BASE64_STANDARD.encode(&encrypted_value);
+
+            grant_extensions.push(OAuthGrantExtension {
+                code_hash: hashed_code.clone(),
+                name: extension.0.to_owned(),
+                value: encoded_value,
+            });
+        }
+
+        conn.transaction::<_, Error, _>(|conn| {
+            async move {
+                let user =
+                    UserRepository::upsert_login(conn, owner_id, &client_cert_data, Utc::now())
+                        .await?;
+                if user.disabled {
+                    return Err(Error::AccessDenied("user is disabled"));
+                }
+
+                let is_pkce_required = auth_clients
+                    .filter(auth_clients_columns::id.eq(oauth_grant.client_id))
+                    .select(auth_clients_columns::require_pkce)
+                    .first::<bool>(conn)
+                    .await?;
+                if is_pkce_required && oauth_grant.code_challenge.is_none() {
+                    return Err(Error::AccessDenied("client requires PKCE"));
+                }
+
+                insert_into(oauth_grants)
+                    .values(&oauth_grant)
+                    .execute(conn)
+                    .await?;
+
+                insert_into(oauth_grant_extensions)
+                    .values(&grant_extensions)
+                    .execute(conn)
+                    .await?;
+
+                AuditLog::insert(conn, audit_record).await?;
+
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await?;
+
+        Ok(BASE64_URL_SAFE_NO_PAD.encode(code))
+    }
+
+    pub async fn extract_grant(&self, code: &str) -> Result<Grant, Error> {
+        let mut conn = self.pool.get().await.map_err(|_| Error::Pool)?;
+
+        let code = BASE64_URL_SAFE_NO_PAD
+            .decode(code)
+            .map_err(|_| Error::Validation("authorization code is not valid base64url"))?;
+        let derived_key =
+            Self::derive_key(&code).ok_or(Error::Crypto("failed to derive the grant key"))?;
+        let hashed_code = Self::hash_code(&code);
+        let now = Utc::now();
+        let clock_skew = self.clock_skew;
+        let source_ip = self.source_ip;
+
+        let redemption = conn
+            .transaction::<_, diesel::result::Error, _>(|conn| {
+                async move {
+                    let oauth_grant = diesel::update(
+                        oauth_grants
+                            .filter(code_hash.eq(&hashed_code))
+                            .filter(consumed_at.is_null()),
+                    )
+                    .set(consumed_at.eq(now))
+                    .returning(OAuthGrant::as_returning())
+                    .get_result(conn)
+                    .await
+                    .optional()?;
+
+                    let Some(oauth_grant) = oauth_grant else {
+                        let consumed_grant = oauth_grants
+                            .filter(code_hash.eq(&hashed_code))
+                            .select(OAuthGrant::as_select())
+                            .for_update()
+                            .first(conn)
+                            .await
+                            .optional()?;
+                        let Some(consumed_grant) = consumed_grant else {
+                            return Ok(Redemption::Unknown);
+                        };
+
+                        // RFC 6749 section 4.1.2: tokens issued from a reused code should be
+                        // revoked.
+                        TokenRepository::revoke_grant_code(conn, &hashed_code, now).await?;
+
+                        let replay = OAuthGrantReplay {
+                            id: Uuid::new_v4(),
+                            code_hash: hashed_code,
+                            attempted_at: now,
+                        };
+                        insert_into(oauth_grant_replays)
+                            .values(&replay)
+                            .execute(conn)
+                            .await?;
+
+                        let audit_record = AuditRecord::new(AuditEventType::ReplayDetected)
+                            .with_client_id(consumed_grant.client_id)
+                            .with_subject(consumed_grant.owner_id)
+                            .with_source_ip(source_ip);
+                        AuditLog::insert(conn, audit_record).await?;
+                        return Ok(Redemption::Replayed(consumed_grant));
+                    };
+
+                    if oauth_grant.until + clock_skew < now {
+                        return Ok(Redemption::Expired(oauth_grant));
+                    }
+
+                    let grant_extensions = OAuthGrantExtension::belonging_to(&oauth_grant)
+                        .select(OAuthGrantExtension::as_select())
+                        .load(conn)
+                        .await?;
+
+                    let audit_record = AuditRecord::new(AuditEventType::CodeRedeemed)
+                        .with_client_id(oauth_grant.client_id)
+                        .with_subject(oauth_grant.owner_id)
+                        .with_source_ip(source_ip);
+                    AuditLog::insert(conn, audit_record).await?;
+
+                    Ok(Redemption::Redeemed(oauth_grant, grant_extensions))
+                }
+                .scope_boxed()
+            })
+            .await?;
+
+        let (oauth_grant, grant_extensions) = match redemption {
+            Redemption::Redeemed(oauth_grant, grant_extensions) => {
+                Self::record_grant(&oauth_grant);
+                (oauth_grant, grant_extensions)
+            }
+            Redemption::Expired(oauth_grant) => {
+                Self::record_grant(&oauth_grant);
+                return Err(Error::Expired);
+            }
+            Redemption::Replayed(oauth_grant) => {
+                Self::record_grant(&oauth_grant);
+                return Err(Error::Replayed);
+            }
+            Redemption::Unknown => return Err(Error::NotFound("authorization code")),
+        };
+
+        let mut recovered_grant = Grant {
+            owner_id: oauth_grant.owner_id.to_string(),
+            client_id: oauth_grant.client_id.to_string(),
+            scope: oauth_grant
+                .scope
+                .parse()
+                .map_err(|_| Error::Validation("stored grant scope is malformed"))?,
+            redirect_uri: oauth_grant
+                .redirect_uri
+                .parse()
+                .map_err(|_| Error::Validation("stored grant redirect URI is malformed"))?,
+            // oxide-auth checks `until` again without any skew allowance.
+            until: oauth_grant.until + self.clock_skew,
+            extensions: Extensions::default(),
+        };
+        recovered_grant.extensions.set_raw(
+            "grant_code".to_owned(),
+            GrantCodeExtension::to_value(&oauth_grant.code_hash),
+        );
+
+        if let (Some(code_challenge), Some(code_challenge_method)) = (
+            oauth_grant.code_challenge,
+            oauth_grant.code_challenge_method,
+        ) {
+            let challenge = PkceChallenge {
+                code_challenge,
+                code_challenge_method,
+            };
+            let value = PkceExtension::to_value(&challenge)
+                .ok_or(Error::Validation("stored PKCE challenge is malformed"))?;
+            recovered_grant.extensions.set_raw("pkce".to_owned(), value);
+        }
+
+        for extension in grant_extensions {
+            let decrypted_value = crypto::decrypt_value(&derived_key, &extension.value)
+                .ok_or(Error::Crypto("failed to decrypt a grant extension"))?;
+            let decoded_value = String::from_utf8(decrypted_value)
+                .map_err(|_| Error::Validation("grant extension is not valid UTF-8"))?;
+
+            recovered_grant
+                .extensions
+                .set_raw(extension.name, Value::Public(Some(decoded_value)));
+        }
+
+        Ok(recovered_grant)
+    }
+
+    fn record_grant(oauth_grant: &OAuthGrant) {
+        let span = Span::current();
+        span.record("client_id", display(&oauth_grant.client_id));
+        span.record("subject", hashed_subject(&oauth_grant.owner_id));
+        span.record("scope", &oauth_grant.scope);
     }
 
     fn generate_code() -> Option<[u8; 32]> {
@@ -51,130 +345,259 @@ impl PgAuthorizer {
         hasher.update(code);
 
         let result = hasher.finalize();
-        BASE64_STANDARD.encode(&result)
+        BASE64_STANDARD.encode(result)
     }
+}
 
-    fn encrypt_value(key_bytes: &[u8], plaintext: &[u8]) -> Option<Vec<u8>> {
-        let key = Key::<Aes256Gcm>::from_slice(key_bytes);
-        let cipher = Aes256Gcm::new(&key);
-
-        let mut nonce_bytes = [0u8; 12];
-        OsRng.try_fill_bytes(&mut nonce_bytes).ok()?;
-
-        let nonce = Nonce::<Aes256Gcm>::from_slice(&nonce_bytes);
-        let ciphertext = cipher.encrypt(nonce, plaintext).ok()?;
+#[async_trait]
+impl Authorizer for PgAuthorizer {
+    #[instrument(
+        skip_all,
+        fields(client_id = %grant.client_id, subject, scope = %grant.scope, outcome)
+    )]
+    async fn authorize(&mut self, grant: Grant) -> Result<String, ()> {
+        let result = self.authorize_grant(grant).await;
+        record_outcome(&result);
+        result.map_err(|_| ())
+    }
 
-        let mut combined = Vec::with_capacity(nonce_bytes.len() + ciphertext.len());
-        combined.extend_from_slice(&nonce_bytes);
-        combined.extend_from_slice(&ciphertext);
+    #[instrument(skip_all, fields(client_id, subject, scope, outcome))]
+    async fn extract(&mut self, code: &str) -> Result<Option<Grant>, ()> {
+        let result = self.extract_grant(code).await;
+        record_outcome(&result);
+        match result {
+            Ok(grant) => Ok(Some(grant)),
+            Err(Error::NotFound(_) | Error::Replayed | Error::Expired) => Ok(None),
+            Err(_) => Err(()),
+        }
+    }
+}
 
-        Some(combined)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::fixtures;
+    use crate::db::models::{OAuthAccessToken, OAuthRefreshToken};
+    use crate::db::schema::oauth_access_tokens;
+    use crate::db::schema::oauth_grant_replays as oauth_grant_replays_columns;
+    use crate::oauth::client_cert_data::ClientCertData;
+    use chrono::DateTime;
+    use diesel_async::AsyncPgConnection;
+
+    const LEAF: &[u8] = include_bytes!("../../testdata/pki/leaf.pem");
+
+    struct Fixture {
+        pool: Arc<db::Pool>,
+        authorizer: PgAuthorizer,
+        client_id: Uuid,
+        owner_id: Uuid,
     }
 
-    fn decrypt_value(key_bytes: &[u8], value: &str) -> Option<Vec<u8>> {
-        let key = Key::<Aes256Gcm>::from_slice(key_bytes);
-        let cipher = Aes256Gcm::new(&key);
+    impl Fixture {
+        async fn new() -> Option<Self> {
+            let pool = fixtures::pool().await?;
+            let client_id = fixtures::insert_client(&pool, false).await;
+            let owner_id = fixtures::insert_user(&pool).await;
+            let subject_generator = Arc::new(SubjectGenerator::new(Uuid::new_v4().as_bytes()));
+            Some(Self {
+                authorizer: PgAuthorizer::new(pool.clone(), subject_generator),
+                pool,
+                client_id,
+                owner_id,
+            })
+        }
 
-        let combined = BASE64_STANDARD.decode(value).ok()?;
-        if combined.len() < 12 {
-            return None;
+        async fn grant(&self, until: DateTime<Utc>) -> (String, String) {
+            let code = PgAuthorizer::generate_code().unwrap();
+            let hashed_code = PgAuthorizer::hash_code(&code);
+            fixtures::insert_grant(
+                &self.pool,
+                &hashed_code,
+                self.client_id,
+                self.owner_id,
+                until,
+            )
+            .await;
+            (BASE64_URL_SAFE_NO_PAD.encode(code), hashed_code)
         }
 
-        let (nonce_bytes, ciphertext) = combined.split_at(12);
-        let nonce = Nonce::<Aes256Gcm>::from_slice(nonce_bytes);
+        async fn replays(&self, hashed_code: &str) -> i64 {
+            let mut conn = self.pool.get().await.unwrap();
+            oauth_grant_replays
+                .filter(oauth_grant_replays_columns::code_hash.eq(hashed_code))
+                .count()
+                .get_result(&mut conn)
+                .await
+                .unwrap()
+        }
 
-        Some(cipher.decrypt(nonce, ciphertext).ok()?)
+        async fn remove(self) {
+            fixtures::remove_client(&self.pool, self.client_id).await;
+            fixtures::remove_user(&self.pool, self.owner_id).await;
+        }
     }
-}
 
-#[async_trait]
-impl Authorizer for PgAuthorizer {
-    async fn authorize(&mut self, grant: Grant) -> Result<String, ()> {
-        let mut conn = self.pool.get().await.map_err(|_| ())?;
-
-        let code = Self::generate_code().ok_or(())?;
-        let derived_key = Self::derive_key(&code).ok_or(())?;
-        let hashed_code = Self::hash_code(&code);
+    async fn access_token_revoked_at(
+        conn: &mut AsyncPgConnection,
+        hashed_token: &str,
+    ) -> Option<DateTime<Utc>> {
+        oauth_access_tokens::table
+            .filter(oauth_access_tokens::token_hash.eq(hashed_token))
+            .select(oauth_access_tokens::revoked_at)
+            .first(conn)
+            .await
+            .unwrap()
+    }
 
-        let oauth_grant = OAuthGrant {
-            code_hash: hashed_code.clone(),
-            client_id: grant.client_id.parse().map_err(|_| ())?,
-            owner_id: Uuid::new_v4(),
-            redirect_uri: grant.redirect_uri.to_string(),
-            scope: grant.scope.to_string(),
-            until: grant.until,
+    #[tokio::test]
+    async fn second_extraction_is_refused_and_recorded() {
+        let Some(mut fixture) = Fixture::new().await else {
+            return;
         };
+        let (code, hashed_code) = fixture.grant(Utc::now() + TimeDelta::minutes(5)).await;
+
+        let first = fixture.authorizer.extract(&code).await;
+        let second = fixture.authorizer.extract(&code).await;
+        let replays = fixture.replays(&hashed_code).await;
+        fixture.remove().await;
+
+        let grant = first.unwrap().unwrap();
+        assert_eq!(
+            GrantCodeExtension::code_hash(&grant.extensions),
+            Some(hashed_code)
+        );
+        assert_eq!(second, Ok(None));
+        assert_eq!(replays, 1);
+    }
 
-        insert_into(oauth_grants)
-            .values(&vec![oauth_grant])
-            .get_result::<OAuthGrant>(&mut conn)
+    #[tokio::test]
+    async fn replay_revokes_tokens_issued_from_the_code() {
+        let Some(fixture) = Fixture::new().await else {
+            return;
+        };
+        let (code, hashed_code) = fixture.grant(Utc::now() + TimeDelta::minutes(5)).await;
+        fixture.authorizer.extract_grant(&code).await.unwrap();
+
+        let now = Utc::now();
+        let access_token = OAuthAccessToken {
+            token_hash: TokenRepository::hash_token(&Uuid::new_v4().to_string()),
+            jti: Uuid::new_v4(),
+            client_id: fixture.client_id,
+            subject: fixture.owner_id,
+            scope: "openid".to_owned(),
+            redirect_uri: "https://client.example/callback".to_owned(),
+            issued_at: now,
+            expires_at: now + TimeDelta::minutes(5),
+            revoked_at: None,
+            certificate_thumbprint: None,
+            refresh_family_id: None,
+            grant_code_hash: Some(hashed_code.clone()),
+        };
+        let refresh_token = OAuthRefreshToken {
+            token_hash: TokenRepository::hash_refresh_token(&Uuid::new_v4().to_string()),
+            family_id: Uuid::new_v4(),
+            client_id: fixture.client_id,
+            subject: fixture.owner_id,
+            scope: "openid".to_owned(),
+            redirect_uri: "https://client.example/callback".to_owned(),
+            certificate_thumbprint: None,
+            issued_at: now,
+            expires_at: now + TimeDelta::hours(1),
+            session_expires_at: now + TimeDelta::hours(1),
+            consumed_at: None,
+            revoked_at: None,
+            auth_time: None,
+            grant_code_hash: Some(hashed_code.clone()),
+        };
+        let mut conn = fixture.pool.get().await.unwrap();
+        TokenRepository::insert(&mut conn, &access_token)
             .await
-            .map_err(|_| ())?;
-
-        let mut grant_extensions = vec![];
-        for extension in grant.extensions.public().filter_map(|x| match x.1 {
-            None => None,
-            Some(v) => Some((x.0, v)),
-        }) {
-            let encrypted_value =
-                Self::encrypt_value(&derived_key, extension.1.as_bytes()).ok_or(())?;
-            let encoded_value = BASE64_STANDARD.encode(&encrypted_value);
+            .unwrap();
+        TokenRepository::insert_refresh(&mut conn, &refresh_token)
+            .await
+            .unwrap();
+
+        let replay = fixture.authorizer.extract_grant(&code).await;
+        let access_revoked_at = access_token_revoked_at(&mut conn, &access_token.token_hash).await;
+        let refresh_revoked_at =
+            TokenRepository::lock_refresh(&mut conn, &refresh_token.token_hash)
+                .await
+                .unwrap()
+                .unwrap()
+                .revoked_at;
+        drop(conn);
+        fixture.remove().await;
+
+        assert!(matches!(replay, Err(Error::Replayed)));
+        assert!(access_revoked_at.is_some());
+        assert!(refresh_revoked_at.is_some());
+    }
 
-            grant_extensions.push(OAuthGrantExtension {
-                code_hash: hashed_code.clone(),
-                name: extension.0.to_owned(),
-                value: encoded_value,
-            });
-        }
+    #[tokio::test]
+    async fn accepts_code_just_inside_the_clock_skew() {
+        let Some(mut fixture) = Fixture::new().await else {
+            return;
+        };
+        fixture.authorizer = fixture.authorizer.with_clock_skew(TimeDelta::seconds(30));
+        let until = Utc::now() - TimeDelta::seconds(25);
+        let (code, _) = fixture.grant(until).await;
 
-        insert_into(oauth_grant_extensions)
-            .values(&grant_extensions)
-            .get_results::<OAuthGrantExtension>(&mut conn)
-            .await
-            .map_err(|_| ())?;
+        let grant = fixture.authorizer.extract_grant(&code).await;
+        fixture.remove().await;
 
-        Ok(BASE64_URL_SAFE_NO_PAD.encode(&code))
+        assert!(grant.unwrap().until > Utc::now());
     }
 
-    async fn extract(&mut self, code: &str) -> Result<Option<Grant>, ()> {
-        let mut conn = self.pool.get().await.map_err(|_| ())?;
+    #[tokio::test]
+    async fn rejects_code_just_outside_the_clock_skew() {
+        let Some(mut fixture) = Fixture::new().await else {
+            return;
+        };
+        fixture.authorizer = fixture.authorizer.with_clock_skew(TimeDelta::seconds(30));
+        let until = Utc::now() - TimeDelta::seconds(35);
+        let (code, _) = fixture.grant(until).await;
 
-        let code = BASE64_URL_SAFE_NO_PAD.decode(code).map_err(|_| ())?;
-        let derived_key = Self::derive_key(&code).ok_or(())?;
-        let hashed_code = Self::hash_code(&code);
+        let grant = fixture.authorizer.extract_grant(&code).await;
+        fixture.remove().await;
+
+        assert!(matches!(grant, Err(Error::Expired)));
+    }
 
+    #[tokio::test]
+    async fn caps_code_validity_at_the_code_lifetime() {
+        let Some(mut fixture) = Fixture::new().await else {
+            return;
+        };
+        fixture.authorizer = fixture.authorizer.with_code_lifetime(TimeDelta::minutes(1));
+        let client_cert_data = ClientCertData::from_pem(LEAF).unwrap();
+        let mut extensions = Extensions::new();
+        extensions.set_raw(
+            "mtls".to_owned(),
+            Value::Public(Some(serde_json::to_string(&client_cert_data).unwrap())),
+        );
+        let grant = Grant {
+            owner_id: String::new(),
+            client_id: fixture.client_id.to_string(),
+            scope: "openid".parse().unwrap(),
+            redirect_uri: "https://client.example/callback".parse().unwrap(),
+            until: Utc::now() + TimeDelta::days(1),
+            extensions,
+        };
+
+        let code = fixture.authorizer.authorize_grant(grant).await.unwrap();
+        let code = BASE64_URL_SAFE_NO_PAD.decode(code).unwrap();
+        let mut conn = fixture.pool.get().await.unwrap();
         let oauth_grant = oauth_grants
-            .filter(code_hash.eq(hashed_code))
+            .filter(code_hash.eq(PgAuthorizer::hash_code(&code)))
             .select(OAuthGrant::as_select())
             .first(&mut conn)
             .await
-            .map_err(|_| ())?;
-
-        let grant_extensions = OAuthGrantExtension::belonging_to(&oauth_grant)
-            .select(OAuthGrantExtension::as_select())
-            .load(&mut conn)
-            .await
-            .map_err(|_| ())?;
-
-        let mut recovered_grant = Grant {
-            owner_id: oauth_grant.owner_id.to_string(),
-            client_id: oauth_grant.client_id.to_string(),
-            scope: oauth_grant.scope.parse().map_err(|_| ())?,
-            redirect_uri: oauth_grant.redirect_uri.parse().map_err(|_| ())?,
-            until: oauth_grant.until,
-            extensions: Extensions::default(),
-        };
-
-        for extension in grant_extensions {
-            let decrypted_value = Self::decrypt_value(&derived_key, &extension.value).ok_or(())?;
-            let decoded_value = BASE64_STANDARD.decode(&decrypted_value).map_err(|_| ())?;
-            let decoded_value = String::from_utf8(decoded_value).map_err(|_| ())?;
-
-            recovered_grant
-                .extensions
-                .set_raw(extension.name, Value::Public(Some(decoded_value)));
-        }
+            .unwrap();
+        drop(conn);
+        let pool = fixture.pool.clone();
+        fixture.remove().await;
+        fixtures::remove_user(&pool, oauth_grant.owner_id).await;
 
-        Ok(Some(recovered_grant))
+        assert!(oauth_grant.until <= Utc::now() + TimeDelta::minutes(1));
     }
 }
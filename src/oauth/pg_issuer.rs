@@ -1,145 +1,1046 @@
 use crate::db;
-use crate::db::models::{OAuthGrant, OAuthGrantExtension};
-use crate::db::schema::oauth_grants::code_hash;
+use crate::db::audit::{AuditEventType, AuditLog, AuditRecord};
+use crate::db::models::{AuthClient, OAuthAccessToken, OAuthRefreshToken, User};
+use crate::db::schema::auth_clients::dsl::auth_clients;
+use crate::db::schema::auth_clients::id;
+use crate::db::schema::oauth_grant_replays::dsl::oauth_grant_replays;
 use crate::db::schema::oauth_grants::dsl::oauth_grants;
+use crate::db::schema::{
+    oauth_grant_replays as oauth_grant_replays_columns, oauth_grants as oauth_grants_columns,
+};
+use crate::db::tokens::TokenRepository;
+use crate::db::users::UserRepository;
+use crate::error::{Error, record_outcome};
 use crate::oauth::client_cert_data::ClientCertData;
+use crate::oauth::grant_code_extension::GrantCodeExtension;
+use crate::oauth::introspection::{Confirmation, IntrospectionResponse};
+use crate::oauth::jwt_access_token::{ACCESS_TOKEN_FORMAT_JWT, JwtAccessTokenBuilder};
+use crate::oauth::key_manager::KeyManager;
+use crate::oauth::mtls_extension::MtlsExtension;
+use crate::oauth::oidc_extension::{OidcAuthentication, OidcExtension};
+use crate::oauth::subject::{SubjectGenerator, hashed_subject};
+use crate::oauth::token_response::IdToken;
+use crate::pki::revocation::RevocationChecker;
 use async_trait::async_trait;
-use chrono::Utc;
-use diesel::{BelongingToDsl, ExpressionMethods, QueryDsl, SelectableHelper};
-use diesel_async::RunQueryDsl;
+use chrono::{DateTime, TimeDelta, Utc};
+use diesel::dsl::{exists, select};
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, SelectableHelper};
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, RunQueryDsl};
 use openidconnect::core::CoreJwsSigningAlgorithm::RsaSsaPkcs1V15Sha256;
 use openidconnect::core::{CoreIdToken, CoreIdTokenClaims, CoreRsaPrivateSigningKey};
 use openidconnect::{
-    EmptyAdditionalClaims, EndUserFamilyName, EndUserGivenName, IssuerUrl, LanguageTag,
-    LocalizedClaim, StandardClaims, SubjectIdentifier,
+    AccessToken, Audience, EmptyAdditionalClaims, EndUserFamilyName, EndUserGivenName, IssuerUrl,
+    LanguageTag, LocalizedClaim, Nonce, StandardClaims, SubjectIdentifier,
 };
-use oxide_auth::primitives::grant::{Extensions, Grant, Value};
+use oxide_auth::primitives::grant::{Extensions, Grant};
 use oxide_auth::primitives::issuer::{IssuedToken, RefreshedToken, TokenType};
+use oxide_auth::primitives::scope::Scope;
 use oxide_auth_async::primitives::Issuer;
+use std::net::IpAddr;
 use std::sync::Arc;
-use std::time::Duration;
+use tracing::field::display;
+use tracing::{Span, instrument};
+use uuid::Uuid;
+
+pub struct IssuedTokens {
+    pub token: IssuedToken,
+    pub id_token: Option<String>,
+}
+
+pub struct RefreshedTokens {
+    pub token: RefreshedToken,
+    pub id_token: Option<String>,
+}
 
-pub struct PgIssuer<'a> {
-    rsa_signing_key: &'a CoreRsaPrivateSigningKey,
+struct IdTokenSubject<'a> {
+    subject: String,
+    user: &'a User,
+    client_id: &'a Uuid,
+    scope: &'a Scope,
+    until: DateTime<Utc>,
+    authentication: Option<&'a OidcAuthentication>,
+}
+
+enum Rotation {
+    Rotated,
+    Reused,
+    Revoked,
+    Expired,
+}
+
+pub struct PgIssuer {
+    key_manager: Arc<KeyManager>,
     pool: Arc<db::Pool>,
     issuer: String,
+    subject_generator: Arc<SubjectGenerator>,
+    revocation_checker: Option<Arc<RevocationChecker>>,
+    source_ip: Option<IpAddr>,
+    refresh_token_lifetime: TimeDelta,
+    session_lifetime: TimeDelta,
+    access_token_audience: Option<String>,
+    acr: Option<String>,
+    id_token: Option<IdToken>,
 }
 
-impl<'a> PgIssuer<'a> {
+impl PgIssuer {
     pub fn new(
-        rsa_signing_key: &'a CoreRsaPrivateSigningKey,
+        key_manager: Arc<KeyManager>,
         pool: Arc<db::Pool>,
         issuer: String,
+        subject_generator: Arc<SubjectGenerator>,
     ) -> Self {
         Self {
-            rsa_signing_key,
+            key_manager,
             pool,
             issuer,
+            subject_generator,
+            revocation_checker: None,
+            source_ip: None,
+            refresh_token_lifetime: TimeDelta::hours(8),
+            session_lifetime: TimeDelta::hours(12),
+            access_token_audience: None,
+            acr: None,
+            id_token: None,
         }
     }
 
-    async fn get_grant(&self, code: &str) -> Option<OAuthGrant> {
-        let mut conn = self.pool.get().await.ok()?;
-        oauth_grants
-            .filter(code_hash.eq(code))
-            .select(OAuthGrant::as_select())
+    pub fn with_revocation_checker(mut self, revocation_checker: Arc<RevocationChecker>) -> Self {
+        self.revocation_checker = Some(revocation_checker);
+        self
+    }
+
+    pub fn with_source_ip(mut self, source_ip: IpAddr) -> Self {
+        self.source_ip = Some(source_ip);
+        self
+    }
+
+    pub fn with_refresh_token_lifetime(mut self, refresh_token_lifetime: TimeDelta) -> Self {
+        self.refresh_token_lifetime = refresh_token_lifetime;
+        self
+    }
+
+    pub fn with_session_lifetime(mut self, session_lifetime: TimeDelta) -> Self {
+        self.session_lifetime = session_lifetime;
+        self
+    }
+
+    pub fn with_access_token_audience(mut self, access_token_audience: String) -> Self {
+        self.access_token_audience = Some(access_token_audience);
+        self
+    }
+
+    pub fn with_acr(mut self, acr: String) -> Self {
+        self.acr = Some(acr);
+        self
+    }
+
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    pub fn acr(&self) -> Option<&str> {
+        self.acr.as_deref()
+    }
+
+    pub fn take_id_token(&mut self) -> Option<IdToken> {
+        self.id_token.take()
+    }
+
+    async fn get_auth_client(&self, client_id: &Uuid) -> Result<AuthClient, Error> {
+        let mut conn = self.pool.get().await.map_err(|_| Error::Pool)?;
+        auth_clients
+            .filter(id.eq(client_id))
+            .select(AuthClient::as_select())
             .first(&mut conn)
             .await
-            .ok()
+            .optional()?
+            .ok_or(Error::NotFound("client"))
     }
 
-    async fn get_grant_extensions(&self, grant: &OAuthGrant) -> Option<Vec<OAuthGrantExtension>> {
-        let mut conn = self.pool.get().await.ok()?;
-        OAuthGrantExtension::belonging_to(grant)
-            .select(OAuthGrantExtension::as_select())
-            .load(&mut conn)
-            .await
-            .ok()
+    async fn get_user(&self, user_id: &Uuid) -> Result<User, Error> {
+        UserRepository::new(self.pool.clone())
+            .find(user_id)
+            .await?
+            .ok_or(Error::NotFound("user"))
     }
-}
 
-#[async_trait]
-impl Issuer for PgIssuer<'_> {
-    async fn issue(&mut self, grant: Grant) -> Result<IssuedToken, ()> {
-        let mtls_extension = grant
-            .extensions
-            .public()
-            .find_map(|x| if x.0 == "mtls" { x.1 } else { None })
-            .ok_or(())?;
-        let deserialized_mtls_data: ClientCertData =
-            serde_json::from_str(&mtls_extension).map_err(|_| ())?;
-        let issuer_url = IssuerUrl::new(self.issuer.clone()).map_err(|_| ())?;
-        let subject = SubjectIdentifier::new(grant.owner_id);
-        let standard_claims = StandardClaims::new(subject);
-
-        let mut localized_given_name = LocalizedClaim::new();
-        localized_given_name.insert(
-            Some(LanguageTag::new(deserialized_mtls_data.country.clone())),
-            EndUserGivenName::new(deserialized_mtls_data.given_name),
-        );
-
-        let mut localized_family_name = LocalizedClaim::new();
-        localized_family_name.insert(
-            Some(LanguageTag::new(deserialized_mtls_data.country)),
-            EndUserFamilyName::new(deserialized_mtls_data.surname),
-        );
-
-        let standard_claims = standard_claims.set_given_name(Some(localized_given_name));
-        let standard_claims = standard_claims.set_family_name(Some(localized_family_name));
+    async fn check_revocation(&self, client_cert_data: &ClientCertData) -> Result<(), Error> {
+        let Some(revocation_checker) = &self.revocation_checker else {
+            return Ok(());
+        };
+
+        let certificate = client_cert_data
+            .decode_certificate()
+            .map_err(|_| Error::Certificate("client certificate could not be decoded"))?
+            .ok_or(Error::Certificate("grant carries no client certificate"))?;
+        Ok(revocation_checker.ensure_not_revoked(&certificate).await?)
+    }
+
+    fn sign_id_token(
+        &self,
+        signing_key: &CoreRsaPrivateSigningKey,
+        id_token_subject: IdTokenSubject<'_>,
+        access_token: &str,
+    ) -> Result<String, Error> {
+        let IdTokenSubject {
+            subject,
+            user,
+            client_id,
+            scope,
+            until,
+            authentication,
+        } = id_token_subject;
+        let issuer_url = IssuerUrl::new(self.issuer.clone())
+            .map_err(|_| Error::Validation("issuer is not a valid URL"))?;
+        let mut standard_claims = StandardClaims::new(SubjectIdentifier::new(subject));
+        if Self::grants_profile(scope) {
+            let mut localized_given_name = LocalizedClaim::new();
+            localized_given_name.insert(
+                Some(LanguageTag::new(user.country.clone())),
+                EndUserGivenName::new(user.given_name.clone()),
+            );
+
+            let mut localized_family_name = LocalizedClaim::new();
+            localized_family_name.insert(
+                Some(LanguageTag::new(user.country.clone())),
+                EndUserFamilyName::new(user.surname.clone()),
+            );
+
+            standard_claims = standard_claims
+                .set_given_name(Some(localized_given_name))
+                .set_family_name(Some(localized_family_name));
+        }
 
-        let now = Utc::now();
         let id_token_claims = CoreIdTokenClaims::new(
             issuer_url,
-            vec![],
-            now,
-            grant.until,
+            vec![Audience::new(client_id.to_string())],
+            until,
+            Utc::now(),
             standard_claims,
             EmptyAdditionalClaims::default(),
-        );
+        )
+        .set_nonce(authentication.and_then(|x| x.nonce.clone().map(Nonce::new)))
+        .set_auth_time(authentication.map(|x| x.auth_time));
 
+        // The code is redeemed before the issuer runs, so `c_hash` only applies to ID tokens
+        // returned from the authorization endpoint, which this server does not issue.
         let id_token = CoreIdToken::new(
             id_token_claims,
-            self.rsa_signing_key,
+            signing_key,
             RsaSsaPkcs1V15Sha256,
-            None,
+            Some(&AccessToken::new(access_token.to_owned())),
             None,
         )
-        .map_err(|_| ())?;
+        .map_err(|_| Error::Crypto("failed to sign the ID token"))?;
+        Ok(id_token.to_string())
+    }
+
+    fn mint_access_token(
+        &self,
+        auth_client: &AuthClient,
+        access_token: JwtAccessTokenBuilder,
+    ) -> Result<String, Error> {
+        if auth_client.access_token_format != ACCESS_TOKEN_FORMAT_JWT {
+            return TokenRepository::generate_token()
+                .ok_or(Error::Crypto("failed to generate an access token"));
+        }
+
+        let access_token = match &self.access_token_audience {
+            Some(audience) => access_token.with_audience(audience.clone()),
+            None => access_token,
+        };
+        let access_token = match &self.acr {
+            Some(acr) => access_token.with_acr(acr.clone()),
+            None => access_token,
+        };
+        access_token.sign()
+    }
+
+    fn grants_openid(scope: &Scope) -> bool {
+        scope.iter().any(|x| x == "openid")
+    }
+
+    fn grants_profile(scope: &Scope) -> bool {
+        scope.iter().any(|x| x == "profile")
+    }
+
+    async fn revoke_reused_family(&self, refresh_token: &OAuthRefreshToken) -> Result<(), Error> {
+        let mut conn = self.pool.get().await.map_err(|_| Error::Pool)?;
+        let audit_record = AuditRecord::new(AuditEventType::RefreshTokenReused)
+            .with_client_id(refresh_token.client_id)
+            .with_subject(refresh_token.subject)
+            .with_source_ip(self.source_ip);
+        let family = refresh_token.family_id;
+
+        conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            async move {
+                TokenRepository::revoke_family(conn, &family, Utc::now()).await?;
+                AuditLog::insert(conn, audit_record).await?;
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await?;
+        Ok(())
+    }
+
+    fn record_grant(grant: &Grant) {
+        let span = Span::current();
+        span.record("client_id", &grant.client_id);
+        if let Ok(owner_id) = grant.owner_id.parse::<Uuid>() {
+            span.record("subject", hashed_subject(&owner_id));
+        }
+        span.record("scope", display(&grant.scope));
+    }
+
+    pub async fn issue_token(&self, grant: Grant) -> Result<IssuedTokens, Error> {
+        let deserialized_mtls_data = MtlsExtension::client_cert_data(&grant.extensions).ok_or(
+            Error::Certificate("grant carries no client certificate data"),
+        )?;
+        if let Err(err) = self.check_revocation(&deserialized_mtls_data).await {
+            let audit_record = AuditRecord::new(AuditEventType::CertificateRejected)
+                .with_client_cert_data(&deserialized_mtls_data)
+                .with_source_ip(self.source_ip);
+            AuditLog::new(self.pool.clone())
+                .record_failure(audit_record)
+                .await;
+            return Err(err);
+        }
+        let audit_record = AuditRecord::new(AuditEventType::TokenIssued)
+            .with_client_cert_data(&deserialized_mtls_data)
+            .with_source_ip(self.source_ip);
+        let certificate_thumbprint = MtlsExtension::certificate_thumbprint(&grant.extensions);
+        let grant_code_hash = GrantCodeExtension::code_hash(&grant.extensions)
+            .ok_or(Error::Validation("grant carries no authorization code"))?;
+
+        let owner_id = grant
+            .owner_id
+            .parse::<Uuid>()
+            .map_err(|_| Error::Validation("owner id is not a UUID"))?;
+        Span::current().record("subject", hashed_subject(&owner_id));
+        let user = self.get_user(&owner_id).await?;
+        if user.disabled {
+            return Err(Error::AccessDenied("user is disabled"));
+        }
+        let client_id = grant
+            .client_id
+            .parse::<Uuid>()
+            .map_err(|_| Error::Validation("client id is not a UUID"))?;
+        let auth_client = self.get_auth_client(&client_id).await?;
+        let subject = self
+            .subject_generator
+            .subject_for_client(&owner_id, &auth_client, &grant.redirect_uri)
+            .ok_or(Error::Validation("client subject type cannot be resolved"))?;
 
-        Ok(IssuedToken {
-            token: id_token.to_string(),
-            refresh: None,
-            until: grant.until,
-            token_type: TokenType::Bearer,
+        let authentication = OidcExtension::authentication(&grant.extensions);
+
+        let now = Utc::now();
+        let token_id = Uuid::new_v4();
+        let signing_keys = self.key_manager.current().await?;
+        let access_token = JwtAccessTokenBuilder::new(
+            signing_keys.active(),
+            self.issuer.clone(),
+            subject.clone(),
+            &client_id,
+        )
+        .with_token_id(&token_id)
+        .with_scope(&grant.scope)
+        .with_validity(now, grant.until);
+        let access_token = match &authentication {
+            Some(authentication) => access_token.with_auth_time(authentication.auth_time),
+            None => access_token,
+        };
+        let access_token = match &certificate_thumbprint {
+            Some(x5t_s256) => access_token.with_confirmation(Confirmation {
+                x5t_s256: x5t_s256.clone(),
+            }),
+            None => access_token,
+        };
+        let token = self.mint_access_token(&auth_client, access_token)?;
+        let id_token = if Self::grants_openid(&grant.scope) {
+            let id_token_subject = IdTokenSubject {
+                subject,
+                user: &user,
+                client_id: &client_id,
+                scope: &grant.scope,
+                until: grant.until,
+                authentication: authentication.as_ref(),
+            };
+            Some(self.sign_id_token(signing_keys.active(), id_token_subject, &token)?)
+        } else {
+            None
+        };
+
+        let (refresh, refresh_token) = if auth_client.allow_refresh_tokens {
+            let refresh = TokenRepository::generate_token()
+                .ok_or(Error::Crypto("failed to generate a refresh token"))?;
+            let session_expires_at = now + self.session_lifetime;
+            let refresh_token = OAuthRefreshToken {
+                token_hash: TokenRepository::hash_refresh_token(&refresh),
+                family_id: Uuid::new_v4(),
+                client_id,
+                subject: owner_id,
+                scope: grant.scope.to_string(),
+                redirect_uri: grant.redirect_uri.to_string(),
+                certificate_thumbprint: certificate_thumbprint.clone(),
+                issued_at: now,
+                expires_at: session_expires_at.min(now + self.refresh_token_lifetime),
+                session_expires_at,
+                consumed_at: None,
+                revoked_at: None,
+                auth_time: authentication.map(|x| x.auth_time),
+                grant_code_hash: Some(grant_code_hash.clone()),
+            };
+            (Some(refresh), Some(refresh_token))
+        } else {
+            (None, None)
+        };
+
+        let access_token = OAuthAccessToken {
+            token_hash: TokenRepository::hash_token(&token),
+            jti: token_id,
+            client_id,
+            subject: owner_id,
+            scope: grant.scope.to_string(),
+            redirect_uri: grant.redirect_uri.to_string(),
+            issued_at: now,
+            expires_at: grant.until,
+            revoked_at: None,
+            certificate_thumbprint,
+            refresh_family_id: refresh_token.as_ref().map(|x| x.family_id),
+            grant_code_hash: Some(grant_code_hash.clone()),
+        };
+        let audit_record = audit_record
+            .with_client_id(client_id)
+            .with_subject(owner_id);
+
+        let mut conn = self.pool.get().await.map_err(|_| Error::Pool)?;
+        conn.transaction::<_, Error, _>(|conn| {
+            async move {
+                // Serializes with the replay check in PgAuthorizer, so a code replayed
+                // while its tokens are being issued cannot miss them.
+                oauth_grants
+                    .filter(oauth_grants_columns::code_hash.eq(&grant_code_hash))
+                    .select(oauth_grants_columns::code_hash)
+                    .for_update()
+                    .first::<String>(conn)
+                    .await
+                    .optional()?;
+                let replayed = select(exists(
+                    oauth_grant_replays
+                        .filter(oauth_grant_replays_columns::code_hash.eq(&grant_code_hash)),
+                ))
+                .get_result::<bool>(conn)
+                .await?;
+                if replayed {
+                    return Err(Error::Replayed);
+                }
+
+                TokenRepository::insert(conn, &access_token).await?;
+                if let Some(refresh_token) = &refresh_token {
+                    TokenRepository::insert_refresh(conn, refresh_token).await?;
+                }
+                AuditLog::insert(conn, audit_record).await?;
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await?;
+
+        Ok(IssuedTokens {
+            token: IssuedToken {
+                token,
+                refresh,
+                until: grant.until,
+                token_type: TokenType::Bearer,
+            },
+            id_token,
         })
     }
 
-    async fn refresh(&mut self, _: &str, _: Grant) -> Result<RefreshedToken, ()> {
-        Err(())
+    pub async fn refresh_token(
+        &self,
+        refresh: &str,
+        grant: Grant,
+    ) -> Result<RefreshedTokens, Error> {
+        let previous = TokenRepository::new(self.pool.clone())
+            .find_refresh(refresh)
+            .await?
+            .ok_or(Error::NotFound("refresh token"))?;
+        Span::current().record("subject", hashed_subject(&previous.subject));
+        let user = self.get_user(&previous.subject).await?;
+        if user.disabled {
+            return Err(Error::AccessDenied("user is disabled"));
+        }
+        let auth_client = self.get_auth_client(&previous.client_id).await?;
+        let subject = self
+            .subject_generator
+            .subject_for_client(&user.id, &auth_client, &grant.redirect_uri)
+            .ok_or(Error::Validation("client subject type cannot be resolved"))?;
+
+        let now = Utc::now();
+        let until = grant.until.min(previous.session_expires_at);
+        let authentication = previous.auth_time.map(|auth_time| OidcAuthentication {
+            nonce: None,
+            auth_time,
+        });
+        let token_id = Uuid::new_v4();
+        let signing_keys = self.key_manager.current().await?;
+        let access_token = JwtAccessTokenBuilder::new(
+            signing_keys.active(),
+            self.issuer.clone(),
+            subject.clone(),
+            &previous.client_id,
+        )
+        .with_token_id(&token_id)
+        .with_scope(&grant.scope)
+        .with_validity(now, until);
+        let access_token = match previous.auth_time {
+            Some(auth_time) => access_token.with_auth_time(auth_time),
+            None => access_token,
+        };
+        let access_token = match &previous.certificate_thumbprint {
+            Some(x5t_s256) => access_token.with_confirmation(Confirmation {
+                x5t_s256: x5t_s256.clone(),
+            }),
+            None => access_token,
+        };
+        let token = self.mint_access_token(&auth_client, access_token)?;
+        let id_token = if Self::grants_openid(&grant.scope) {
+            let id_token_subject = IdTokenSubject {
+                subject,
+                user: &user,
+                client_id: &previous.client_id,
+                scope: &grant.scope,
+                until,
+                authentication: authentication.as_ref(),
+            };
+            Some(self.sign_id_token(signing_keys.active(), id_token_subject, &token)?)
+        } else {
+            None
+        };
+        let next_refresh = TokenRepository::generate_token()
+            .ok_or(Error::Crypto("failed to generate a refresh token"))?;
+
+        let hashed_refresh = previous.token_hash.clone();
+        let hashed_next_refresh = TokenRepository::hash_refresh_token(&next_refresh);
+        let access_token = OAuthAccessToken {
+            token_hash: TokenRepository::hash_token(&token),
+            jti: token_id,
+            client_id: previous.client_id,
+            subject: previous.subject,
+            scope: grant.scope.to_string(),
+            redirect_uri: previous.redirect_uri.clone(),
+            issued_at: now,
+            expires_at: until,
+            revoked_at: None,
+            certificate_thumbprint: previous.certificate_thumbprint.clone(),
+            refresh_family_id: Some(previous.family_id),
+            grant_code_hash: previous.grant_code_hash.clone(),
+        };
+        let refresh_lifetime = self.refresh_token_lifetime;
+        let source_ip = self.source_ip;
+
+        let mut conn = self.pool.get().await.map_err(|_| Error::Pool)?;
+        let rotation = conn
+            .transaction::<_, diesel::result::Error, _>(|conn| {
+                async move {
+                    let consumed =
+                        TokenRepository::consume_refresh(conn, &hashed_refresh, now).await?;
+                    let Some(consumed) = consumed else {
+                        let current = TokenRepository::lock_refresh(conn, &hashed_refresh).await?;
+                        let Some(current) = current else {
+                            return Ok(Rotation::Expired);
+                        };
+                        if current.consumed_at.is_none() {
+                            return Ok(if current.revoked_at.is_some() {
+                                Rotation::Revoked
+                            } else {
+                                Rotation::Expired
+                            });
+                        }
+                        TokenRepository::revoke_family(conn, &previous.family_id, now).await?;
+                        let audit_record = AuditRecord::new(AuditEventType::RefreshTokenReused)
+                            .with_client_id(previous.client_id)
+                            .with_subject(previous.subject)
+                            .with_source_ip(source_ip);
+                        AuditLog::insert(conn, audit_record).await?;
+                        return Ok(Rotation::Reused);
+                    };
+
+                    let next_refresh_token = OAuthRefreshToken {
+                        token_hash: hashed_next_refresh,
+                        family_id: consumed.family_id,
+                        client_id: consumed.client_id,
+                        subject: consumed.subject,
+                        scope: consumed.scope,
+                        redirect_uri: consumed.redirect_uri,
+                        certificate_thumbprint: consumed.certificate_thumbprint,
+                        issued_at: now,
+                        expires_at: consumed.session_expires_at.min(now + refresh_lifetime),
+                        session_expires_at: consumed.session_expires_at,
+                        consumed_at: None,
+                        revoked_at: None,
+                        auth_time: consumed.auth_time,
+                        grant_code_hash: consumed.grant_code_hash,
+                    };
+                    TokenRepository::insert(conn, &access_token).await?;
+                    TokenRepository::insert_refresh(conn, &next_refresh_token).await?;
+
+                    let audit_record = AuditRecord::new(AuditEventType::TokenRefreshed)
+                        .with_client_id(consumed.client_id)
+                        .with_subject(consumed.subject)
+                        .with_source_ip(source_ip);
+                    AuditLog::insert(conn, audit_record).await?;
+                    Ok(Rotation::Rotated)
+                }
+                .scope_boxed()
+            })
+            .await?;
+
+        match rotation {
+            Rotation::Rotated => Ok(RefreshedTokens {
+                token: RefreshedToken {
+                    token,
+                    refresh: Some(next_refresh),
+                    until,
+                    token_type: TokenType::Bearer,
+                },
+                id_token,
+            }),
+            Rotation::Reused => Err(Error::AccessDenied("refresh token has already been used")),
+            Rotation::Revoked => Err(Error::AccessDenied("refresh token has been revoked")),
+            Rotation::Expired => Err(Error::AccessDenied("refresh token has expired")),
+        }
     }
 
-    async fn recover_token(&mut self, code: &str) -> Result<Option<Grant>, ()> {
-        let base_grant = self.get_grant(code).await.ok_or(())?;
-        let grant_extensions = self.get_grant_extensions(&base_grant).await.ok_or(())?;
+    pub async fn recover_grant(&self, token: &str) -> Result<Grant, Error> {
+        let access_token = TokenRepository::new(self.pool.clone())
+            .find_active(token)
+            .await?
+            .ok_or(Error::NotFound("access token"))?;
 
-        let mut extensions = Extensions::new();
-        for grant_extension in grant_extensions {
+        Ok(Grant {
+            owner_id: access_token.subject.to_string(),
+            client_id: access_token.client_id.to_string(),
+            scope: access_token
+                .scope
+                .parse()
+                .map_err(|_| Error::Validation("stored token scope is malformed"))?,
+            redirect_uri: access_token
+                .redirect_uri
+                .parse()
+                .map_err(|_| Error::Validation("stored token redirect URI is malformed"))?,
+            until: access_token.expires_at,
+            extensions: Extensions::new(),
+        })
+    }
+
+    pub async fn recover_refresh_grant(&self, refresh: &str) -> Result<Grant, Error> {
+        let refresh_token = TokenRepository::new(self.pool.clone())
+            .find_refresh(refresh)
+            .await?
+            .ok_or(Error::NotFound("refresh token"))?;
+
+        if refresh_token.consumed_at.is_some() {
+            self.revoke_reused_family(&refresh_token).await?;
+            return Err(Error::AccessDenied("refresh token has already been used"));
+        }
+        if refresh_token.revoked_at.is_some() {
+            return Err(Error::AccessDenied("refresh token has been revoked"));
+        }
+        let now = Utc::now();
+        if refresh_token.session_expires_at <= now {
+            return Err(Error::AccessDenied("session has expired"));
+        }
+        if refresh_token.expires_at <= now {
+            return Err(Error::AccessDenied("refresh token has expired"));
+        }
+        if self.get_user(&refresh_token.subject).await?.disabled {
+            return Err(Error::AccessDenied("user is disabled"));
+        }
+
+        Ok(Grant {
+            owner_id: refresh_token.subject.to_string(),
+            client_id: refresh_token.client_id.to_string(),
+            scope: refresh_token
+                .scope
+                .parse()
+                .map_err(|_| Error::Validation("stored token scope is malformed"))?,
+            redirect_uri: refresh_token
+                .redirect_uri
+                .parse()
+                .map_err(|_| Error::Validation("stored token redirect URI is malformed"))?,
+            until: refresh_token.expires_at,
+            extensions: Extensions::new(),
+        })
+    }
+
+    pub async fn introspect_token(&self, token: &str) -> Result<IntrospectionResponse, Error> {
+        let Some(access_token) = TokenRepository::new(self.pool.clone())
+            .find_active(token)
+            .await?
+        else {
+            return Ok(IntrospectionResponse::inactive());
+        };
+
+        let user = self.get_user(&access_token.subject).await?;
+        if user.disabled {
+            return Ok(IntrospectionResponse::inactive());
+        }
+        let auth_client = self.get_auth_client(&access_token.client_id).await?;
+        let redirect_uri = access_token
+            .redirect_uri
+            .parse()
+            .map_err(|_| Error::Validation("stored token redirect URI is malformed"))?;
+        let subject = self
+            .subject_generator
+            .subject_for_client(&user.id, &auth_client, &redirect_uri)
+            .ok_or(Error::Validation("client subject type cannot be resolved"))?;
+        let grants_profile = access_token
+            .scope
+            .split_whitespace()
+            .any(|x| x == "profile");
+
+        Ok(IntrospectionResponse {
+            active: true,
+            scope: Some(access_token.scope),
+            client_id: Some(access_token.client_id.to_string()),
+            token_type: Some("Bearer".to_owned()),
+            exp: Some(access_token.expires_at.timestamp()),
+            iat: Some(access_token.issued_at.timestamp()),
+            sub: Some(subject),
+            iss: Some(self.issuer.clone()),
+            jti: Some(access_token.jti.to_string()),
+            cnf: access_token
+                .certificate_thumbprint
+                .map(|x| Confirmation { x5t_s256: x }),
+            given_name: grants_profile.then_some(user.given_name),
+            family_name: grants_profile.then_some(user.surname),
+            country: grants_profile.then_some(user.country),
+        })
+    }
+}
+
+#[async_trait]
+impl Issuer for PgIssuer {
+    #[instrument(
+        skip_all,
+        fields(client_id = %grant.client_id, subject, scope = %grant.scope, outcome)
+    )]
+    async fn issue(&mut self, grant: Grant) -> Result<IssuedToken, ()> {
+        self.id_token = None;
+        let result = self.issue_token(grant).await;
+        record_outcome(&result);
+        let tokens = result.map_err(|_| ())?;
+        self.id_token = tokens
+            .id_token
+            .map(|id_token| IdToken::new(tokens.token.token.clone(), id_token));
+        Ok(tokens.token)
+    }
+
+    #[instrument(
+        skip_all,
+        fields(client_id = %grant.client_id, subject, scope = %grant.scope, outcome)
+    )]
+    async fn refresh(&mut self, refresh: &str, grant: Grant) -> Result<RefreshedToken, ()> {
+        self.id_token = None;
+        let result = self.refresh_token(refresh, grant).await;
+        record_outcome(&result);
+        let tokens = result.map_err(|_| ())?;
+        self.id_token = tokens
+            .id_token
+            .map(|id_token| IdToken::new(tokens.token.token.clone(), id_token));
+        Ok(tokens.token)
+    }
+
+    #[instrument(skip_all, fields(client_id, subject, scope, outcome))]
+    async fn recover_token(&mut self, token: &str) -> Result<Option<Grant>, ()> {
+        let result = self.recover_grant(token).await;
+        if let Ok(grant) = &result {
+            Self::record_grant(grant);
+        }
+        record_outcome(&result);
+        match result {
+            Ok(grant) => Ok(Some(grant)),
+            Err(Error::NotFound(_)) => Ok(None),
+            Err(_) => Err(()),
+        }
+    }
+
+    #[instrument(skip_all, fields(client_id, subject, scope, outcome))]
+    async fn recover_refresh(&mut self, refresh: &str) -> Result<Option<Grant>, ()> {
+        let result = self.recover_refresh_grant(refresh).await;
+        if let Ok(grant) = &result {
+            Self::record_grant(grant);
+        }
+        record_outcome(&result);
+        match result {
+            Ok(grant) => Ok(Some(grant)),
+            Err(Error::NotFound(_) | Error::AccessDenied(_)) => Ok(None),
+            Err(_) => Err(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::fixtures;
+    use crate::db::models::OAuthGrantReplay;
+    use crate::db::schema::audit_events as audit_events_columns;
+    use crate::db::schema::audit_events::dsl::audit_events;
+    use crate::db::schema::oauth_access_tokens as oauth_access_tokens_columns;
+    use crate::db::schema::oauth_access_tokens::dsl::oauth_access_tokens;
+    use crate::db::schema::oauth_refresh_tokens as oauth_refresh_tokens_columns;
+    use crate::db::schema::oauth_refresh_tokens::dsl::oauth_refresh_tokens;
+    use base64::Engine;
+    use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+    use diesel::dsl::insert_into;
+    use oxide_auth::primitives::grant::Value;
+
+    const LEAF: &[u8] = include_bytes!("../../testdata/pki/leaf.pem");
+
+    struct Fixture {
+        pool: Arc<db::Pool>,
+        issuer: PgIssuer,
+        client_id: Uuid,
+        owner_id: Uuid,
+    }
+
+    impl Fixture {
+        async fn new(allow_refresh_tokens: bool) -> Option<Self> {
+            let pool = fixtures::pool().await?;
+            let client_id = fixtures::insert_client(&pool, allow_refresh_tokens).await;
+            let owner_id = fixtures::insert_user(&pool).await;
+            let key_manager = Arc::new(KeyManager::new(pool.clone(), fixtures::ENCRYPTION_KEY));
+            let subject_generator = Arc::new(SubjectGenerator::new(Uuid::new_v4().as_bytes()));
+            Some(Self {
+                issuer: PgIssuer::new(
+                    key_manager,
+                    pool.clone(),
+                    "https://idp.example".to_owned(),
+                    subject_generator,
+                ),
+                pool,
+                client_id,
+                owner_id,
+            })
+        }
+
+        async fn grant(&self, scope: &str) -> Grant {
+            let code_hash = Uuid::new_v4().to_string();
+            let until = Utc::now() + TimeDelta::minutes(5);
+            fixtures::insert_grant(&self.pool, &code_hash, self.client_id, self.owner_id, until)
+                .await;
+
+            let client_cert_data = ClientCertData::from_pem(LEAF).unwrap();
+            let mut extensions = Extensions::new();
             extensions.set_raw(
-                grant_extension.name,
-                Value::Public(Some(grant_extension.value)),
-            )
+                "mtls".to_owned(),
+                Value::Public(Some(serde_json::to_string(&client_cert_data).unwrap())),
+            );
+            extensions.set_raw(
+                "grant_code".to_owned(),
+                GrantCodeExtension::to_value(&code_hash),
+            );
+            Grant {
+                owner_id: self.owner_id.to_string(),
+                client_id: self.client_id.to_string(),
+                scope: scope.parse().unwrap(),
+                redirect_uri: "https://client.example/callback".parse().unwrap(),
+                until,
+                extensions,
+            }
         }
-        Ok(Some(Grant {
-            owner_id: base_grant.owner_id.to_string(),
-            client_id: base_grant.client_id.to_string(),
-            scope: base_grant.scope.parse().map_err(|_| ())?,
-            redirect_uri: base_grant.redirect_uri.parse().map_err(|_| ())?,
-            until: Utc::now() + Duration::from_mins(5),
-            extensions,
-        }))
+
+        async fn access_tokens(&self) -> i64 {
+            let mut conn = self.pool.get().await.unwrap();
+            oauth_access_tokens
+                .filter(oauth_access_tokens_columns::client_id.eq(self.client_id))
+                .count()
+                .get_result(&mut conn)
+                .await
+                .unwrap()
+        }
+
+        async fn remove(self) {
+            fixtures::remove_client(&self.pool, self.client_id).await;
+            fixtures::remove_user(&self.pool, self.owner_id).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_to_issue_tokens_for_a_replayed_code() {
+        let Some(fixture) = Fixture::new(false).await else {
+            return;
+        };
+        let grant = fixture.grant("openid").await;
+        let replay = OAuthGrantReplay {
+            id: Uuid::new_v4(),
+            code_hash: GrantCodeExtension::code_hash(&grant.extensions).unwrap(),
+            attempted_at: Utc::now(),
+        };
+        let mut conn = fixture.pool.get().await.unwrap();
+        insert_into(oauth_grant_replays)
+            .values(&replay)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        drop(conn);
+
+        let issued = fixture.issuer.issue_token(grant).await;
+        let access_tokens = fixture.access_tokens().await;
+        fixture.remove().await;
+
+        assert!(matches!(issued, Err(Error::Replayed)));
+        assert_eq!(access_tokens, 0);
+    }
+
+    fn id_token_claims(id_token: &str) -> serde_json::Value {
+        let payload = id_token.split('.').nth(1).unwrap();
+        serde_json::from_slice(&BASE64_URL_SAFE_NO_PAD.decode(payload).unwrap()).unwrap()
     }
 
-    async fn recover_refresh(&mut self, _: &str) -> Result<Option<Grant>, ()> {
-        Ok(None)
+    #[tokio::test]
+    async fn includes_names_in_id_token_only_with_profile_scope() {
+        let Some(fixture) = Fixture::new(false).await else {
+            return;
+        };
+        let openid = fixture
+            .issuer
+            .issue_token(fixture.grant("openid").await)
+            .await;
+        let profile = fixture
+            .issuer
+            .issue_token(fixture.grant("openid profile").await)
+            .await;
+        fixture.remove().await;
+
+        let openid = id_token_claims(&openid.unwrap().id_token.unwrap());
+        assert!(openid.get("given_name#ES").is_none());
+        assert!(openid.get("family_name#ES").is_none());
+        let profile = id_token_claims(&profile.unwrap().id_token.unwrap());
+        assert!(profile.get("given_name#ES").is_some());
+        assert!(profile.get("family_name#ES").is_some());
+    }
+
+    async fn refresh_token(fixture: &Fixture, refresh: &str) -> OAuthRefreshToken {
+        TokenRepository::new(fixture.pool.clone())
+            .find_refresh(refresh)
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn rotates_refresh_tokens() {
+        let Some(fixture) = Fixture::new(true).await else {
+            return;
+        };
+        let issued = fixture
+            .issuer
+            .issue_token(fixture.grant("openid").await)
+            .await;
+        let refresh = issued.unwrap().token.refresh.unwrap();
+        let grant = fixture
+            .issuer
+            .recover_refresh_grant(&refresh)
+            .await
+            .unwrap();
+
+        let refreshed = fixture.issuer.refresh_token(&refresh, grant).await;
+        let next_refresh = refreshed.unwrap().token.refresh.unwrap();
+        let previous = refresh_token(&fixture, &refresh).await;
+        let next = refresh_token(&fixture, &next_refresh).await;
+        fixture.remove().await;
+
+        assert_ne!(next_refresh, refresh);
+        assert!(previous.consumed_at.is_some());
+        assert_eq!(next.family_id, previous.family_id);
+        assert!(next.consumed_at.is_none());
+        assert!(next.revoked_at.is_none());
+    }
+
+    #[tokio::test]
+    async fn reuse_of_a_rotated_refresh_token_revokes_the_family() {
+        let Some(fixture) = Fixture::new(true).await else {
+            return;
+        };
+        let issued = fixture
+            .issuer
+            .issue_token(fixture.grant("openid").await)
+            .await;
+        let refresh = issued.unwrap().token.refresh.unwrap();
+        let grant = fixture
+            .issuer
+            .recover_refresh_grant(&refresh)
+            .await
+            .unwrap();
+        let next_refresh = fixture
+            .issuer
+            .refresh_token(&refresh, grant.clone())
+            .await
+            .unwrap()
+            .token
+            .refresh
+            .unwrap();
+
+        let reused = fixture.issuer.refresh_token(&refresh, grant).await;
+        let next = refresh_token(&fixture, &next_refresh).await;
+        let mut conn = fixture.pool.get().await.unwrap();
+        let reuse_events = audit_events
+            .filter(audit_events_columns::client_id.eq(fixture.client_id))
+            .filter(
+                audit_events_columns::event_type.eq(AuditEventType::RefreshTokenReused.as_str()),
+            )
+            .count()
+            .get_result::<i64>(&mut conn)
+            .await
+            .unwrap();
+        drop(conn);
+        fixture.remove().await;
+
+        assert!(matches!(reused, Err(Error::AccessDenied(_))));
+        assert!(next.revoked_at.is_some());
+        assert_eq!(reuse_events, 1);
+    }
+
+    #[tokio::test]
+    async fn expired_refresh_token_is_denied() {
+        let Some(fixture) = Fixture::new(true).await else {
+            return;
+        };
+        let issued = fixture
+            .issuer
+            .issue_token(fixture.grant("openid").await)
+            .await;
+        let refresh = issued.unwrap().token.refresh.unwrap();
+        let grant = fixture
+            .issuer
+            .recover_refresh_grant(&refresh)
+            .await
+            .unwrap();
+        let mut conn = fixture.pool.get().await.unwrap();
+        diesel::update(
+            oauth_refresh_tokens.filter(
+                oauth_refresh_tokens_columns::token_hash
+                    .eq(TokenRepository::hash_refresh_token(&refresh)),
+            ),
+        )
+        .set(oauth_refresh_tokens_columns::expires_at.eq(Utc::now() - TimeDelta::minutes(1)))
+        .execute(&mut conn)
+        .await
+        .unwrap();
+        drop(conn);
+
+        let refreshed = fixture.issuer.refresh_token(&refresh, grant).await;
+        fixture.remove().await;
+
+        assert!(matches!(
+            refreshed,
+            Err(Error::AccessDenied("refresh token has expired"))
+        ));
     }
 }
@@ -1,90 +1,185 @@
+use crate::crypto;
 use crate::db;
-use crate::db::models::{AuthClient, AuthClientAllowedScope, AuthClientRedirectUri};
+use crate::db::audit::{AuditEventType, AuditLog, AuditRecord};
+use crate::db::models::{
+    AuthClient, AuthClientAllowedScope, AuthClientRedirectUri, ClientAssertionJti,
+};
 use crate::db::schema::auth_client_redirect_uris::uri;
 use crate::db::schema::auth_clients::dsl::auth_clients;
 use crate::db::schema::auth_clients::id;
+use crate::db::schema::client_assertion_jtis::dsl::client_assertion_jtis;
+use crate::error::{Error, record_outcome};
+use crate::oauth::client_assertion::{
+    CLIENT_ASSERTION_TYPE_JWT_BEARER, ClientAssertion, JwksFetcher,
+};
+use crate::oauth::client_auth::{
+    ClientCredentials, TOKEN_ENDPOINT_AUTH_METHOD_CLIENT_SECRET_BASIC,
+    TOKEN_ENDPOINT_AUTH_METHOD_CLIENT_SECRET_JWT, TOKEN_ENDPOINT_AUTH_METHOD_CLIENT_SECRET_POST,
+    TOKEN_ENDPOINT_AUTH_METHOD_NONE, TOKEN_ENDPOINT_AUTH_METHOD_PRIVATE_KEY_JWT,
+    TOKEN_ENDPOINT_AUTH_METHOD_SELF_SIGNED_TLS_CLIENT_AUTH,
+    TOKEN_ENDPOINT_AUTH_METHOD_TLS_CLIENT_AUTH, verify_self_signed, verify_subject_dn,
+};
+use crate::pki::chain_validator::{ChainValidator, ValidatedChain};
 use argon2::{Argon2, PasswordHash, PasswordVerifier};
 use async_trait::async_trait;
-use diesel::{BelongingToDsl, QueryDsl};
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use chrono::TimeDelta;
+use diesel::dsl::insert_into;
+use diesel::{BelongingToDsl, OptionalExtension, QueryDsl};
 use diesel::{ExpressionMethods, SelectableHelper};
 use diesel_async::RunQueryDsl;
+use openidconnect::core::{CoreJsonWebKey, CoreJsonWebKeySet};
 use oxide_auth::endpoint::{PreGrant, Scope};
 use oxide_auth::primitives::prelude::ClientUrl;
 use oxide_auth::primitives::registrar::{BoundClient, RegisteredUrl, RegistrarError};
 use oxide_auth_async::primitives::Registrar;
 use std::borrow::Cow;
+use std::net::IpAddr;
 use std::sync::Arc;
+use tracing::field::display;
+use tracing::{Span, instrument};
 use uuid::Uuid;
 
 pub struct PgRegistrar {
     pool: Arc<db::Pool>,
+    source_ip: Option<IpAddr>,
+    chain_validator: Option<Arc<ChainValidator>>,
+    token_endpoint: Option<String>,
+    jwks_fetcher: Option<Arc<dyn JwksFetcher>>,
+    client_secret_key: Option<[u8; 32]>,
+    clock_skew: TimeDelta,
 }
 
 impl PgRegistrar {
-    fn new(pool: Arc<db::Pool>) -> Self {
-        Self { pool }
+    pub fn new(pool: Arc<db::Pool>) -> Self {
+        Self {
+            pool,
+            source_ip: None,
+            chain_validator: None,
+            token_endpoint: None,
+            jwks_fetcher: None,
+            client_secret_key: None,
+            clock_skew: TimeDelta::seconds(30),
+        }
+    }
+
+    pub fn with_source_ip(mut self, source_ip: IpAddr) -> Self {
+        self.source_ip = Some(source_ip);
+        self
+    }
+
+    pub fn with_chain_validator(mut self, chain_validator: Arc<ChainValidator>) -> Self {
+        self.chain_validator = Some(chain_validator);
+        self
+    }
+
+    pub fn with_token_endpoint(mut self, token_endpoint: String) -> Self {
+        self.token_endpoint = Some(token_endpoint);
+        self
+    }
+
+    pub fn with_jwks_fetcher(mut self, jwks_fetcher: Arc<dyn JwksFetcher>) -> Self {
+        self.jwks_fetcher = Some(jwks_fetcher);
+        self
+    }
+
+    pub fn with_client_secret_key(mut self, client_secret_key: [u8; 32]) -> Self {
+        self.client_secret_key = Some(client_secret_key);
+        self
+    }
+
+    pub fn with_clock_skew(mut self, clock_skew: TimeDelta) -> Self {
+        self.clock_skew = clock_skew;
+        self
     }
 
-    async fn get_auth_client(&self, client_id: &Uuid) -> Option<AuthClient> {
-        let mut conn = self.pool.get().await.ok()?;
+    pub fn token_endpoint_auth_methods(&self) -> Vec<&'static str> {
+        let mut methods = vec![
+            TOKEN_ENDPOINT_AUTH_METHOD_NONE,
+            TOKEN_ENDPOINT_AUTH_METHOD_CLIENT_SECRET_BASIC,
+            TOKEN_ENDPOINT_AUTH_METHOD_CLIENT_SECRET_POST,
+            TOKEN_ENDPOINT_AUTH_METHOD_SELF_SIGNED_TLS_CLIENT_AUTH,
+        ];
+        if self.chain_validator.is_some() {
+            methods.push(TOKEN_ENDPOINT_AUTH_METHOD_TLS_CLIENT_AUTH);
+        }
+        if self.token_endpoint.is_some() {
+            methods.push(TOKEN_ENDPOINT_AUTH_METHOD_PRIVATE_KEY_JWT);
+            if self.client_secret_key.is_some() {
+                methods.push(TOKEN_ENDPOINT_AUTH_METHOD_CLIENT_SECRET_JWT);
+            }
+        }
+        methods
+    }
+
+    pub fn encrypt_client_secret(
+        client_secret_key: &[u8; 32],
+        client_secret: &[u8],
+    ) -> Option<String> {
+        let encrypted_value = crypto::encrypt_value(client_secret_key, client_secret)?;
+        Some(BASE64_STANDARD.encode(encrypted_value))
+    }
+
+    async fn get_auth_client(&self, client_id: &Uuid) -> Result<AuthClient, Error> {
+        let mut conn = self.pool.get().await.map_err(|_| Error::Pool)?;
         auth_clients
             .filter(id.eq(client_id))
             .select(AuthClient::as_select())
             .first(&mut conn)
             .await
-            .ok()
+            .optional()?
+            .ok_or(Error::NotFound("client"))
     }
 
     async fn get_matching_client_redirect_uri(
         &self,
         client: &AuthClient,
         redirect_uri: &str,
-    ) -> Option<AuthClientRedirectUri> {
-        let mut conn = self.pool.get().await.ok()?;
+    ) -> Result<AuthClientRedirectUri, Error> {
+        let mut conn = self.pool.get().await.map_err(|_| Error::Pool)?;
         AuthClientRedirectUri::belonging_to(client)
             .filter(uri.eq(redirect_uri))
             .select(AuthClientRedirectUri::as_select())
             .first(&mut conn)
             .await
-            .ok()
+            .optional()?
+            .ok_or(Error::NotFound("redirect URI"))
     }
 
-    async fn get_client_scopes(&self, client: &AuthClient) -> Option<Vec<AuthClientAllowedScope>> {
-        let mut conn = self.pool.get().await.ok()?;
-        AuthClientAllowedScope::belonging_to(client)
+    async fn get_client_scopes(
+        &self,
+        client: &AuthClient,
+    ) -> Result<Vec<AuthClientAllowedScope>, Error> {
+        let mut conn = self.pool.get().await.map_err(|_| Error::Pool)?;
+        Ok(AuthClientAllowedScope::belonging_to(client)
             .select(AuthClientAllowedScope::as_select())
             .load(&mut conn)
-            .await
-            .ok()
+            .await?)
     }
-}
 
-#[async_trait]
-impl Registrar for PgRegistrar {
-    async fn bound_redirect<'a>(
-        &self,
-        bound: ClientUrl<'a>,
-    ) -> Result<BoundClient<'a>, RegistrarError> {
-        let client_id = &bound
-            .client_id
-            .parse::<Uuid>()
-            .map_err(|_| RegistrarError::PrimitiveError)?;
-        let client = self
-            .get_auth_client(client_id)
-            .await
-            .ok_or(RegistrarError::Unspecified)?;
-        let client_uri = bound.redirect_uri.ok_or(RegistrarError::PrimitiveError)?;
+    fn parse_client_id(client_id: &str) -> Result<Uuid, Error> {
+        client_id
+            .parse()
+            .map_err(|_| Error::Validation("client id is not a UUID"))
+    }
+
+    pub async fn bind_redirect<'a>(&self, bound: ClientUrl<'a>) -> Result<BoundClient<'a>, Error> {
+        let client_id = Self::parse_client_id(&bound.client_id)?;
+        let client = self.get_auth_client(&client_id).await?;
+        let client_uri = bound
+            .redirect_uri
+            .ok_or(Error::Validation("redirect URI is missing"))?;
 
         let matching_client_uri = self
             .get_matching_client_redirect_uri(&client, client_uri.as_str())
-            .await
-            .ok_or(RegistrarError::PrimitiveError)?;
+            .await?;
 
         let registered_uri = RegisteredUrl::Exact(
             matching_client_uri
                 .uri
                 .parse()
-                .map_err(|_| RegistrarError::PrimitiveError)?,
+                .map_err(|_| Error::Validation("registered redirect URI is malformed"))?,
         );
 
         Ok(BoundClient {
@@ -93,19 +188,13 @@ impl Registrar for PgRegistrar {
         })
     }
 
-    async fn negotiate<'a>(
+    pub async fn negotiate_grant<'a>(
         &self,
         client: BoundClient<'a>,
         scope: Option<Scope>,
-    ) -> Result<PreGrant, RegistrarError> {
-        let client_id = &client
-            .client_id
-            .parse::<Uuid>()
-            .map_err(|_| RegistrarError::PrimitiveError)?;
-        let auth_client = self
-            .get_auth_client(client_id)
-            .await
-            .ok_or(RegistrarError::Unspecified)?;
+    ) -> Result<PreGrant, Error> {
+        let client_id = Self::parse_client_id(&client.client_id)?;
+        let auth_client = self.get_auth_client(&client_id).await?;
 
         let scope = if let Some(scope) = scope {
             &scope.to_string()
@@ -113,50 +202,294 @@ impl Registrar for PgRegistrar {
             &auth_client.default_scope
         };
 
-        let client_scopes = self
-            .get_client_scopes(&auth_client)
-            .await
-            .ok_or(RegistrarError::PrimitiveError)?;
+        let client_scopes = self.get_client_scopes(&auth_client).await?;
 
         if client_scopes.iter().map(|x| &x.scope).any(|x| x == scope) {
             Ok(PreGrant {
                 client_id: client.client_id.into_owned(),
                 redirect_uri: client.redirect_uri.into_owned(),
-                scope: scope.parse().map_err(|_| RegistrarError::PrimitiveError)?,
+                scope: scope
+                    .parse()
+                    .map_err(|_| Error::Validation("scope is malformed"))?,
             })
         } else {
-            Err(RegistrarError::Unspecified)
+            Err(Error::AccessDenied("scope is not allowed for this client"))
         }
     }
 
-    async fn check(
+    pub async fn is_confidential(&self, client_id: &str) -> Result<bool, Error> {
+        let client_id = Self::parse_client_id(client_id)?;
+        Ok(self.get_auth_client(&client_id).await?.confidential)
+    }
+
+    pub fn authenticating(&self, credentials: ClientCredentials) -> AuthenticatingRegistrar<'_> {
+        AuthenticatingRegistrar {
+            registrar: self,
+            credentials,
+        }
+    }
+
+    pub async fn check_client(
         &self,
         client_id: &str,
-        passphrase: Option<&[u8]>,
-    ) -> Result<(), RegistrarError> {
-        let client_id = client_id
-            .parse::<Uuid>()
-            .map_err(|_| RegistrarError::PrimitiveError)?;
-        let client = self
-            .get_auth_client(&client_id)
-            .await
-            .ok_or(RegistrarError::Unspecified)?;
+        credentials: &ClientCredentials,
+    ) -> Result<(), Error> {
+        let client_id = Self::parse_client_id(client_id)?;
+        let result = self.authenticate_client(&client_id, credentials).await;
+        if let Err(Error::NotFound(_) | Error::AccessDenied(_)) = &result {
+            let audit_record = AuditRecord::new(AuditEventType::ClientAuthFailed)
+                .with_client_id(client_id)
+                .with_source_ip(self.source_ip);
+            AuditLog::new(self.pool.clone())
+                .record_failure(audit_record)
+                .await;
+        }
+        result
+    }
+
+    async fn authenticate_client(
+        &self,
+        client_id: &Uuid,
+        credentials: &ClientCredentials,
+    ) -> Result<(), Error> {
+        let client = self.get_auth_client(client_id).await?;
 
+        if credentials.client_assertion.is_some()
+            && credentials.client_assertion_type.as_deref()
+                != Some(CLIENT_ASSERTION_TYPE_JWT_BEARER)
+        {
+            return Err(Error::AccessDenied(
+                "client assertion type is not supported",
+            ));
+        }
         if !client.confidential {
             return Ok(());
         }
 
+        match client.token_endpoint_auth_method.as_str() {
+            TOKEN_ENDPOINT_AUTH_METHOD_CLIENT_SECRET_BASIC
+            | TOKEN_ENDPOINT_AUTH_METHOD_CLIENT_SECRET_POST => {
+                Self::verify_client_secret(&client, credentials.passphrase.as_deref())
+            }
+            TOKEN_ENDPOINT_AUTH_METHOD_TLS_CLIENT_AUTH => {
+                let subject_dn = client
+                    .tls_client_auth_subject_dn
+                    .as_ref()
+                    .ok_or(Error::AccessDenied("client has no registered subject DN"))?;
+                let chain = self.validate_client_certificate(credentials)?;
+                verify_subject_dn(&chain.certificate, subject_dn)
+            }
+            TOKEN_ENDPOINT_AUTH_METHOD_SELF_SIGNED_TLS_CLIENT_AUTH => {
+                let certificate = credentials
+                    .certificate
+                    .as_ref()
+                    .ok_or(Error::AccessDenied("client certificate is missing"))?;
+                verify_self_signed(
+                    certificate,
+                    client.tls_client_certificate_thumbprint.as_deref(),
+                    client.jwks.as_deref(),
+                )
+            }
+            TOKEN_ENDPOINT_AUTH_METHOD_PRIVATE_KEY_JWT
+            | TOKEN_ENDPOINT_AUTH_METHOD_CLIENT_SECRET_JWT => {
+                self.verify_client_assertion(&client, credentials).await
+            }
+            _ => Err(Error::AccessDenied(
+                "client authentication method is not supported",
+            )),
+        }
+    }
+
+    fn validate_client_certificate(
+        &self,
+        credentials: &ClientCredentials,
+    ) -> Result<ValidatedChain, Error> {
+        let certificate = credentials
+            .certificate
+            .as_ref()
+            .ok_or(Error::AccessDenied("client certificate is missing"))?;
+        let chain_validator = self
+            .chain_validator
+            .as_ref()
+            .ok_or(Error::Validation("no chain validator is configured"))?;
+        let intermediates = credentials
+            .intermediates
+            .iter()
+            .map(Vec::as_slice)
+            .collect::<Vec<_>>();
+        chain_validator
+            .validate_der(certificate, &intermediates)
+            .map_err(|_| Error::AccessDenied("client certificate chain is not trusted"))
+    }
+
+    async fn verify_client_assertion(
+        &self,
+        client: &AuthClient,
+        credentials: &ClientCredentials,
+    ) -> Result<(), Error> {
+        let client_assertion = credentials
+            .client_assertion
+            .as_ref()
+            .ok_or(Error::AccessDenied("client assertion is missing"))?;
+        let token_endpoint = self
+            .token_endpoint
+            .as_ref()
+            .ok_or(Error::Validation("token endpoint URL is not configured"))?;
+        let client_assertion = ClientAssertion::parse(client_assertion)?;
+
+        if client.token_endpoint_auth_method == TOKEN_ENDPOINT_AUTH_METHOD_PRIVATE_KEY_JWT {
+            let keys = self.get_client_keys(client).await?;
+            client_assertion.verify_signature(&keys)?;
+        } else {
+            let client_secret = self.get_client_secret(client)?;
+            client_assertion.verify_hmac(&client_secret)?;
+        }
+        client_assertion.validate_claims(
+            &client.id.to_string(),
+            token_endpoint,
+            self.clock_skew,
+        )?;
+
+        let jti = ClientAssertionJti {
+            client_id: client.id,
+            jti: client_assertion.claims.jti.clone(),
+            expires_at: client_assertion.expires_at()? + self.clock_skew,
+        };
+        let mut conn = self.pool.get().await.map_err(|_| Error::Pool)?;
+        let inserted = insert_into(client_assertion_jtis)
+            .values(&jti)
+            .on_conflict_do_nothing()
+            .execute(&mut conn)
+            .await?;
+        if inserted == 0 {
+            return Err(Error::AccessDenied(
+                "client assertion has already been used",
+            ));
+        }
+        Ok(())
+    }
+
+    async fn get_client_keys(&self, client: &AuthClient) -> Result<Vec<CoreJsonWebKey>, Error> {
+        let jwks = if let Some(jwks) = &client.jwks {
+            serde_json::from_str::<CoreJsonWebKeySet>(jwks)
+                .map_err(|_| Error::Validation("registered JWKS is malformed"))?
+        } else if let Some(jwks_uri) = &client.jwks_uri {
+            self.jwks_fetcher
+                .as_ref()
+                .ok_or(Error::Validation("no JWKS fetcher is configured"))?
+                .fetch(jwks_uri)
+                .await?
+        } else {
+            return Err(Error::AccessDenied("client has no registered keys"));
+        };
+        Ok(jwks.keys().clone())
+    }
+
+    fn get_client_secret(&self, client: &AuthClient) -> Result<Vec<u8>, Error> {
+        let client_secret_key = self
+            .client_secret_key
+            .as_ref()
+            .ok_or(Error::Validation("client secret key is not configured"))?;
+        let encrypted_client_secret = client
+            .encrypted_client_secret
+            .as_ref()
+            .ok_or(Error::AccessDenied("client has no registered secret"))?;
+        crypto::decrypt_value(client_secret_key, encrypted_client_secret)
+            .ok_or(Error::Crypto("failed to decrypt the client secret"))
+    }
+
+    fn verify_client_secret(client: &AuthClient, passphrase: Option<&[u8]>) -> Result<(), Error> {
         if let Some(passphrase) = passphrase
             && let Some(secret_hash) = &client.client_secret_hash
         {
             let argon2 = Argon2::default();
-            let secret_hash =
-                PasswordHash::new(secret_hash).map_err(|_| RegistrarError::PrimitiveError)?;
+            let secret_hash = PasswordHash::new(secret_hash)
+                .map_err(|_| Error::Crypto("stored client secret hash is malformed"))?;
             argon2
                 .verify_password(passphrase, &secret_hash)
-                .map_err(|_| RegistrarError::PrimitiveError)
+                .map_err(|_| Error::AccessDenied("client secret does not match"))
         } else {
-            Err(RegistrarError::Unspecified)
+            Err(Error::AccessDenied("client credentials are missing"))
+        }
+    }
+}
+
+#[async_trait]
+impl Registrar for PgRegistrar {
+    #[instrument(skip_all, fields(client_id = %bound.client_id, outcome))]
+    async fn bound_redirect<'a>(
+        &self,
+        bound: ClientUrl<'a>,
+    ) -> Result<BoundClient<'a>, RegistrarError> {
+        let result = self.bind_redirect(bound).await;
+        record_outcome(&result);
+        Ok(result?)
+    }
+
+    #[instrument(skip_all, fields(client_id = %client.client_id, scope, outcome))]
+    async fn negotiate<'a>(
+        &self,
+        client: BoundClient<'a>,
+        scope: Option<Scope>,
+    ) -> Result<PreGrant, RegistrarError> {
+        let result = self.negotiate_grant(client, scope).await;
+        if let Ok(pre_grant) = &result {
+            Span::current().record("scope", display(&pre_grant.scope));
+        }
+        record_outcome(&result);
+        Ok(result?)
+    }
+
+    #[instrument(skip_all, fields(client_id, outcome))]
+    async fn check(
+        &self,
+        client_id: &str,
+        passphrase: Option<&[u8]>,
+    ) -> Result<(), RegistrarError> {
+        Span::current().record("client_id", client_id);
+        let mut credentials = ClientCredentials::new();
+        credentials.passphrase = passphrase.map(<[u8]>::to_vec);
+        let result = self.check_client(client_id, &credentials).await;
+        record_outcome(&result);
+        Ok(result?)
+    }
+}
+
+pub struct AuthenticatingRegistrar<'a> {
+    registrar: &'a PgRegistrar,
+    credentials: ClientCredentials,
+}
+
+#[async_trait]
+impl Registrar for AuthenticatingRegistrar<'_> {
+    async fn bound_redirect<'a>(
+        &self,
+        bound: ClientUrl<'a>,
+    ) -> Result<BoundClient<'a>, RegistrarError> {
+        self.registrar.bound_redirect(bound).await
+    }
+
+    async fn negotiate<'a>(
+        &self,
+        client: BoundClient<'a>,
+        scope: Option<Scope>,
+    ) -> Result<PreGrant, RegistrarError> {
+        self.registrar.negotiate(client, scope).await
+    }
+
+    #[instrument(skip_all, fields(client_id, outcome))]
+    async fn check(
+        &self,
+        client_id: &str,
+        passphrase: Option<&[u8]>,
+    ) -> Result<(), RegistrarError> {
+        Span::current().record("client_id", client_id);
+        let mut credentials = self.credentials.clone();
+        if let Some(passphrase) = passphrase {
+            credentials.passphrase = Some(passphrase.to_vec());
         }
+        let result = self.registrar.check_client(client_id, &credentials).await;
+        record_outcome(&result);
+        Ok(result?)
     }
 }
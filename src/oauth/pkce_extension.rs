@@ -0,0 +1,185 @@
+use base64::Engine;
+use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+use oxide_auth::code_grant::accesstoken::Request as AccessTokenRequest;
+use oxide_auth::code_grant::authorization::Request as AuthorizationRequest;
+use oxide_auth::frontends::simple::extensions::{
+    AccessTokenAddon, AddonResult, AuthorizationAddon,
+};
+use oxide_auth::primitives::grant::{Extensions, GrantExtension, Value};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+pub const CODE_CHALLENGE_METHOD_S256: &str = "S256";
+pub const CODE_CHALLENGE_METHOD_PLAIN: &str = "plain";
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PkceChallenge {
+    pub code_challenge: String,
+    pub code_challenge_method: String,
+}
+
+impl PkceChallenge {
+    pub fn verify(&self, code_verifier: &str) -> bool {
+        if !Self::is_valid_value(code_verifier) {
+            return false;
+        }
+
+        let expected = match self.code_challenge_method.as_str() {
+            CODE_CHALLENGE_METHOD_S256 => {
+                BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+            }
+            CODE_CHALLENGE_METHOD_PLAIN => code_verifier.to_owned(),
+            _ => return false,
+        };
+
+        expected
+            .as_bytes()
+            .ct_eq(self.code_challenge.as_bytes())
+            .into()
+    }
+
+    fn is_valid_value(value: &str) -> bool {
+        (43..=128).contains(&value.len())
+            && value
+                .bytes()
+                .all(|x| x.is_ascii_alphanumeric() || b"-._~".contains(&x))
+    }
+}
+
+#[derive(Default)]
+pub struct PkceExtension {
+    allow_plain: bool,
+}
+
+impl PkceExtension {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_allow_plain(mut self, allow_plain: bool) -> Self {
+        self.allow_plain = allow_plain;
+        self
+    }
+
+    pub fn challenge(extensions: &Extensions) -> Option<PkceChallenge> {
+        let value = extensions
+            .public()
+            .find_map(|x| if x.0 == "pkce" { x.1 } else { None })?;
+        serde_json::from_str(value).ok()
+    }
+
+    pub fn to_value(challenge: &PkceChallenge) -> Option<Value> {
+        let json = serde_json::to_string(challenge).ok()?;
+        Some(Value::Public(Some(json)))
+    }
+}
+
+impl GrantExtension for PkceExtension {
+    fn identifier(&self) -> &'static str {
+        "pkce"
+    }
+}
+
+impl AuthorizationAddon for PkceExtension {
+    fn execute(&self, request: &dyn AuthorizationRequest) -> AddonResult {
+        let code_challenge = match request.extension("code_challenge") {
+            Some(code_challenge) => code_challenge.into_owned(),
+            None if request.extension("code_challenge_method").is_some() => {
+                return AddonResult::Err;
+            }
+            None => return AddonResult::Ok,
+        };
+        let code_challenge_method = request
+            .extension("code_challenge_method")
+            .map(|x| x.into_owned())
+            .unwrap_or_else(|| CODE_CHALLENGE_METHOD_PLAIN.to_owned());
+
+        match code_challenge_method.as_str() {
+            CODE_CHALLENGE_METHOD_S256 => {}
+            CODE_CHALLENGE_METHOD_PLAIN if self.allow_plain => {}
+            _ => return AddonResult::Err,
+        }
+        if !PkceChallenge::is_valid_value(&code_challenge) {
+            return AddonResult::Err;
+        }
+
+        let challenge = PkceChallenge {
+            code_challenge,
+            code_challenge_method,
+        };
+        match Self::to_value(&challenge) {
+            Some(value) => AddonResult::Data(value),
+            None => AddonResult::Err,
+        }
+    }
+}
+
+impl AccessTokenAddon for PkceExtension {
+    fn execute(&self, request: &dyn AccessTokenRequest, code_data: Option<Value>) -> AddonResult {
+        let code_verifier = request.extension("code_verifier");
+        let challenge = match code_data {
+            Some(Value::Public(Some(json))) => match serde_json::from_str::<PkceChallenge>(&json) {
+                Ok(challenge) => Some(challenge),
+                Err(_) => return AddonResult::Err,
+            },
+            Some(_) => return AddonResult::Err,
+            None => None,
+        };
+
+        match (challenge, code_verifier) {
+            (None, None) => AddonResult::Ok,
+            (Some(challenge), Some(code_verifier)) if challenge.verify(&code_verifier) => {
+                AddonResult::Ok
+            }
+            _ => AddonResult::Err,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 7636, appendix B.
+    const CODE_VERIFIER: &str = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+    const CODE_CHALLENGE: &str = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+
+    fn challenge(code_challenge: &str, code_challenge_method: &str) -> PkceChallenge {
+        PkceChallenge {
+            code_challenge: code_challenge.to_owned(),
+            code_challenge_method: code_challenge_method.to_owned(),
+        }
+    }
+
+    #[test]
+    fn verifies_s256_challenge() {
+        let challenge = challenge(CODE_CHALLENGE, CODE_CHALLENGE_METHOD_S256);
+        assert!(challenge.verify(CODE_VERIFIER));
+        assert!(!challenge.verify(&CODE_VERIFIER.replace('d', "e")));
+    }
+
+    #[test]
+    fn verifies_plain_challenge() {
+        let challenge = challenge(CODE_VERIFIER, CODE_CHALLENGE_METHOD_PLAIN);
+        assert!(challenge.verify(CODE_VERIFIER));
+        assert!(!challenge.verify(CODE_CHALLENGE));
+    }
+
+    #[test]
+    fn rejects_unknown_method() {
+        assert!(!challenge(CODE_VERIFIER, "S512").verify(CODE_VERIFIER));
+    }
+
+    #[test]
+    fn rejects_malformed_verifier() {
+        let short = &CODE_VERIFIER[..42];
+        assert!(!challenge(short, CODE_CHALLENGE_METHOD_PLAIN).verify(short));
+
+        let long = "a".repeat(129);
+        assert!(!challenge(&long, CODE_CHALLENGE_METHOD_PLAIN).verify(&long));
+
+        let invalid = format!("{}+", &CODE_VERIFIER[..42]);
+        assert!(!challenge(&invalid, CODE_CHALLENGE_METHOD_PLAIN).verify(&invalid));
+    }
+}
@@ -0,0 +1,208 @@
+use crate::db::models::AuthClient;
+use crate::oauth::client_cert_data::ClientCertData;
+use base64::Engine;
+use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use url::Url;
+use uuid::{Builder, Uuid};
+
+pub const SUBJECT_TYPE_PUBLIC: &str = "public";
+pub const SUBJECT_TYPE_PAIRWISE: &str = "pairwise";
+
+pub(crate) fn hashed_subject(local_subject: &Uuid) -> String {
+    let digest = Sha256::digest(local_subject.as_bytes());
+    BASE64_URL_SAFE_NO_PAD.encode(&digest[..12])
+}
+
+pub struct SubjectGenerator {
+    secret: Vec<u8>,
+}
+
+impl SubjectGenerator {
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            secret: secret.into(),
+        }
+    }
+
+    pub fn local_subject(&self, client_cert_data: &ClientCertData) -> Option<Uuid> {
+        let digest = self.digest(&[b"dnie-subject-v1", client_cert_data.nif().as_bytes()])?;
+
+        let mut bytes = [0u8; 16];
+        bytes.copy_from_slice(&digest[..16]);
+        Some(Builder::from_custom_bytes(bytes).into_uuid())
+    }
+
+    pub fn pairwise_subject(
+        &self,
+        local_subject: &Uuid,
+        sector_identifier: &str,
+    ) -> Option<String> {
+        let digest = self.digest(&[
+            b"dnie-pairwise-subject-v1",
+            sector_identifier.as_bytes(),
+            local_subject.as_bytes(),
+        ])?;
+        Some(BASE64_URL_SAFE_NO_PAD.encode(digest))
+    }
+
+    pub fn subject_for_client(
+        &self,
+        local_subject: &Uuid,
+        client: &AuthClient,
+        redirect_uri: &Url,
+    ) -> Option<String> {
+        match client.subject_type.as_str() {
+            SUBJECT_TYPE_PUBLIC => Some(local_subject.to_string()),
+            SUBJECT_TYPE_PAIRWISE => {
+                let sector_identifier = match &client.sector_identifier {
+                    Some(sector_identifier) => sector_identifier.as_str(),
+                    None => redirect_uri.host_str()?,
+                };
+                self.pairwise_subject(local_subject, sector_identifier)
+            }
+            _ => None,
+        }
+    }
+
+    fn digest(&self, parts: &[&[u8]]) -> Option<Vec<u8>> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).ok()?;
+        for part in parts {
+            mac.update(&(part.len() as u32).to_be_bytes());
+            mac.update(part);
+        }
+        Some(mac.finalize().into_bytes().to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(subject_type: &str, sector_identifier: Option<&str>) -> AuthClient {
+        AuthClient {
+            id: Uuid::nil(),
+            client_secret_hash: None,
+            default_scope: "openid".to_owned(),
+            confidential: false,
+            subject_type: subject_type.to_owned(),
+            sector_identifier: sector_identifier.map(str::to_owned),
+            require_pkce: true,
+            allow_refresh_tokens: false,
+            access_token_format: "opaque".to_owned(),
+            token_endpoint_auth_method: "none".to_owned(),
+            tls_client_auth_subject_dn: None,
+            tls_client_certificate_thumbprint: None,
+            jwks: None,
+            jwks_uri: None,
+            encrypted_client_secret: None,
+        }
+    }
+
+    fn redirect_uri(uri: &str) -> Url {
+        uri.parse().unwrap()
+    }
+
+    #[test]
+    fn local_subject_depends_on_nif_and_secret() {
+        let client_cert_data =
+            ClientCertData::from_pem(include_bytes!("../../testdata/pki/leaf.pem")).unwrap();
+        let subject = SubjectGenerator::new(b"secret".to_vec())
+            .local_subject(&client_cert_data)
+            .unwrap();
+
+        assert_eq!(
+            SubjectGenerator::new(b"secret".to_vec()).local_subject(&client_cert_data),
+            Some(subject)
+        );
+        assert_ne!(
+            SubjectGenerator::new(b"other".to_vec()).local_subject(&client_cert_data),
+            Some(subject)
+        );
+    }
+
+    #[test]
+    fn public_subject_is_local_subject() {
+        let local_subject = Uuid::new_v4();
+        let subject = SubjectGenerator::new(b"secret".to_vec()).subject_for_client(
+            &local_subject,
+            &client(SUBJECT_TYPE_PUBLIC, None),
+            &redirect_uri("https://rp.example/cb"),
+        );
+        assert_eq!(subject, Some(local_subject.to_string()));
+    }
+
+    #[test]
+    fn pairwise_subject_is_stable_per_sector() {
+        let generator = SubjectGenerator::new(b"secret".to_vec());
+        let local_subject = Uuid::new_v4();
+        let client = client(SUBJECT_TYPE_PAIRWISE, None);
+
+        let subject = generator
+            .subject_for_client(
+                &local_subject,
+                &client,
+                &redirect_uri("https://rp.example/cb"),
+            )
+            .unwrap();
+        assert_ne!(subject, local_subject.to_string());
+        assert_eq!(
+            generator.subject_for_client(
+                &local_subject,
+                &client,
+                &redirect_uri("https://rp.example/other")
+            ),
+            Some(subject.clone())
+        );
+        assert_ne!(
+            generator.subject_for_client(
+                &local_subject,
+                &client,
+                &redirect_uri("https://other.example/cb")
+            ),
+            Some(subject.clone())
+        );
+        assert_ne!(
+            generator.subject_for_client(
+                &Uuid::new_v4(),
+                &client,
+                &redirect_uri("https://rp.example/cb")
+            ),
+            Some(subject)
+        );
+    }
+
+    #[test]
+    fn pairwise_subject_prefers_sector_identifier() {
+        let generator = SubjectGenerator::new(b"secret".to_vec());
+        let local_subject = Uuid::new_v4();
+        let client = client(SUBJECT_TYPE_PAIRWISE, Some("sector.example"));
+
+        let first = generator.subject_for_client(
+            &local_subject,
+            &client,
+            &redirect_uri("https://rp.example/cb"),
+        );
+        let second = generator.subject_for_client(
+            &local_subject,
+            &client,
+            &redirect_uri("https://other.example/cb"),
+        );
+        assert_eq!(first, second);
+        assert_eq!(
+            first,
+            generator.pairwise_subject(&local_subject, "sector.example")
+        );
+    }
+
+    #[test]
+    fn unknown_subject_type_yields_no_subject() {
+        let subject = SubjectGenerator::new(b"secret".to_vec()).subject_for_client(
+            &Uuid::new_v4(),
+            &client("opaque", None),
+            &redirect_uri("https://rp.example/cb"),
+        );
+        assert_eq!(subject, None);
+    }
+}
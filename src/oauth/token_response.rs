@@ -0,0 +1,49 @@
+use axum::body::{Body, to_bytes};
+use axum::http::header::CONTENT_LENGTH;
+use axum::response::{IntoResponse, Response};
+use oxide_auth_axum::{OAuthResponse, WebError};
+use serde_json::{Map, Value};
+
+const MAX_TOKEN_RESPONSE_SIZE: usize = 64 * 1024;
+
+pub struct IdToken {
+    access_token: String,
+    id_token: String,
+}
+
+impl IdToken {
+    pub fn new(access_token: String, id_token: String) -> Self {
+        Self {
+            access_token,
+            id_token,
+        }
+    }
+}
+
+pub async fn with_id_token(
+    response: OAuthResponse,
+    id_token: Option<IdToken>,
+) -> Result<Response, WebError> {
+    let response = response.into_response();
+    let Some(id_token) = id_token else {
+        return Ok(response);
+    };
+    if !response.status().is_success() {
+        return Ok(response);
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let body = to_bytes(body, MAX_TOKEN_RESPONSE_SIZE)
+        .await
+        .map_err(|_| WebError::InternalError(None))?;
+    let mut json: Map<String, Value> =
+        serde_json::from_slice(&body).map_err(|_| WebError::InternalError(None))?;
+    if json.get("access_token").and_then(Value::as_str) != Some(id_token.access_token.as_str()) {
+        return Err(WebError::InternalError(None));
+    }
+    json.insert("id_token".to_owned(), Value::String(id_token.id_token));
+    let body = serde_json::to_vec(&json).map_err(|_| WebError::InternalError(None))?;
+
+    parts.headers.remove(CONTENT_LENGTH);
+    Ok(Response::from_parts(parts, Body::from(body)))
+}
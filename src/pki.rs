@@ -0,0 +1,7 @@
+pub mod chain_validator;
+pub mod crl;
+pub mod ocsp;
+pub mod revocation;
+pub(crate) mod signature;
+pub mod transport;
+pub mod trust_store;
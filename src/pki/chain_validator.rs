@@ -0,0 +1,352 @@
+use crate::pki::signature::verify_certificate_signature;
+use crate::pki::trust_store::TrustStore;
+use chrono::{DateTime, Utc};
+use const_oid::db::rfc5280::{ANY_EXTENDED_KEY_USAGE, ID_KP_CLIENT_AUTH};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use x509_cert::Certificate;
+use x509_cert::der::Decode;
+use x509_cert::ext::pkix::{BasicConstraints, ExtendedKeyUsage, KeyUsage};
+
+const DEFAULT_MAX_DEPTH: usize = 4;
+
+#[derive(Debug)]
+pub enum ChainError {
+    Io(std::io::Error),
+    InvalidCertificate(x509_cert::der::Error),
+    NoCertificates,
+    NotYetValid,
+    Expired,
+    UntrustedIssuer,
+    InvalidSignature,
+    NotCertificateAuthority,
+    PathLengthExceeded,
+    PathTooLong,
+    KeyUsage(&'static str),
+}
+
+impl fmt::Display for ChainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "unable to read trust store: {err}"),
+            Self::InvalidCertificate(err) => write!(f, "invalid certificate encoding: {err}"),
+            Self::NoCertificates => write!(f, "PEM input contains no certificates"),
+            Self::NotYetValid => write!(f, "certificate is not yet valid"),
+            Self::Expired => write!(f, "certificate has expired"),
+            Self::UntrustedIssuer => write!(f, "certificate was not issued by a trusted CA"),
+            Self::InvalidSignature => write!(f, "certificate signature is invalid"),
+            Self::NotCertificateAuthority => write!(f, "issuer is not a certificate authority"),
+            Self::PathLengthExceeded => write!(f, "issuer path length constraint exceeded"),
+            Self::PathTooLong => write!(f, "certificate path is too long"),
+            Self::KeyUsage(usage) => write!(f, "certificate is not valid for {usage}"),
+        }
+    }
+}
+
+impl std::error::Error for ChainError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::InvalidCertificate(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+pub struct ValidatedChain {
+    pub certificate: Certificate,
+    pub path: Vec<Certificate>,
+}
+
+impl ValidatedChain {
+    pub fn issuer(&self) -> &Certificate {
+        &self.path[0]
+    }
+
+    pub fn anchor(&self) -> &Certificate {
+        &self.path[self.path.len() - 1]
+    }
+}
+
+pub struct ChainValidator {
+    trust_store: Arc<TrustStore>,
+    max_depth: usize,
+}
+
+impl ChainValidator {
+    pub fn new(trust_store: Arc<TrustStore>) -> Self {
+        Self {
+            trust_store,
+            max_depth: DEFAULT_MAX_DEPTH,
+        }
+    }
+
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    pub fn trust_store(&self) -> &TrustStore {
+        &self.trust_store
+    }
+
+    pub fn validate_der(
+        &self,
+        certificate: &[u8],
+        intermediates: &[&[u8]],
+    ) -> Result<ValidatedChain, ChainError> {
+        let certificate =
+            Certificate::from_der(certificate).map_err(ChainError::InvalidCertificate)?;
+        let intermediates = intermediates
+            .iter()
+            .map(|x| Certificate::from_der(x))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(ChainError::InvalidCertificate)?;
+
+        self.validate(certificate, &intermediates)
+    }
+
+    pub fn validate(
+        &self,
+        certificate: Certificate,
+        intermediates: &[Certificate],
+    ) -> Result<ValidatedChain, ChainError> {
+        self.validate_at(certificate, intermediates, Utc::now())
+    }
+
+    pub fn validate_at(
+        &self,
+        certificate: Certificate,
+        intermediates: &[Certificate],
+        time: DateTime<Utc>,
+    ) -> Result<ValidatedChain, ChainError> {
+        let time = Duration::from_secs(time.timestamp().max(0) as u64);
+
+        Self::check_validity(&certificate, time)?;
+        Self::check_client_key_usage(&certificate)?;
+
+        let path = self.build_path(&certificate, intermediates, 0, time)?;
+        Ok(ValidatedChain { certificate, path })
+    }
+
+    fn build_path(
+        &self,
+        certificate: &Certificate,
+        intermediates: &[Certificate],
+        depth: usize,
+        time: Duration,
+    ) -> Result<Vec<Certificate>, ChainError> {
+        let issuer_name = &certificate.tbs_certificate.issuer;
+
+        let mut last_error = ChainError::UntrustedIssuer;
+        for anchor in self.trust_store.anchors_for(issuer_name) {
+            if let Err(err) = Self::check_validity(anchor, time) {
+                last_error = err;
+            } else if verify_certificate_signature(certificate, anchor) {
+                return Ok(vec![anchor.clone()]);
+            } else {
+                last_error = ChainError::InvalidSignature;
+            }
+        }
+
+        if depth >= self.max_depth {
+            return Err(ChainError::PathTooLong);
+        }
+
+        let candidates = intermediates
+            .iter()
+            .filter(|x| &x.tbs_certificate.subject == issuer_name)
+            .chain(self.trust_store.intermediates_for(issuer_name));
+
+        for candidate in candidates {
+            if let Err(err) = Self::check_validity(candidate, time)
+                .and_then(|_| Self::check_certificate_authority(candidate, depth))
+            {
+                last_error = err;
+                continue;
+            }
+
+            if !verify_certificate_signature(certificate, candidate) {
+                last_error = ChainError::InvalidSignature;
+                continue;
+            }
+
+            match self.build_path(candidate, intermediates, depth + 1, time) {
+                Ok(mut path) => {
+                    path.insert(0, candidate.clone());
+                    return Ok(path);
+                }
+                Err(err) => last_error = err,
+            }
+        }
+
+        Err(last_error)
+    }
+
+    fn check_validity(certificate: &Certificate, time: Duration) -> Result<(), ChainError> {
+        let validity = &certificate.tbs_certificate.validity;
+        if time < validity.not_before.to_unix_duration() {
+            return Err(ChainError::NotYetValid);
+        }
+        if time > validity.not_after.to_unix_duration() {
+            return Err(ChainError::Expired);
+        }
+        Ok(())
+    }
+
+    fn check_certificate_authority(
+        certificate: &Certificate,
+        depth: usize,
+    ) -> Result<(), ChainError> {
+        let tbs_certificate = &certificate.tbs_certificate;
+
+        let basic_constraints = tbs_certificate
+            .get::<BasicConstraints>()
+            .map_err(ChainError::InvalidCertificate)?
+            .map(|x| x.1)
+            .ok_or(ChainError::NotCertificateAuthority)?;
+        if !basic_constraints.ca {
+            return Err(ChainError::NotCertificateAuthority);
+        }
+        if let Some(path_len) = basic_constraints.path_len_constraint
+            && depth > path_len as usize
+        {
+            return Err(ChainError::PathLengthExceeded);
+        }
+
+        if let Some((_, key_usage)) = tbs_certificate
+            .get::<KeyUsage>()
+            .map_err(ChainError::InvalidCertificate)?
+            && !key_usage.key_cert_sign()
+        {
+            return Err(ChainError::KeyUsage("keyCertSign"));
+        }
+
+        Ok(())
+    }
+
+    fn check_client_key_usage(certificate: &Certificate) -> Result<(), ChainError> {
+        let tbs_certificate = &certificate.tbs_certificate;
+
+        let key_usage = tbs_certificate
+            .get::<KeyUsage>()
+            .map_err(ChainError::InvalidCertificate)?
+            .map(|x| x.1)
+            .ok_or(ChainError::KeyUsage("digitalSignature"))?;
+        if !key_usage.digital_signature() {
+            return Err(ChainError::KeyUsage("digitalSignature"));
+        }
+
+        if let Some((_, extended_key_usage)) = tbs_certificate
+            .get::<ExtendedKeyUsage>()
+            .map_err(ChainError::InvalidCertificate)?
+            && !extended_key_usage
+                .0
+                .iter()
+                .any(|x| *x == ID_KP_CLIENT_AUTH || *x == ANY_EXTENDED_KEY_USAGE)
+        {
+            return Err(ChainError::KeyUsage("clientAuth"));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use x509_cert::der::DecodePem;
+    use x509_cert::der::asn1::BitString;
+
+    const ROOT: &[u8] = include_bytes!("../../testdata/pki/root.pem");
+    const INTERMEDIATE: &[u8] = include_bytes!("../../testdata/pki/int.pem");
+    const LEAF: &[u8] = include_bytes!("../../testdata/pki/leaf.pem");
+
+    fn certificate(pem: &[u8]) -> Certificate {
+        Certificate::from_pem(pem).unwrap()
+    }
+
+    fn validator() -> ChainValidator {
+        let mut trust_store = TrustStore::new();
+        trust_store.add_pem(ROOT).unwrap();
+        ChainValidator::new(Arc::new(trust_store))
+    }
+
+    fn at(year: i32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, 1, 1, 0, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn validates_leaf_through_presented_intermediate() {
+        let chain = validator()
+            .validate_at(certificate(LEAF), &[certificate(INTERMEDIATE)], at(2027))
+            .unwrap();
+        assert_eq!(chain.issuer(), &certificate(INTERMEDIATE));
+        assert_eq!(chain.anchor(), &certificate(ROOT));
+    }
+
+    #[test]
+    fn validates_leaf_through_stored_intermediate() {
+        let mut trust_store = TrustStore::new();
+        trust_store.add_pem(ROOT).unwrap();
+        trust_store.add_pem(INTERMEDIATE).unwrap();
+        let chain = ChainValidator::new(Arc::new(trust_store))
+            .validate_at(certificate(LEAF), &[], at(2027))
+            .unwrap();
+        assert_eq!(chain.path.len(), 2);
+    }
+
+    #[test]
+    fn rejects_leaf_without_path_to_anchor() {
+        let result = validator().validate_at(certificate(LEAF), &[], at(2027));
+        assert!(matches!(result, Err(ChainError::UntrustedIssuer)));
+
+        let result = ChainValidator::new(Arc::new(TrustStore::new())).validate_at(
+            certificate(LEAF),
+            &[certificate(INTERMEDIATE)],
+            at(2027),
+        );
+        assert!(matches!(result, Err(ChainError::UntrustedIssuer)));
+    }
+
+    #[test]
+    fn rejects_leaf_outside_validity_period() {
+        let intermediates = [certificate(INTERMEDIATE)];
+        let result = validator().validate_at(certificate(LEAF), &intermediates, at(2026));
+        assert!(matches!(result, Err(ChainError::NotYetValid)));
+        let result = validator().validate_at(certificate(LEAF), &intermediates, at(2028));
+        assert!(matches!(result, Err(ChainError::Expired)));
+    }
+
+    #[test]
+    fn rejects_tampered_signature() {
+        let mut leaf = certificate(LEAF);
+        let mut signature = leaf.signature.raw_bytes().to_vec();
+        signature[0] ^= 0xff;
+        leaf.signature = BitString::from_bytes(&signature).unwrap();
+
+        let result = validator().validate_at(leaf, &[certificate(INTERMEDIATE)], at(2027));
+        assert!(matches!(result, Err(ChainError::InvalidSignature)));
+    }
+
+    #[test]
+    fn rejects_certificate_authority_as_client_certificate() {
+        let result = validator().validate_at(certificate(INTERMEDIATE), &[], at(2027));
+        assert!(matches!(
+            result,
+            Err(ChainError::KeyUsage("digitalSignature"))
+        ));
+    }
+
+    #[test]
+    fn rejects_path_longer_than_max_depth() {
+        let result = validator().with_max_depth(0).validate_at(
+            certificate(LEAF),
+            &[certificate(INTERMEDIATE)],
+            at(2027),
+        );
+        assert!(matches!(result, Err(ChainError::PathTooLong)));
+    }
+}
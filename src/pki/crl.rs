@@ -0,0 +1,473 @@
+use crate::db;
+use crate::db::models::{Crl, CrlEntry};
+use crate::db::schema::crl_entries::dsl::crl_entries;
+use crate::db::schema::crls::dsl::crls;
+use crate::db::schema::{crl_entries as crl_entries_columns, crls as crls_columns};
+use crate::pki::revocation::{RevocationStatus, serial_number_hex, to_date_time};
+use crate::pki::signature::verify_signature;
+use crate::pki::transport::{HttpTransport, TransportError};
+use crate::pki::trust_store::TrustStore;
+use chrono::{DateTime, TimeDelta, Utc};
+use diesel::dsl::{delete, insert_into};
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, SelectableHelper};
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use std::fmt;
+use std::sync::Arc;
+use x509_cert::Certificate;
+use x509_cert::crl::CertificateList;
+use x509_cert::der::{Decode, Encode};
+use x509_cert::ext::pkix::CrlDistributionPoints;
+use x509_cert::ext::pkix::name::{DistributionPointName, GeneralName};
+
+const ENTRY_BATCH_SIZE: usize = 10_000;
+
+#[derive(Debug)]
+pub enum CrlError {
+    Transport(TransportError),
+    Encoding(x509_cert::der::Error),
+    Database(diesel::result::Error),
+    Pool,
+    MissingDistributionPoint,
+    IssuerNotFound,
+    InvalidSignature,
+    StaleCrl,
+    OutdatedCrl,
+}
+
+impl fmt::Display for CrlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Transport(err) => write!(f, "CRL download failed: {err}"),
+            Self::Encoding(err) => write!(f, "invalid CRL encoding: {err}"),
+            Self::Database(err) => write!(f, "CRL cache query failed: {err}"),
+            Self::Pool => write!(f, "no database connection available"),
+            Self::MissingDistributionPoint => {
+                write!(f, "certificate has no HTTP CRL distribution point")
+            }
+            Self::IssuerNotFound => write!(f, "certificate issuer is not in the trust store"),
+            Self::InvalidSignature => write!(f, "CRL signature is not trusted"),
+            Self::StaleCrl => write!(f, "no sufficiently fresh CRL is available"),
+            Self::OutdatedCrl => write!(f, "CRL is older than the cached one"),
+        }
+    }
+}
+
+impl std::error::Error for CrlError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Transport(err) => Some(err.as_ref()),
+            Self::Encoding(err) => Some(err),
+            Self::Database(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<x509_cert::der::Error> for CrlError {
+    fn from(err: x509_cert::der::Error) -> Self {
+        Self::Encoding(err)
+    }
+}
+
+impl From<diesel::result::Error> for CrlError {
+    fn from(err: diesel::result::Error) -> Self {
+        Self::Database(err)
+    }
+}
+
+pub struct CrlClient {
+    transport: Arc<dyn HttpTransport>,
+    trust_store: Arc<TrustStore>,
+    pool: Arc<db::Pool>,
+    max_staleness: TimeDelta,
+}
+
+impl CrlClient {
+    pub fn new(
+        transport: Arc<dyn HttpTransport>,
+        trust_store: Arc<TrustStore>,
+        pool: Arc<db::Pool>,
+    ) -> Self {
+        Self {
+            transport,
+            trust_store,
+            pool,
+            max_staleness: TimeDelta::zero(),
+        }
+    }
+
+    pub fn with_max_staleness(mut self, max_staleness: TimeDelta) -> Self {
+        self.max_staleness = max_staleness;
+        self
+    }
+
+    pub async fn check(&self, certificate: &Certificate) -> Result<RevocationStatus, CrlError> {
+        let issuer = self
+            .trust_store
+            .issuer_of(certificate)
+            .ok_or(CrlError::IssuerNotFound)?;
+        let serial_number = serial_number_hex(&certificate.tbs_certificate.serial_number);
+
+        let mut last_error = CrlError::MissingDistributionPoint;
+        for distribution_point in Self::distribution_points(certificate)? {
+            match self.usable_crl(&distribution_point, issuer).await {
+                Ok(_) => return self.lookup(&distribution_point, &serial_number).await,
+                Err(err) => last_error = err,
+            }
+        }
+
+        Err(last_error)
+    }
+
+    pub async fn refresh(
+        &self,
+        distribution_point: &str,
+        issuer: &Certificate,
+    ) -> Result<Crl, CrlError> {
+        let response = self
+            .transport
+            .get(distribution_point)
+            .await
+            .map_err(CrlError::Transport)?;
+        let certificate_list = CertificateList::from_der(&response)?;
+
+        Self::verify_crl(&certificate_list, issuer)?;
+
+        let tbs_cert_list = certificate_list.tbs_cert_list;
+        let crl = Crl {
+            distribution_point: distribution_point.to_owned(),
+            issuer: tbs_cert_list.issuer.to_string(),
+            this_update: to_date_time(tbs_cert_list.this_update.to_unix_duration()),
+            next_update: tbs_cert_list
+                .next_update
+                .map(|x| to_date_time(x.to_unix_duration())),
+            fetched_at: Utc::now(),
+        };
+        if !self.is_within_staleness(&crl, crl.fetched_at) {
+            return Err(CrlError::StaleCrl);
+        }
+
+        let entries = tbs_cert_list
+            .revoked_certificates
+            .unwrap_or_default()
+            .into_iter()
+            .map(|x| CrlEntry {
+                distribution_point: distribution_point.to_owned(),
+                serial_number: serial_number_hex(&x.serial_number),
+                revocation_time: to_date_time(x.revocation_date.to_unix_duration()),
+            })
+            .collect::<Vec<_>>();
+
+        let mut conn = self.pool.get().await.map_err(|_| CrlError::Pool)?;
+        conn.transaction::<_, CrlError, _>(|conn| {
+            async move {
+                // An older CRL must not replace a newer one, otherwise a replayed
+                // download could hide revocations issued since.
+                let cached = crls
+                    .filter(crls_columns::distribution_point.eq(distribution_point))
+                    .select((crls_columns::issuer, crls_columns::this_update))
+                    .for_update()
+                    .first::<(String, DateTime<Utc>)>(conn)
+                    .await
+                    .optional()?;
+                if cached.is_some_and(|(issuer, this_update)| {
+                    issuer == crl.issuer && this_update > crl.this_update
+                }) {
+                    return Err(CrlError::OutdatedCrl);
+                }
+
+                delete(
+                    crl_entries
+                        .filter(crl_entries_columns::distribution_point.eq(distribution_point)),
+                )
+                .execute(conn)
+                .await?;
+
+                insert_into(crls)
+                    .values(&crl)
+                    .on_conflict(crls_columns::distribution_point)
+                    .do_update()
+                    .set(&crl)
+                    .execute(conn)
+                    .await?;
+
+                for batch in entries.chunks(ENTRY_BATCH_SIZE) {
+                    insert_into(crl_entries)
+                        .values(batch)
+                        .on_conflict_do_nothing()
+                        .execute(conn)
+                        .await?;
+                }
+
+                Ok(crl)
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    async fn usable_crl(
+        &self,
+        distribution_point: &str,
+        issuer: &Certificate,
+    ) -> Result<Crl, CrlError> {
+        let now = Utc::now();
+        // A CRL cached for another issuer says nothing about this one.
+        let issuer_name = issuer.tbs_certificate.subject.to_string();
+        let cached = self
+            .cached_crl(distribution_point)
+            .await?
+            .filter(|x| x.issuer == issuer_name);
+        match cached {
+            Some(crl) if crl.next_update.is_some_and(|x| x > now) => Ok(crl),
+            cached => match self.refresh(distribution_point, issuer).await {
+                Ok(crl) => Ok(crl),
+                Err(err) => match cached {
+                    Some(crl) if self.is_within_staleness(&crl, now) => Ok(crl),
+                    Some(_) => Err(CrlError::StaleCrl),
+                    None => Err(err),
+                },
+            },
+        }
+    }
+
+    async fn cached_crl(&self, distribution_point: &str) -> Result<Option<Crl>, CrlError> {
+        let mut conn = self.pool.get().await.map_err(|_| CrlError::Pool)?;
+        Ok(crls
+            .filter(crls_columns::distribution_point.eq(distribution_point))
+            .select(Crl::as_select())
+            .first(&mut conn)
+            .await
+            .optional()?)
+    }
+
+    async fn lookup(
+        &self,
+        distribution_point: &str,
+        serial_number: &str,
+    ) -> Result<RevocationStatus, CrlError> {
+        let mut conn = self.pool.get().await.map_err(|_| CrlError::Pool)?;
+        let entry = crl_entries
+            .filter(crl_entries_columns::distribution_point.eq(distribution_point))
+            .filter(crl_entries_columns::serial_number.eq(serial_number))
+            .select(CrlEntry::as_select())
+            .first(&mut conn)
+            .await
+            .optional()?;
+
+        Ok(match entry {
+            Some(entry) => RevocationStatus::Revoked {
+                revocation_time: entry.revocation_time,
+            },
+            None => RevocationStatus::Good,
+        })
+    }
+
+    fn is_within_staleness(&self, crl: &Crl, now: DateTime<Utc>) -> bool {
+        let valid_until = crl.next_update.unwrap_or(crl.this_update);
+        now <= valid_until + self.max_staleness
+    }
+
+    fn verify_crl(
+        certificate_list: &CertificateList,
+        issuer: &Certificate,
+    ) -> Result<(), CrlError> {
+        if certificate_list.tbs_cert_list.issuer != issuer.tbs_certificate.subject {
+            return Err(CrlError::InvalidSignature);
+        }
+
+        let tbs_cert_list = certificate_list.tbs_cert_list.to_der()?;
+        let signature = certificate_list
+            .signature
+            .as_bytes()
+            .ok_or(CrlError::InvalidSignature)?;
+
+        verify_signature(
+            &issuer.tbs_certificate.subject_public_key_info,
+            &certificate_list.signature_algorithm,
+            &tbs_cert_list,
+            signature,
+        )
+        .ok_or(CrlError::InvalidSignature)
+    }
+
+    fn distribution_points(certificate: &Certificate) -> Result<Vec<String>, CrlError> {
+        let (_, distribution_points) = certificate
+            .tbs_certificate
+            .get::<CrlDistributionPoints>()?
+            .ok_or(CrlError::MissingDistributionPoint)?;
+
+        Ok(distribution_points
+            .0
+            .into_iter()
+            .filter_map(|x| match x.distribution_point {
+                Some(DistributionPointName::FullName(names)) => Some(names),
+                _ => None,
+            })
+            .flatten()
+            .filter_map(|x| match x {
+                GeneralName::UniformResourceIdentifier(url) => Some(url.to_string()),
+                _ => None,
+            })
+            .filter(|x| x.starts_with("http://") || x.starts_with("https://"))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use chrono::TimeZone;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use x509_cert::der::DecodePem;
+
+    const INTERMEDIATE: &[u8] = include_bytes!("../../testdata/pki/int.pem");
+    const LEAF: &[u8] = include_bytes!("../../testdata/pki/leaf.pem");
+    const CRL: &[u8] = include_bytes!("../../testdata/pki/crl.der");
+
+    #[derive(Default)]
+    struct StubDistributionPoint {
+        unavailable: bool,
+        requests: AtomicUsize,
+    }
+
+    impl StubDistributionPoint {
+        fn requests(&self) -> usize {
+            AtomicUsize::load(&self.requests, Ordering::SeqCst)
+        }
+    }
+
+    #[async_trait]
+    impl HttpTransport for StubDistributionPoint {
+        async fn post(
+            &self,
+            _url: &str,
+            _content_type: &str,
+            _body: Vec<u8>,
+        ) -> Result<Vec<u8>, TransportError> {
+            unimplemented!()
+        }
+
+        async fn get(&self, _url: &str) -> Result<Vec<u8>, TransportError> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            if self.unavailable {
+                return Err("distribution point unavailable".into());
+            }
+            Ok(CRL.to_vec())
+        }
+    }
+
+    fn certificate(pem: &[u8]) -> Certificate {
+        Certificate::from_pem(pem).unwrap()
+    }
+
+    async fn client_with_cached_crl(
+        transport: Arc<StubDistributionPoint>,
+        issuer: &str,
+    ) -> Option<(CrlClient, String)> {
+        let pool = db::test_pool().await?;
+        let distribution_point = format!("http://crl.example/{}.crl", uuid::Uuid::new_v4());
+        let crl = Crl {
+            distribution_point: distribution_point.clone(),
+            issuer: issuer.to_owned(),
+            this_update: Utc::now() - TimeDelta::hours(1),
+            next_update: Some(Utc::now() + TimeDelta::days(1)),
+            fetched_at: Utc::now(),
+        };
+        let mut conn = pool.get().await.unwrap();
+        insert_into(crls)
+            .values(&crl)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        drop(conn);
+
+        let client = CrlClient::new(transport, Arc::new(TrustStore::new()), Arc::new(pool));
+        Some((client, distribution_point))
+    }
+
+    async fn remove(client: &CrlClient, distribution_point: &str) {
+        let mut conn = client.pool.get().await.unwrap();
+        delete(crl_entries.filter(crl_entries_columns::distribution_point.eq(distribution_point)))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        delete(crls.filter(crls_columns::distribution_point.eq(distribution_point)))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn uses_cached_crl_from_the_same_issuer() {
+        let issuer = certificate(INTERMEDIATE);
+        let transport = Arc::new(StubDistributionPoint::default());
+        let Some((client, distribution_point)) = client_with_cached_crl(
+            transport.clone(),
+            &issuer.tbs_certificate.subject.to_string(),
+        )
+        .await
+        else {
+            return;
+        };
+
+        let crl = client.usable_crl(&distribution_point, &issuer).await;
+        remove(&client, &distribution_point).await;
+
+        assert!(crl.is_ok());
+        assert_eq!(transport.requests(), 0);
+    }
+
+    #[tokio::test]
+    async fn refreshes_crl_cached_for_another_issuer() {
+        let issuer = certificate(INTERMEDIATE);
+        let transport = Arc::new(StubDistributionPoint::default());
+        let Some((client, distribution_point)) =
+            client_with_cached_crl(transport.clone(), "CN=Another CA").await
+        else {
+            return;
+        };
+
+        let crl = client.usable_crl(&distribution_point, &issuer).await;
+        let status = client
+            .lookup(
+                &distribution_point,
+                &serial_number_hex(&certificate(LEAF).tbs_certificate.serial_number),
+            )
+            .await;
+        remove(&client, &distribution_point).await;
+
+        assert_eq!(
+            crl.unwrap().issuer,
+            issuer.tbs_certificate.subject.to_string()
+        );
+        assert_eq!(transport.requests(), 1);
+        assert_eq!(
+            status.unwrap(),
+            RevocationStatus::Revoked {
+                revocation_time: Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap(),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn does_not_fall_back_to_crl_cached_for_another_issuer() {
+        let issuer = certificate(INTERMEDIATE);
+        let transport = Arc::new(StubDistributionPoint {
+            unavailable: true,
+            ..Default::default()
+        });
+        let Some((client, distribution_point)) =
+            client_with_cached_crl(transport, "CN=Another CA").await
+        else {
+            return;
+        };
+
+        let crl = client.usable_crl(&distribution_point, &issuer).await;
+        remove(&client, &distribution_point).await;
+
+        assert!(matches!(crl, Err(CrlError::Transport(_))));
+    }
+}
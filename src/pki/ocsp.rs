@@ -0,0 +1,535 @@
+use crate::pki::revocation::{RevocationStatus, to_date_time};
+use crate::pki::signature::{verify_certificate_signature, verify_signature};
+use crate::pki::transport::{HttpTransport, TransportError};
+use crate::pki::trust_store::TrustStore;
+use chrono::{DateTime, TimeDelta, Utc};
+use const_oid::db::rfc5280::{ID_AD_OCSP, ID_KP_OCSP_SIGNING};
+use const_oid::db::rfc6960::ID_PKIX_OCSP_BASIC;
+use rand::TryRngCore;
+use rand::rand_core::OsRng;
+use sha1::Sha1;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use x509_cert::Certificate;
+use x509_cert::der::{Decode, Encode};
+use x509_cert::ext::pkix::name::GeneralName;
+use x509_cert::ext::pkix::{AuthorityInfoAccessSyntax, ExtendedKeyUsage};
+use x509_ocsp::builder::OcspRequestBuilder;
+use x509_ocsp::ext::Nonce;
+use x509_ocsp::{
+    BasicOcspResponse, CertId, CertStatus, OcspResponse, OcspResponseStatus, Request,
+    SingleResponse,
+};
+
+const OCSP_REQUEST_CONTENT_TYPE: &str = "application/ocsp-request";
+const NONCE_LENGTH: usize = 16;
+
+#[derive(Debug)]
+pub enum OcspError {
+    Transport(TransportError),
+    Encoding(x509_cert::der::Error),
+    Request(x509_ocsp::builder::Error),
+    Random,
+    MissingResponderUrl,
+    IssuerNotFound,
+    Unsuccessful(OcspResponseStatus),
+    UnsupportedResponseType,
+    UntrustedResponder,
+    NonceMismatch,
+    MissingStatus,
+    StaleResponse,
+}
+
+impl fmt::Display for OcspError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Transport(err) => write!(f, "OCSP transport failed: {err}"),
+            Self::Encoding(err) => write!(f, "invalid OCSP encoding: {err}"),
+            Self::Request(err) => write!(f, "unable to build OCSP request: {err}"),
+            Self::Random => write!(f, "unable to generate OCSP nonce"),
+            Self::MissingResponderUrl => write!(f, "certificate has no OCSP responder URL"),
+            Self::IssuerNotFound => write!(f, "certificate issuer is not in the trust store"),
+            Self::Unsuccessful(status) => write!(f, "OCSP responder returned {status:?}"),
+            Self::UnsupportedResponseType => write!(f, "OCSP response type is not supported"),
+            Self::UntrustedResponder => write!(f, "OCSP response signature is not trusted"),
+            Self::NonceMismatch => write!(f, "OCSP response nonce does not match the request"),
+            Self::MissingStatus => write!(f, "OCSP response does not cover the certificate"),
+            Self::StaleResponse => write!(f, "OCSP response is outside its validity window"),
+        }
+    }
+}
+
+impl std::error::Error for OcspError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Transport(err) => Some(err.as_ref()),
+            Self::Encoding(err) => Some(err),
+            Self::Request(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<x509_cert::der::Error> for OcspError {
+    fn from(err: x509_cert::der::Error) -> Self {
+        Self::Encoding(err)
+    }
+}
+
+struct CachedStatus {
+    status: RevocationStatus,
+    expires_at: DateTime<Utc>,
+}
+
+pub struct OcspClient {
+    transport: Arc<dyn HttpTransport>,
+    trust_store: Arc<TrustStore>,
+    responders: Vec<Certificate>,
+    default_cache_ttl: TimeDelta,
+    clock_skew: TimeDelta,
+    cache: Mutex<HashMap<Vec<u8>, CachedStatus>>,
+}
+
+impl OcspClient {
+    pub fn new(transport: Arc<dyn HttpTransport>, trust_store: Arc<TrustStore>) -> Self {
+        Self {
+            transport,
+            trust_store,
+            responders: vec![],
+            default_cache_ttl: TimeDelta::minutes(5),
+            clock_skew: TimeDelta::minutes(5),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_responder_certificate(mut self, certificate: Certificate) -> Self {
+        self.responders.push(certificate);
+        self
+    }
+
+    pub fn with_default_cache_ttl(mut self, default_cache_ttl: TimeDelta) -> Self {
+        self.default_cache_ttl = default_cache_ttl;
+        self
+    }
+
+    pub fn with_clock_skew(mut self, clock_skew: TimeDelta) -> Self {
+        self.clock_skew = clock_skew;
+        self
+    }
+
+    pub async fn check(&self, certificate: &Certificate) -> Result<RevocationStatus, OcspError> {
+        let issuer = self
+            .trust_store
+            .issuer_of(certificate)
+            .ok_or(OcspError::IssuerNotFound)?;
+        self.check_with_issuer(certificate, issuer).await
+    }
+
+    pub async fn check_with_issuer(
+        &self,
+        certificate: &Certificate,
+        issuer: &Certificate,
+    ) -> Result<RevocationStatus, OcspError> {
+        let cert_id = CertId::from_cert::<Sha1>(issuer, certificate).map_err(OcspError::Request)?;
+        let cache_key = cert_id.to_der()?;
+
+        let now = Utc::now();
+        if let Some(status) = self.cached_status(&cache_key, now) {
+            return Ok(status);
+        }
+
+        let responder_url = Self::responder_url(certificate)?;
+
+        let mut nonce_bytes = [0u8; NONCE_LENGTH];
+        OsRng
+            .try_fill_bytes(&mut nonce_bytes)
+            .map_err(|_| OcspError::Random)?;
+        let nonce = Nonce::new(nonce_bytes)?;
+
+        let request = OcspRequestBuilder::default()
+            .with_request(Request::new(cert_id.clone()))
+            .with_extension(nonce.clone())
+            .map_err(OcspError::Request)?
+            .build()
+            .to_der()?;
+
+        let response = self
+            .transport
+            .post(&responder_url, OCSP_REQUEST_CONTENT_TYPE, request)
+            .await
+            .map_err(OcspError::Transport)?;
+        let response = OcspResponse::from_der(&response)?;
+
+        if response.response_status != OcspResponseStatus::Successful {
+            return Err(OcspError::Unsuccessful(response.response_status));
+        }
+        let response_bytes = response
+            .response_bytes
+            .ok_or(OcspError::UnsupportedResponseType)?;
+        if response_bytes.response_type != ID_PKIX_OCSP_BASIC {
+            return Err(OcspError::UnsupportedResponseType);
+        }
+        let basic_response = BasicOcspResponse::from_der(response_bytes.response.as_bytes())?;
+
+        self.verify_responder(&basic_response, issuer, now)?;
+
+        if let Some(response_nonce) = basic_response.nonce()
+            && response_nonce != nonce
+        {
+            return Err(OcspError::NonceMismatch);
+        }
+
+        let single_response = basic_response
+            .tbs_response_data
+            .responses
+            .iter()
+            .find(|x| x.cert_id == cert_id)
+            .ok_or(OcspError::MissingStatus)?;
+
+        let expires_at = self.check_freshness(single_response, now)?;
+        let status = match &single_response.cert_status {
+            CertStatus::Good(_) => RevocationStatus::Good,
+            CertStatus::Revoked(info) => RevocationStatus::Revoked {
+                revocation_time: to_date_time(info.revocation_time.0.to_unix_duration()),
+            },
+            CertStatus::Unknown(_) => RevocationStatus::Unknown,
+        };
+
+        self.cache_status(cache_key, status, expires_at, now);
+        Ok(status)
+    }
+
+    fn responder_url(certificate: &Certificate) -> Result<String, OcspError> {
+        let (_, authority_info_access) = certificate
+            .tbs_certificate
+            .get::<AuthorityInfoAccessSyntax>()?
+            .ok_or(OcspError::MissingResponderUrl)?;
+
+        authority_info_access
+            .0
+            .into_iter()
+            .filter(|x| x.access_method == ID_AD_OCSP)
+            .find_map(|x| match x.access_location {
+                GeneralName::UniformResourceIdentifier(url) => Some(url.to_string()),
+                _ => None,
+            })
+            .ok_or(OcspError::MissingResponderUrl)
+    }
+
+    fn verify_responder(
+        &self,
+        response: &BasicOcspResponse,
+        issuer: &Certificate,
+        now: DateTime<Utc>,
+    ) -> Result<(), OcspError> {
+        let tbs_response_data = response.tbs_response_data.to_der()?;
+        let signature = response
+            .signature
+            .as_bytes()
+            .ok_or(OcspError::UntrustedResponder)?;
+
+        let delegated_responders = response
+            .certs
+            .iter()
+            .flatten()
+            .filter(|x| Self::is_delegated_responder(x, issuer, now));
+
+        let trusted = self
+            .responders
+            .iter()
+            .chain(std::iter::once(issuer))
+            .chain(delegated_responders)
+            .any(|x| {
+                verify_signature(
+                    &x.tbs_certificate.subject_public_key_info,
+                    &response.signature_algorithm,
+                    &tbs_response_data,
+                    signature,
+                )
+                .is_some()
+            });
+
+        if trusted {
+            Ok(())
+        } else {
+            Err(OcspError::UntrustedResponder)
+        }
+    }
+
+    fn is_delegated_responder(
+        certificate: &Certificate,
+        issuer: &Certificate,
+        now: DateTime<Utc>,
+    ) -> bool {
+        let tbs_certificate = &certificate.tbs_certificate;
+        if tbs_certificate.issuer != issuer.tbs_certificate.subject {
+            return false;
+        }
+
+        let not_before = to_date_time(tbs_certificate.validity.not_before.to_unix_duration());
+        let not_after = to_date_time(tbs_certificate.validity.not_after.to_unix_duration());
+        if now < not_before || now > not_after {
+            return false;
+        }
+
+        let is_ocsp_signer = matches!(
+            tbs_certificate.get::<ExtendedKeyUsage>(),
+            Ok(Some((_, extended_key_usage))) if extended_key_usage.0.contains(&ID_KP_OCSP_SIGNING)
+        );
+
+        is_ocsp_signer && verify_certificate_signature(certificate, issuer)
+    }
+
+    fn check_freshness(
+        &self,
+        response: &SingleResponse,
+        now: DateTime<Utc>,
+    ) -> Result<DateTime<Utc>, OcspError> {
+        let this_update = to_date_time(response.this_update.0.to_unix_duration());
+        if this_update > now + self.clock_skew {
+            return Err(OcspError::StaleResponse);
+        }
+
+        match &response.next_update {
+            Some(next_update) => {
+                let next_update = to_date_time(next_update.0.to_unix_duration());
+                if next_update + self.clock_skew < now {
+                    return Err(OcspError::StaleResponse);
+                }
+                Ok(next_update)
+            }
+            None => Ok(now + self.default_cache_ttl),
+        }
+    }
+
+    fn cached_status(&self, cache_key: &[u8], now: DateTime<Utc>) -> Option<RevocationStatus> {
+        let cache = self.cache.lock().ok()?;
+        cache
+            .get(cache_key)
+            .filter(|x| x.expires_at > now)
+            .map(|x| x.status)
+    }
+
+    fn cache_status(
+        &self,
+        cache_key: Vec<u8>,
+        status: RevocationStatus,
+        expires_at: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) {
+        if let Ok(mut cache) = self.cache.lock() {
+            cache.retain(|_, x| x.expires_at > now);
+            cache.insert(cache_key, CachedStatus { status, expires_at });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use chrono::TimeZone;
+    use rsa::RsaPrivateKey;
+    use rsa::pkcs1::DecodeRsaPrivateKey;
+    use rsa::pkcs1v15::SigningKey;
+    use sha2::Sha256;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, SystemTime};
+    use x509_cert::der::DecodePem;
+    use x509_ocsp::builder::OcspResponseBuilder;
+    use x509_ocsp::{OcspGeneralizedTime, OcspRequest, RevokedInfo};
+
+    const INTERMEDIATE: &[u8] = include_bytes!("../../testdata/pki/int.pem");
+    const LEAF: &[u8] = include_bytes!("../../testdata/pki/leaf.pem");
+    const RESPONDER: &[u8] = include_bytes!("../../testdata/pki/responder.pem");
+    const RESPONDER_KEY: &str = include_str!("../../testdata/pki/responder.key");
+    const UNRELATED_KEY: &str = include_str!("../../testdata/signing_key.pem");
+
+    struct StubResponder {
+        signing_key: &'static str,
+        cert_status: CertStatus,
+        this_update: DateTime<Utc>,
+        next_update: Option<DateTime<Utc>>,
+        replace_nonce: bool,
+        requests: AtomicUsize,
+    }
+
+    impl StubResponder {
+        fn new(cert_status: CertStatus) -> Self {
+            Self {
+                signing_key: RESPONDER_KEY,
+                cert_status,
+                this_update: Utc::now() - TimeDelta::hours(1),
+                next_update: Some(Utc::now() + TimeDelta::hours(1)),
+                replace_nonce: false,
+                requests: AtomicUsize::new(0),
+            }
+        }
+
+        fn requests(&self) -> usize {
+            self.requests.load(Ordering::SeqCst)
+        }
+    }
+
+    fn generalized_time(time: DateTime<Utc>) -> OcspGeneralizedTime {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(time.timestamp() as u64);
+        OcspGeneralizedTime::try_from(time).unwrap()
+    }
+
+    #[async_trait]
+    impl HttpTransport for StubResponder {
+        async fn post(
+            &self,
+            url: &str,
+            content_type: &str,
+            body: Vec<u8>,
+        ) -> Result<Vec<u8>, TransportError> {
+            assert_eq!(url, "http://ocsp.example/");
+            assert_eq!(content_type, OCSP_REQUEST_CONTENT_TYPE);
+            self.requests.fetch_add(1, Ordering::SeqCst);
+
+            let request = OcspRequest::from_der(&body)?;
+            let cert_id = request.tbs_request.request_list[0].req_cert.clone();
+            let mut single_response = SingleResponse::new(
+                cert_id,
+                self.cert_status,
+                generalized_time(self.this_update),
+            );
+            if let Some(next_update) = self.next_update {
+                single_response = single_response.with_next_update(generalized_time(next_update));
+            }
+
+            let responder = Certificate::from_pem(RESPONDER)?;
+            let mut builder = OcspResponseBuilder::new(responder.tbs_certificate.subject)
+                .with_single_response(single_response);
+            let nonce = if self.replace_nonce {
+                Some(Nonce::new([0u8; NONCE_LENGTH])?)
+            } else {
+                request.nonce()
+            };
+            if let Some(nonce) = nonce {
+                builder = builder.with_extension(nonce)?;
+            }
+
+            let private_key = RsaPrivateKey::from_pkcs1_pem(self.signing_key)?;
+            let mut signer = SigningKey::<Sha256>::new(private_key);
+            let response = builder.sign(&mut signer, None, generalized_time(Utc::now()))?;
+            Ok(response.to_der()?)
+        }
+
+        async fn get(&self, _url: &str) -> Result<Vec<u8>, TransportError> {
+            unimplemented!()
+        }
+    }
+
+    async fn check(responder: &Arc<StubResponder>) -> Result<RevocationStatus, OcspError> {
+        let client = client(responder.clone());
+        client
+            .check_with_issuer(&certificate(LEAF), &certificate(INTERMEDIATE))
+            .await
+    }
+
+    fn client(responder: Arc<StubResponder>) -> OcspClient {
+        OcspClient::new(responder, Arc::new(TrustStore::new()))
+            .with_responder_certificate(certificate(RESPONDER))
+    }
+
+    fn certificate(pem: &[u8]) -> Certificate {
+        Certificate::from_pem(pem).unwrap()
+    }
+
+    #[tokio::test]
+    async fn reports_good_status() {
+        let responder = Arc::new(StubResponder::new(CertStatus::good()));
+        assert_eq!(check(&responder).await.unwrap(), RevocationStatus::Good);
+    }
+
+    #[tokio::test]
+    async fn reports_revoked_status() {
+        let revocation_time = Utc.with_ymd_and_hms(2026, 10, 1, 0, 0, 0).unwrap();
+        let responder = Arc::new(StubResponder::new(CertStatus::revoked(RevokedInfo {
+            revocation_time: generalized_time(revocation_time),
+            revocation_reason: None,
+        })));
+        assert_eq!(
+            check(&responder).await.unwrap(),
+            RevocationStatus::Revoked { revocation_time }
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_unknown_status() {
+        let responder = Arc::new(StubResponder::new(CertStatus::unknown()));
+        assert_eq!(check(&responder).await.unwrap(), RevocationStatus::Unknown);
+    }
+
+    #[tokio::test]
+    async fn rejects_response_signed_by_unrelated_key() {
+        let mut responder = StubResponder::new(CertStatus::good());
+        responder.signing_key = UNRELATED_KEY;
+        assert!(matches!(
+            check(&Arc::new(responder)).await,
+            Err(OcspError::UntrustedResponder)
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_nonce_mismatch() {
+        let mut responder = StubResponder::new(CertStatus::good());
+        responder.replace_nonce = true;
+        assert!(matches!(
+            check(&Arc::new(responder)).await,
+            Err(OcspError::NonceMismatch)
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_this_update_in_the_future() {
+        let mut responder = StubResponder::new(CertStatus::good());
+        responder.this_update = Utc::now() + TimeDelta::minutes(30);
+        assert!(matches!(
+            check(&Arc::new(responder)).await,
+            Err(OcspError::StaleResponse)
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_response_past_next_update() {
+        let mut responder = StubResponder::new(CertStatus::good());
+        responder.this_update = Utc::now() - TimeDelta::days(2);
+        responder.next_update = Some(Utc::now() - TimeDelta::days(1));
+        assert!(matches!(
+            check(&Arc::new(responder)).await,
+            Err(OcspError::StaleResponse)
+        ));
+    }
+
+    #[tokio::test]
+    async fn caches_status_until_next_update() {
+        let responder = Arc::new(StubResponder::new(CertStatus::good()));
+        let client = client(responder.clone());
+        for _ in 0..2 {
+            client
+                .check_with_issuer(&certificate(LEAF), &certificate(INTERMEDIATE))
+                .await
+                .unwrap();
+        }
+        assert_eq!(responder.requests(), 1);
+    }
+
+    #[tokio::test]
+    async fn refetches_after_next_update() {
+        let mut responder = StubResponder::new(CertStatus::good());
+        responder.next_update = Some(Utc::now() - TimeDelta::minutes(1));
+        let responder = Arc::new(responder);
+        let client = client(responder.clone());
+        for _ in 0..2 {
+            client
+                .check_with_issuer(&certificate(LEAF), &certificate(INTERMEDIATE))
+                .await
+                .unwrap();
+        }
+        assert_eq!(responder.requests(), 2);
+    }
+}
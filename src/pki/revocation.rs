@@ -0,0 +1,143 @@
+use crate::pki::crl::{CrlClient, CrlError};
+use crate::pki::ocsp::{OcspClient, OcspError};
+use chrono::{DateTime, Utc};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use x509_cert::Certificate;
+use x509_cert::serial_number::SerialNumber;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RevocationStatus {
+    Good,
+    Revoked { revocation_time: DateTime<Utc> },
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureMode {
+    FailOpen,
+    FailClosed,
+}
+
+#[derive(Debug)]
+pub enum RevocationError {
+    Revoked {
+        revocation_time: DateTime<Utc>,
+    },
+    Indeterminate {
+        ocsp: Option<OcspError>,
+        crl: Option<CrlError>,
+    },
+}
+
+impl fmt::Display for RevocationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Revoked { revocation_time } => {
+                write!(f, "certificate was revoked at {revocation_time}")
+            }
+            Self::Indeterminate { ocsp, crl } => {
+                write!(f, "revocation status is unknown")?;
+                if let Some(err) = ocsp {
+                    write!(f, "; OCSP: {err}")?;
+                }
+                if let Some(err) = crl {
+                    write!(f, "; CRL: {err}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for RevocationError {}
+
+pub struct RevocationChecker {
+    ocsp_client: Option<Arc<OcspClient>>,
+    crl_client: Option<Arc<CrlClient>>,
+    failure_mode: FailureMode,
+}
+
+impl RevocationChecker {
+    pub fn new(failure_mode: FailureMode) -> Self {
+        Self {
+            ocsp_client: None,
+            crl_client: None,
+            failure_mode,
+        }
+    }
+
+    pub fn with_ocsp_client(mut self, ocsp_client: Arc<OcspClient>) -> Self {
+        self.ocsp_client = Some(ocsp_client);
+        self
+    }
+
+    pub fn with_crl_client(mut self, crl_client: Arc<CrlClient>) -> Self {
+        self.crl_client = Some(crl_client);
+        self
+    }
+
+    pub async fn check(
+        &self,
+        certificate: &Certificate,
+    ) -> Result<RevocationStatus, RevocationError> {
+        let mut ocsp_error = None;
+        if let Some(ocsp_client) = &self.ocsp_client {
+            match ocsp_client.check(certificate).await {
+                Ok(RevocationStatus::Unknown) => {}
+                Ok(status) => return Ok(status),
+                Err(err) => ocsp_error = Some(err),
+            }
+        }
+
+        let mut crl_error = None;
+        if let Some(crl_client) = &self.crl_client {
+            match crl_client.check(certificate).await {
+                Ok(RevocationStatus::Unknown) => {}
+                Ok(status) => return Ok(status),
+                Err(err) => crl_error = Some(err),
+            }
+        }
+
+        if ocsp_error.is_none() && crl_error.is_none() {
+            Ok(RevocationStatus::Unknown)
+        } else {
+            Err(RevocationError::Indeterminate {
+                ocsp: ocsp_error,
+                crl: crl_error,
+            })
+        }
+    }
+
+    pub async fn ensure_not_revoked(
+        &self,
+        certificate: &Certificate,
+    ) -> Result<(), RevocationError> {
+        match self.check(certificate).await {
+            Ok(RevocationStatus::Good) => Ok(()),
+            Ok(RevocationStatus::Revoked { revocation_time }) => {
+                Err(RevocationError::Revoked { revocation_time })
+            }
+            Ok(RevocationStatus::Unknown) if self.failure_mode == FailureMode::FailOpen => Ok(()),
+            Ok(RevocationStatus::Unknown) => Err(RevocationError::Indeterminate {
+                ocsp: None,
+                crl: None,
+            }),
+            Err(_) if self.failure_mode == FailureMode::FailOpen => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+pub(crate) fn to_date_time(unix_duration: Duration) -> DateTime<Utc> {
+    DateTime::from_timestamp(unix_duration.as_secs() as i64, 0).unwrap_or(DateTime::UNIX_EPOCH)
+}
+
+pub(crate) fn serial_number_hex(serial_number: &SerialNumber) -> String {
+    serial_number
+        .as_bytes()
+        .iter()
+        .map(|x| format!("{x:02X}"))
+        .collect()
+}
@@ -0,0 +1,59 @@
+use const_oid::db::rfc5912::{
+    SHA_1_WITH_RSA_ENCRYPTION, SHA_256_WITH_RSA_ENCRYPTION, SHA_384_WITH_RSA_ENCRYPTION,
+    SHA_512_WITH_RSA_ENCRYPTION,
+};
+use rsa::RsaPublicKey;
+use rsa::pkcs1v15::{Signature, VerifyingKey};
+use rsa::pkcs8::DecodePublicKey;
+use rsa::signature::Verifier;
+use sha1::Sha1;
+use sha2::{Sha256, Sha384, Sha512};
+use x509_cert::Certificate;
+use x509_cert::der::Encode;
+use x509_cert::spki::{AlgorithmIdentifierOwned, SubjectPublicKeyInfoOwned};
+
+pub(crate) fn verify_signature(
+    public_key: &SubjectPublicKeyInfoOwned,
+    algorithm: &AlgorithmIdentifierOwned,
+    message: &[u8],
+    signature: &[u8],
+) -> Option<()> {
+    let public_key = RsaPublicKey::from_public_key_der(&public_key.to_der().ok()?).ok()?;
+    let signature = Signature::try_from(signature).ok()?;
+
+    match algorithm.oid {
+        SHA_1_WITH_RSA_ENCRYPTION => VerifyingKey::<Sha1>::new(public_key)
+            .verify(message, &signature)
+            .ok(),
+        SHA_256_WITH_RSA_ENCRYPTION => VerifyingKey::<Sha256>::new(public_key)
+            .verify(message, &signature)
+            .ok(),
+        SHA_384_WITH_RSA_ENCRYPTION => VerifyingKey::<Sha384>::new(public_key)
+            .verify(message, &signature)
+            .ok(),
+        SHA_512_WITH_RSA_ENCRYPTION => VerifyingKey::<Sha512>::new(public_key)
+            .verify(message, &signature)
+            .ok(),
+        _ => None,
+    }
+}
+
+pub(crate) fn verify_certificate_signature(
+    certificate: &Certificate,
+    issuer: &Certificate,
+) -> bool {
+    let Ok(tbs_certificate) = certificate.tbs_certificate.to_der() else {
+        return false;
+    };
+    let Some(signature) = certificate.signature.as_bytes() else {
+        return false;
+    };
+
+    verify_signature(
+        &issuer.tbs_certificate.subject_public_key_info,
+        &certificate.signature_algorithm,
+        &tbs_certificate,
+        signature,
+    )
+    .is_some()
+}
@@ -0,0 +1,15 @@
+use async_trait::async_trait;
+
+pub type TransportError = Box<dyn std::error::Error + Send + Sync>;
+
+#[async_trait]
+pub trait HttpTransport: Send + Sync {
+    async fn post(
+        &self,
+        url: &str,
+        content_type: &str,
+        body: Vec<u8>,
+    ) -> Result<Vec<u8>, TransportError>;
+
+    async fn get(&self, url: &str) -> Result<Vec<u8>, TransportError>;
+}
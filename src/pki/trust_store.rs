@@ -0,0 +1,121 @@
+use crate::pki::chain_validator::ChainError;
+use crate::pki::signature::verify_certificate_signature;
+use std::path::Path;
+use x509_cert::Certificate;
+use x509_cert::der::Decode;
+use x509_cert::name::Name;
+
+#[derive(Default)]
+pub struct TrustStore {
+    anchors: Vec<Certificate>,
+    intermediates: Vec<Certificate>,
+}
+
+impl TrustStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_pem_files<P: AsRef<Path>>(paths: &[P]) -> Result<Self, ChainError> {
+        let mut trust_store = Self::new();
+        for path in paths {
+            trust_store.add_pem_file(path)?;
+        }
+        Ok(trust_store)
+    }
+
+    pub fn add_pem_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), ChainError> {
+        let pem = std::fs::read(path).map_err(ChainError::Io)?;
+        self.add_pem(&pem)
+    }
+
+    pub fn add_pem(&mut self, pem: &[u8]) -> Result<(), ChainError> {
+        if pem.trim_ascii().is_empty() {
+            return Err(ChainError::NoCertificates);
+        }
+        let certificates =
+            Certificate::load_pem_chain(pem).map_err(ChainError::InvalidCertificate)?;
+        for certificate in certificates {
+            self.add_certificate(certificate);
+        }
+        Ok(())
+    }
+
+    pub fn add_der(&mut self, der: &[u8]) -> Result<(), ChainError> {
+        let certificate = Certificate::from_der(der).map_err(ChainError::InvalidCertificate)?;
+        self.add_certificate(certificate);
+        Ok(())
+    }
+
+    pub fn add_certificate(&mut self, certificate: Certificate) {
+        let tbs_certificate = &certificate.tbs_certificate;
+        if tbs_certificate.subject == tbs_certificate.issuer {
+            self.anchors.push(certificate);
+        } else {
+            self.intermediates.push(certificate);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.anchors.is_empty()
+    }
+
+    pub fn issuer_of(&self, certificate: &Certificate) -> Option<&Certificate> {
+        let issuer_name = &certificate.tbs_certificate.issuer;
+        self.anchors
+            .iter()
+            .chain(self.intermediates.iter())
+            .filter(|x| &x.tbs_certificate.subject == issuer_name)
+            .find(|x| verify_certificate_signature(certificate, x))
+    }
+
+    pub(crate) fn anchors_for<'a>(
+        &'a self,
+        name: &'a Name,
+    ) -> impl Iterator<Item = &'a Certificate> {
+        self.anchors
+            .iter()
+            .filter(move |x| &x.tbs_certificate.subject == name)
+    }
+
+    pub(crate) fn intermediates_for<'a>(
+        &'a self,
+        name: &'a Name,
+    ) -> impl Iterator<Item = &'a Certificate> {
+        self.intermediates
+            .iter()
+            .filter(move |x| &x.tbs_certificate.subject == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_empty_pem() {
+        let mut trust_store = TrustStore::new();
+        assert!(matches!(
+            trust_store.add_pem(b""),
+            Err(ChainError::NoCertificates)
+        ));
+        assert!(matches!(
+            trust_store.add_pem(b" \n"),
+            Err(ChainError::NoCertificates)
+        ));
+        assert!(trust_store.is_empty());
+    }
+
+    #[test]
+    fn classifies_anchors_and_intermediates() {
+        let mut trust_store = TrustStore::new();
+        trust_store
+            .add_pem(include_bytes!("../../testdata/pki/root.pem"))
+            .unwrap();
+        trust_store
+            .add_pem(include_bytes!("../../testdata/pki/int.pem"))
+            .unwrap();
+        assert_eq!(trust_store.anchors.len(), 1);
+        assert_eq!(trust_store.intermediates.len(), 1);
+    }
+}